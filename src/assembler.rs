@@ -2,9 +2,11 @@ use std::fmt;
 use std::str;
 use std::ascii::AsciiExt;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use dcpu::MEMORY_SIZE;
 use instructions::*;
+use extensions::Extensions;
 
 const MAX_PRIO: usize = 2;
 
@@ -89,6 +91,17 @@ pub struct PCPU {
 
     // Next string literal id
     next_string_id: u16,
+
+    // Word ranges [start, end) produced by each (zero-based) source line
+    line_ranges: Vec<(u16, u16, usize)>,
+
+    // Lines that hold data (DAT and DAF) rather than instructions
+    data_lines: Vec<usize>,
+
+    // Instruction set to encode for
+    isa: Isa,
+
+    extensions: Arc<Extensions>,
 }
 
 impl PCPU {
@@ -104,8 +117,51 @@ impl PCPU {
             next_label_id: 0,
             string_literals: Vec::new(),
             next_string_id: 0,
+            line_ranges: Vec::new(),
+            data_lines: Vec::new(),
+            isa: Isa::V1_7,
+            extensions: Arc::new(Extensions::standard()),
         }
     }
+
+    /// Sets the extension instructions that can be used (`Extensions::standard` by default).
+    pub fn set_extensions(&mut self, extensions: Arc<Extensions>) {
+        self.extensions = extensions;
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Selects the instruction set to assemble for (1.7 by default). Instructions and operands
+    /// that do not exist in it are parsing errors.
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+    }
+
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Defined labels and their addresses, sorted by address.
+    pub fn labels(&self) -> Vec<(String, u16)> {
+        let mut v: Vec<(String, u16)> = self.labels.iter().filter_map(|(id, addr)| {
+            self.id_to_label.get(id).map(|name| (name.clone(), *addr))
+        }).collect();
+        v.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        v
+    }
+
+    /// Word ranges `[start, end)` emitted by each source line, where lines are zero-based. Lines
+    /// that do not emit anything (comments, labels only) are left out.
+    pub fn line_ranges(&self) -> &[(u16, u16, usize)] {
+        &self.line_ranges[..]
+    }
+
+    /// Whether a (zero-based) line emits data rather than instructions.
+    pub fn is_data_line(&self, line: usize) -> bool {
+        self.data_lines.contains(&line)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -123,6 +179,7 @@ pub enum ParsingErrorType {
     EndOfTokens,
     ExtraTokens,
     IncorrectPushPop,
+    UnsupportedByIsa,
     UnknownLabel(u16),
 }
 
@@ -154,6 +211,8 @@ pub fn format_error(etype: &ParsingErrorType, cpu: &PCPU) -> String {
             format!("Extra tokens found after successfully parsed line"),
         &ParsingErrorType::IncorrectPushPop =>
             format!("Push cannot be used as a-operand / Pop cannot be used as b-operand"),
+        &ParsingErrorType::UnsupportedByIsa =>
+            format!("Not available in the selected instruction set"),
         &ParsingErrorType::UnknownLabel(label) => {
             match cpu.id_to_label.get(&label) {
                 Some(s) => format!("Label definition not found: {}", s),
@@ -278,8 +337,10 @@ fn registry_char(c: char) -> isize {
     }
 }
 
-fn keyword_token(s: &str) -> Option<TokenType> {
+fn keyword_token(s: &str, isa: Isa) -> Option<TokenType> {
     match &s.to_ascii_uppercase()[..] {
+        // Overflow register of 1.1, which became EX
+        "O" if isa == Isa::V1_1 => Some(TokenType::EX),
         "PICK" => Some(TokenType::Pick),
         "PEEK" => Some(TokenType::Peek),
         "PUSH" => Some(TokenType::Push),
@@ -335,9 +396,6 @@ fn special_opcode(s: &str) -> Option<usize> {
         "HWN" => Some(HWN),
         "HWQ" => Some(HWQ),
         "HWI" => Some(HWI),
-        // Extra
-        "OUT" => Some(OUT),
-        "OUV" => Some(OUV),
         _ => None,
     }
 }
@@ -505,7 +563,7 @@ pub fn tokenize(line_no: usize, line: &str, cpu: &mut PCPU) -> Result<Vec<Token>
                     Token { ttype: TokenType::Registry(registry_char(s.chars().nth(0).unwrap()) as u16),
                             col: col,
                             len: 1 }
-                } else if let Some(ttype) = keyword_token(&s[..]) {
+                } else if let Some(ttype) = keyword_token(&s[..], cpu.isa) {
                     Token { ttype: ttype,
                             col: col,
                             len: s.len() }
@@ -517,6 +575,10 @@ pub fn tokenize(line_no: usize, line: &str, cpu: &mut PCPU) -> Result<Vec<Token>
                     Token { ttype: TokenType::SpecialOpcode(special_opcode(&s[..]).unwrap()),
                             col: col,
                             len: 3 }
+                } else if let Some(opcode) = cpu.extensions.by_mnemonic(&s[..]).map(|e| e.opcode()) {
+                    Token { ttype: TokenType::SpecialOpcode(opcode),
+                            col: col,
+                            len: s.len() }
                 } else if s.to_ascii_uppercase() == "DAT" {
                     Token { ttype: TokenType::DataOpcode,
                             col: col,
@@ -657,8 +719,15 @@ pub fn canonize_tokens(tokens: &Vec<Token>) -> Vec<Token> {
     new_tokens
 }
 
-fn process_value(value: u16, allow_inline: bool) -> Result<ParsingInfo, ParsingError> {
-    let info = if value == 0xffff && allow_inline {
+fn process_value(value: u16, allow_inline: bool, isa: Isa) -> Result<ParsingInfo, ParsingError> {
+    let info = if isa == Isa::V1_1 {
+        // 1.1 inlines 0 to 0x1f, and has no -1
+        if value <= 0x1f && allow_inline {
+            ParsingInfo::new_single(value + 0x20)
+        } else {
+            ParsingInfo::new_extra(0x1f, value)
+        }
+    } else if value == 0xffff && allow_inline {
         ParsingInfo::new_single(0x20)
     } else if value <= 0x1e && allow_inline {
         ParsingInfo::new_single(value + 0x21)
@@ -686,13 +755,13 @@ fn parse_value(line_no: usize, tokens: &Vec<Token>, cur: &mut usize,
             */
             *cur += 1;
             // L-values are not allowed to have inlined values
-            Ok(try!(process_value(value, !lvalue)))
+            Ok(try!(process_value(value, !lvalue, cpu.isa)))
         },
         TokenType::Label(id) => {
             match cpu.labels.get(&id) {
                 Some(label) => {
                     *cur += 1;
-                    Ok(try!(process_value(*label, !lvalue)))
+                    Ok(try!(process_value(*label, !lvalue, cpu.isa)))
                 },
                 None => {
                     *cur += 1;
@@ -706,6 +775,14 @@ fn parse_value(line_no: usize, tokens: &Vec<Token>, cur: &mut usize,
             Ok(info)
         },
         TokenType::Pick => {
+            if cpu.isa == Isa::V1_1 {
+                let err = ParsingError{ line: line_no,
+                                        col: tokens[*cur].col,
+                                        len: tokens[*cur].len,
+                                        global: false,
+                                        etype: ParsingErrorType::UnsupportedByIsa };
+                return Err(err);
+            }
             *cur += 1;
             let ttype0 = try!(fetch_token_type(line_no, tokens, *cur));
             match *ttype0 {
@@ -739,7 +816,8 @@ fn parse_value(line_no: usize, tokens: &Vec<Token>, cur: &mut usize,
                 Err(err)
             } else {
                 *cur += 1;
-                let info = ParsingInfo::new_single(0x18);
+                // PUSH and POP share 0x18 in 1.7
+                let info = ParsingInfo::new_single(if cpu.isa == Isa::V1_1 { 0x1a } else { 0x18 });
                 Ok(info)
             }
         },
@@ -1109,6 +1187,7 @@ fn parse_basic_opcode(line_no: usize,
     let ttype = try!(fetch_token_type(line_no, tokens, *cur));
     match ttype {
         &TokenType::BasicOpcode(opcode) => {
+            let opcode_token = *cur;
             *cur += 1;
             let b = try!(parse_value(line_no, tokens, cur, cpu, true));
 
@@ -1118,6 +1197,27 @@ fn parse_basic_opcode(line_no: usize,
 
             try!(check_end_of_line(line_no, tokens, cur));
 
+            if cpu.isa == Isa::V1_1 {
+                let opcode = match basic_opcode_v1_1(opcode) {
+                    Some(op) => op,
+                    None => {
+                        let err = ParsingError { line: line_no,
+                                                 col: tokens[opcode_token].col,
+                                                 len: tokens[opcode_token].len,
+                                                 global: false,
+                                                 etype: ParsingErrorType::UnsupportedByIsa };
+                        return Err(err);
+                    },
+                };
+                // Pack byte as aaaaaabbbbbboooo, where b is the target and goes first
+                let byte: u16 = (a.operand << 10) + (b.operand << 4) + opcode as u16;
+                cpu.mem[cpu.pc as usize] = byte;
+                cpu.pc += 1;
+                push_extra(cpu, &b);
+                push_extra(cpu, &a);
+                return Ok(());
+            }
+
             // Pack byte as aaaaaabbbbbooooo
             let byte: u16 = (a.operand << 10) + (b.operand << 5) + opcode as u16;
             cpu.mem[cpu.pc as usize] = byte;
@@ -1164,12 +1264,35 @@ fn parse_special_opcode(line_no: usize,
     let ttype = try!(fetch_token_type(line_no, tokens, *cur));
     match ttype {
         &TokenType::SpecialOpcode(opcode) => {
+            let opcode_token = *cur;
             *cur += 1;
 
             let a = try!(parse_value(line_no, tokens, cur, cpu, false));
 
             try!(check_end_of_line(line_no, tokens, cur));
 
+            if cpu.isa == Isa::V1_1 {
+                // Extensions keep their numbers
+                let extension = cpu.extensions.by_opcode(opcode).map(|e| e.opcode());
+                let opcode = match special_opcode_v1_1(opcode).or(extension) {
+                    Some(op) => op,
+                    None => {
+                        let err = ParsingError { line: line_no,
+                                                 col: tokens[opcode_token].col,
+                                                 len: tokens[opcode_token].len,
+                                                 global: false,
+                                                 etype: ParsingErrorType::UnsupportedByIsa };
+                        return Err(err);
+                    },
+                };
+                // Pack byte as aaaaaaoooooo0000
+                let byte: u16 = (a.operand << 10) + ((opcode as u16) << 4);
+                cpu.mem[cpu.pc as usize] = byte;
+                cpu.pc += 1;
+                push_extra(cpu, &a);
+                return Ok(());
+            }
+
             // Pack byte as aaaaaaooooo00000
             let byte: u16 = (a.operand << 10) + ((opcode as u16) << 5);
             cpu.mem[cpu.pc as usize] = byte;
@@ -1195,6 +1318,18 @@ fn parse_special_opcode(line_no: usize,
     Ok(())
 }
 
+// Writes the next word of an operand, if it has one
+fn push_extra(cpu: &mut PCPU, info: &ParsingInfo) {
+    if let Some(byte) = info.extra_byte {
+        cpu.mem[cpu.pc as usize] = byte;
+        if info.unassigned {
+            let ul = UnassignedLabel{addr: cpu.pc, label: byte, offset: info.offset};
+            cpu.unassigned_addresses.push(ul);
+        }
+        cpu.pc += 1;
+    }
+}
+
 fn parse_data_opcode(line_no: usize,
                      tokens: &Vec<Token>,
                      cur: &mut usize,
@@ -1308,10 +1443,12 @@ fn parse_line(line_no: usize, tokens: &Vec<Token>, cpu: &mut PCPU, cur: &mut usi
             Ok(())
         },
         &TokenType::DataOpcode => {
+            cpu.data_lines.push(line_no);
             try!(parse_data_opcode(line_no, tokens, cur, cpu));
             Ok(())
         },
         &TokenType::DataFillOpcode => {
+            cpu.data_lines.push(line_no);
             try!(parse_data_fill_opcode(line_no, tokens, cur, cpu));
             Ok(())
         },
@@ -1352,6 +1489,8 @@ fn parse_line(line_no: usize, tokens: &Vec<Token>, cpu: &mut PCPU, cur: &mut usi
 pub fn parse(lines: &Vec<String>, cpu: &mut PCPU) -> Result<(), ParsingError> {
     // We're going to use the PC register to keep track of the position
     cpu.pc = 0;
+    cpu.line_ranges.clear();
+    cpu.data_lines.clear();
 
     let mut line_no = 0usize;
     for line in lines.iter() {
@@ -1364,7 +1503,11 @@ pub fn parse(lines: &Vec<String>, cpu: &mut PCPU) -> Result<(), ParsingError> {
 
         let tokens = canonize_tokens(&(tokenize(line_no, l, cpu))?);
         let mut cur = 0;
+        let start = cpu.pc;
         try!(parse_line(line_no, &tokens, cpu, &mut cur));
+        if cpu.pc != start {
+            cpu.line_ranges.push((start, cpu.pc, line_no));
+        }
 
         line_no += 1;
     }
@@ -11,6 +11,8 @@ use std::path::Path;
 use std::env;
 use getopts::Options;
 use dcpu16::assembler;
+use dcpu16::instructions::Isa;
+use dcpu16::symbols::SymbolMap;
 use std::process::exit;
 
 fn main() {
@@ -19,6 +21,8 @@ fn main() {
     let program = args[0].clone();
 
     opts.optopt("o", "output", "output binary file to path (otherwise defaults to output.bin)", "PATH");
+    opts.optopt("s", "symbols", "write symbol map (labels and source lines) to path", "PATH");
+    opts.optopt("", "isa", "instruction set revision: 1.1 or 1.7 (default: 1.7)", "VERSION");
     opts.optflag("v", "version", "print version");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
@@ -30,7 +34,8 @@ fn main() {
     };
 
     if matches.opt_present("h") {
-        cli::print_usage(&program, "FILE", opts, &["program.asm -o program.bin"]);
+        cli::print_usage(&program, "FILE", opts, &["program.asm -o program.bin",
+                                                   "program.asm -o program.bin -s program.sym"]);
         return;
     }
 
@@ -67,7 +72,19 @@ fn main() {
         }
     }
 
+    let isa = match matches.opt_str("isa") {
+        Some(s) => match s.parse() {
+            Ok(isa) => isa,
+            Err(_) => {
+                println!("Invalid instruction set: {} (expected 1.1 or 1.7)", s);
+                exit(1);
+            },
+        },
+        None => Isa::V1_7,
+    };
+
     let mut cpu = assembler::PCPU::new();
+    cpu.set_isa(isa);
     let ret = assembler::parse(&lines, &mut cpu);
 
     let mut buf = [0u8; 2];
@@ -101,6 +118,15 @@ fn main() {
 
                 //io::stdout().write_be_u16(cpu.mem[i as usize]);
             }
+
+            if let Some(symbols_filename) = matches.opt_str("symbols") {
+                let mut symbols = SymbolMap::from_assembler(&cpu);
+                symbols.set_source(filename);
+                if let Err(why) = symbols.save(&Path::new(&symbols_filename)) {
+                    println!("Could not write symbol map {}: {}", symbols_filename, why);
+                    exit(1);
+                }
+            }
         },
         Err(err) => {
             assembler::print_parse_error(&cpu, &lines[err.line as usize][..], err);
@@ -0,0 +1,135 @@
+extern crate dcpu16;
+extern crate getopts;
+
+mod cli;
+
+use std::env;
+use std::time::Instant;
+use getopts::Options;
+use dcpu16::assembler::{self, PCPU};
+use dcpu16::dcpu::{self, DCPU};
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::keyboard_generic::DeviceKeyboardGeneric;
+use std::process::exit;
+
+const DEFAULT_CYCLES: u64 = 50_000_000;
+
+// Fixed workload: memory writes, arithmetic, subroutine calls and branches, with the clock
+// interrupting 60 times per second
+const WORKLOAD: [&'static str; 30] = [
+    "        IAS handler",
+    "        SET A, 0            ; clock: tick 60 times per second",
+    "        SET B, 1",
+    "        HWI 0",
+    "        SET A, 2            ; clock: interrupt on tick",
+    "        SET B, 1",
+    "        HWI 0",
+    ":outer  SET I, 0",
+    ":fill   SET [buffer + I], I",
+    "        MUL [buffer + I], 3",
+    "        ADD I, 1",
+    "        IFL I, 64",
+    "            SET PC, fill",
+    "        SET I, 0",
+    "        SET X, 0",
+    ":sum    ADD X, [buffer + I]",
+    "        JSR mix",
+    "        ADD I, 1",
+    "        IFN I, 64",
+    "            SET PC, sum",
+    "        SET PC, outer",
+    ":mix    XOR X, 0x5555",
+    "        SHR X, 1",
+    "        SET PC, POP",
+    ":handler",
+    "        ADD [ticks], 1",
+    "        RFI 0",
+    ":ticks  DAT 0",
+    ":buffer DAF 64, 0",
+    "",
+];
+
+fn main() {
+    let mut opts = Options::new();
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    opts.optopt("c", "cycles", &format!("cycles to emulate (default: {})", DEFAULT_CYCLES), "N");
+    opts.optopt("", "device-batch", "run devices every N cycles (default: 1)", "N");
+    opts.optflag("", "no-cache", "turn off the decoded instruction cache");
+    opts.optflag("", "compare-cache", "run with the decode cache on and then off");
+    opts.optflag("v", "version", "print version");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m },
+        Err(why) => {
+            println!("{}", why);
+            exit(1);
+        },
+    };
+
+    if matches.opt_present("h") {
+        cli::print_usage(&program, "", opts, &["--device-batch 100", "--compare-cache"]);
+        return;
+    }
+
+    if matches.opt_present("v") {
+        cli::print_version(&program);
+        return;
+    }
+
+    let cycles = parse_count(matches.opt_str("cycles"), DEFAULT_CYCLES);
+    let batch = parse_count(matches.opt_str("device-batch"), 1);
+
+    let lines: Vec<String> = WORKLOAD.iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    if let Err(err) = assembler::parse(&lines, &mut pcpu) {
+        assembler::print_parse_error(&pcpu, &lines[err.line], err);
+        exit(1);
+    }
+
+    if matches.opt_present("compare-cache") {
+        println!("Decode cache on:");
+        bench(&pcpu.mem[..], cycles, batch, true);
+        println!("Decode cache off:");
+        bench(&pcpu.mem[..], cycles, batch, false);
+    } else {
+        bench(&pcpu.mem[..], cycles, batch, !matches.opt_present("no-cache"));
+    }
+}
+
+// Runs the workload in `mem` and prints the speed
+fn bench(mem: &[u16], cycles: u64, batch: u64, decode_cache: bool) {
+    let mut cpu = DCPU::new();
+    cpu.mem.copy_from_slice(mem);
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.add_device(Box::new(DeviceKeyboardGeneric::new())).unwrap();
+    cpu.set_device_batch(batch);
+    cpu.set_decode_cache(decode_cache);
+
+    let start = Instant::now();
+    let status = cpu.run(cycles as usize);
+    let elapsed = start.elapsed();
+    if let Some(reason) = status.stop_reason() {
+        println!("Workload stopped unexpectedly: {:?}", reason);
+        exit(1);
+    }
+
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+    let hz = cpu.cycle() as f64 / seconds;
+    println!("{} cycles in {:.3} s", cpu.cycle(), seconds);
+    println!("{:.2} MHz ({:.0}x real time)", hz / 1e6, hz / dcpu::CYCLE_HZ as f64);
+}
+
+fn parse_count(s: Option<String>, default: u64) -> u64 {
+    match s {
+        Some(s) => match s.parse::<u64>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                println!("Invalid number: {}", s);
+                exit(1);
+            },
+        },
+        None => default,
+    }
+}
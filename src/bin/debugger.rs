@@ -0,0 +1,707 @@
+extern crate dcpu16;
+extern crate getopts;
+
+mod cli;
+
+use std::env;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use getopts::Options;
+use dcpu16::dcpu::{self, DCPU, StopReason, WatchKind};
+use dcpu16::assembler;
+use dcpu16::disassembler;
+use dcpu16::instructions::{self, Isa};
+use dcpu16::symbols::SymbolMap;
+use std::process::exit;
+
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+
+// Instructions shown before PC when listing
+const LIST_BEFORE: usize = 3;
+// Instructions shown after PC when listing (unless specified)
+const LIST_AFTER: usize = 5;
+// Words shown by `x` (unless specified)
+const EXAMINE_WORDS: usize = 16;
+// Instructions that can be stepped back (unless specified)
+const HISTORY: usize = 100_000;
+// Ticks between checks for Ctrl-C while running
+const INTERRUPT_POLL_TICKS: usize = 10_000;
+
+// Set when Ctrl-C is pressed while the program is running
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+const HELP: &'static str = "\
+Commands:
+  s, step [N]            execute N instructions (default 1)
+  n, next                step, but execute JSR calls as a single instruction
+  finish                 run until the current subroutine returns (SET PC, POP)
+  c, continue            run until a breakpoint, watchpoint or termination
+  bs, back [N]           undo N instructions (default 1)
+  bc, reverse-continue   run backwards until a breakpoint or write watchpoint
+  lastwrite LOC          run backwards to the last instruction that wrote to LOC
+  b, break LOC           set breakpoint at LOC
+  d, delete [LOC]        delete breakpoint at LOC (all breakpoints if omitted)
+  w, watch LOC [END] [r|w|rw]
+                         stop when memory in LOC..END is read/written (default w)
+  unwatch ID             delete watchpoint
+  info                   list breakpoints and watchpoints
+  r, regs                print registers
+  set REG VALUE          set register (A, B, C, X, Y, Z, I, J, PC, SP, EX, IA)
+  x LOC [N]              examine N words of memory
+  poke LOC VALUE...      write words to memory starting at LOC
+  l, list [N]            disassemble around PC
+  save PATH              save a machine snapshot
+  restore PATH           restore a machine snapshot
+  reset [clear]          reset the CPU and devices (clear: also clear memory)
+  h, help                print this help
+  q, quit                exit
+
+LOC and VALUE can be decimal, hexadecimal (0x1234), a label or label+offset.
+An empty line repeats the last command. Ctrl-C stops a running program.";
+
+// While the program runs, Ctrl-C sets INTERRUPTED instead of ending the debugger
+#[cfg(unix)]
+mod sigint {
+    use std::sync::atomic::Ordering;
+    use super::INTERRUPTED;
+
+    const SIGINT: i32 = 2;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+
+    extern "C" fn handler(_: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    pub fn catch() {
+        unsafe { signal(SIGINT, handler as extern "C" fn(i32) as usize); }
+    }
+
+    pub fn release() {
+        unsafe { signal(SIGINT, SIG_DFL); }
+    }
+}
+
+#[cfg(not(unix))]
+mod sigint {
+    pub fn catch() {}
+    pub fn release() {}
+}
+
+struct Source {
+    lines: Vec<String>,
+    ranges: Vec<(u16, u16, usize)>,
+}
+
+struct Watch {
+    id: usize,
+    from: u16,
+    to: u16,
+    kind: WatchKind,
+}
+
+struct Debugger {
+    cpu: DCPU,
+    symbols: SymbolMap,
+    source: Option<Source>,
+    watches: Vec<Watch>,
+    color: bool,
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    let file = match File::open(&path) {
+        Err(why) => {
+            println!("Could not open file {}: {}", path.display(), why);
+            exit(1);
+        },
+        Ok(file) => file,
+    };
+    let x: &[_] = &[' ', '\n', '\t'];
+    let mut lines: Vec<String> = Vec::new();
+    for line in BufReader::new(&file).lines() {
+        match line {
+            Ok(s) => {lines.push(s.trim_matches(x).to_string())},
+            Err(_) => {},
+        }
+    }
+    lines
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u16::from_str_radix(&s[2..], 16).ok()
+    } else if s.starts_with("-") {
+        s[1..].parse::<u16>().ok().map(|v| v.wrapping_neg())
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn register_index(name: &str) -> Option<usize> {
+    match &name.to_uppercase()[..] {
+        "A" => Some(dcpu::REG_A),
+        "B" => Some(dcpu::REG_B),
+        "C" => Some(dcpu::REG_C),
+        "X" => Some(dcpu::REG_X),
+        "Y" => Some(dcpu::REG_Y),
+        "Z" => Some(dcpu::REG_Z),
+        "I" => Some(dcpu::REG_I),
+        "J" => Some(dcpu::REG_J),
+        _ => None,
+    }
+}
+
+impl Debugger {
+    fn parse_location(&self, s: &str) -> Option<u16> {
+        let mut total = 0u16;
+        for part in s.split('+') {
+            let part = part.trim();
+            let v = match parse_number(part) {
+                Some(v) => v,
+                None => match self.symbols.address_of(part) {
+                    Some(v) => v,
+                    None => return None,
+                },
+            };
+            total = total.wrapping_add(v);
+        }
+        Some(total)
+    }
+
+    fn source_line(&self, address: u16) -> Option<(usize, &str)> {
+        match self.source {
+            Some(ref source) => {
+                source.ranges.iter()
+                      .find(|r| address >= r.0 && address < r.1)
+                      .map(|r| (r.2, &source.lines[r.2][..]))
+            },
+            None => None,
+        }
+    }
+
+    fn print_registers(&self) {
+        let cpu = &self.cpu;
+        println!("A  {:04x}  B  {:04x}  C  {:04x}  X  {:04x}  Y  {:04x}  Z  {:04x}  I  {:04x}  J  {:04x}",
+                 cpu.reg[0], cpu.reg[1], cpu.reg[2], cpu.reg[3],
+                 cpu.reg[4], cpu.reg[5], cpu.reg[6], cpu.reg[7]);
+        println!("PC {:04x}  SP {:04x}  EX {:04x}  IA {:04x}  cycles: {}",
+                 cpu.pc, cpu.sp, cpu.ex, cpu.ia, cpu.cycle());
+    }
+
+    fn print_instruction(&self, address: u16, current: bool) -> u16 {
+        if let Some(label) = self.symbols.label_at(address) {
+            println!("{}:", label);
+        }
+        let (offset, s) = disassembler::disassemble_instruction_at(&self.cpu, address, self.color);
+        let marker = if current { "=>" } else { "  " };
+        let bp = if self.cpu.breakpoints().contains(&address) { "*" } else { " " };
+        println!("{}{} {:04x}: {}", marker, bp, address, s);
+        offset
+    }
+
+    fn print_location(&self) {
+        let pc = self.cpu.pc;
+        if let Some((line_no, line)) = self.source_line(pc) {
+            println!("{:>5} | {}", line_no + 1, line);
+        }
+        self.print_instruction(pc, true);
+    }
+
+    fn print_listing(&self, after: usize) {
+        let pc = self.cpu.pc;
+
+        // Instructions have variable length, so we look for the furthest starting point (within
+        // reason) that decodes into a sequence that lands exactly on PC.
+        let mut start = pc;
+        for back in (1..(LIST_BEFORE * 3 + 1)).rev() {
+            let candidate = pc.wrapping_sub(back as u16);
+            if candidate > pc {
+                continue;
+            }
+            let mut p = candidate;
+            let mut count = 0;
+            while p < pc && count < LIST_BEFORE {
+                let (offset, _) = disassembler::disassemble_instruction_at(&self.cpu, p, false);
+                p = p.wrapping_add(offset);
+                count += 1;
+            }
+            if p == pc {
+                start = candidate;
+                break;
+            }
+        }
+
+        let mut p = start;
+        while p != pc {
+            let offset = self.print_instruction(p, false);
+            p = p.wrapping_add(offset);
+        }
+        for _ in 0..(after + 1) {
+            let offset = self.print_instruction(p, p == pc);
+            p = p.wrapping_add(offset);
+        }
+    }
+
+    fn examine(&self, address: u16, n: usize) {
+        let mut i = 0;
+        while i < n {
+            print!("{:04x}:", address.wrapping_add(i as u16));
+            for _ in 0..8 {
+                if i >= n {
+                    break;
+                }
+                print!(" {:04x}", self.cpu.mem[address.wrapping_add(i as u16) as usize]);
+                i += 1;
+            }
+            println!("");
+        }
+    }
+
+    fn report(&self, reason: Option<StopReason>) {
+        match reason {
+            Some(StopReason::Breakpoint(address)) => {
+                println!("Breakpoint at {}", self.symbols.describe(address));
+            },
+            Some(StopReason::Watchpoint { id, address, access }) => {
+                println!("Watchpoint {}: {:?} at 0x{:04x} (now 0x{:04x})", id, access,
+                         address, self.cpu.mem[address as usize]);
+            },
+            Some(StopReason::RomWrite(address)) => {
+                println!("Write to ROM at 0x{:04x}", address);
+            },
+            Some(StopReason::Fault(fault)) => {
+                println!("Fault: {}", fault);
+            },
+            Some(StopReason::Halt(status)) => {
+                println!("Program halted with status {}", status);
+            },
+            Some(StopReason::ZeroWord(address)) => {
+                println!("Program terminated (0x0000 at 0x{:04x})", address);
+            },
+            Some(StopReason::DeviceStop(code)) => {
+                println!("Stopped by device (code {})", code);
+            },
+            None => {},
+        }
+        self.print_location();
+    }
+
+    // Ticks until `done` returns true, or execution is stopped by a breakpoint, a watchpoint, a
+    // halt, a fault or Ctrl-C.
+    fn run_until<F>(&mut self, mut done: F) where F: FnMut(&DCPU, u16) -> bool {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        sigint::catch();
+        let mut ticks = 0;
+        // None if interrupted
+        let stopped = loop {
+            let (pc, cycle) = (self.cpu.pc, self.cpu.cycle());
+            let word = self.cpu.mem[pc as usize];
+            let reason = self.cpu.tick().stop_reason();
+            // Stopped on a breakpoint at PC, without executing anything
+            if reason == Some(StopReason::Breakpoint(pc)) && self.cpu.cycle() == cycle {
+                break Some(reason);
+            }
+            if done(&self.cpu, word) {
+                // Landing on a breakpoint is not interesting if we were going to stop anyway
+                let reason = match reason {
+                    Some(StopReason::Breakpoint(_)) => None,
+                    r => r,
+                };
+                break Some(reason);
+            }
+            if reason.is_some() {
+                break Some(reason);
+            }
+            ticks += 1;
+            if ticks % INTERRUPT_POLL_TICKS == 0 && INTERRUPTED.load(Ordering::SeqCst) {
+                break None;
+            }
+        };
+        sigint::release();
+        match stopped {
+            Some(reason) => self.report(reason),
+            None => {
+                println!("Interrupted");
+                self.print_location();
+            },
+        }
+    }
+
+    fn step(&mut self, n: usize) {
+        let mut count = 0;
+        self.run_until(|_, _| {
+            count += 1;
+            count >= n
+        });
+    }
+
+    fn next(&mut self) {
+        let pc = self.cpu.pc;
+        let word = self.cpu.mem[pc as usize];
+        if instructions::is_call(word, self.cpu.isa()) {
+            let (offset, _) = disassembler::disassemble_instruction_at(&self.cpu, pc, false);
+            let target = pc.wrapping_add(offset);
+            let sp = self.cpu.sp;
+            self.run_until(|cpu, _| cpu.pc == target && cpu.sp == sp);
+        } else {
+            self.step(1);
+        }
+    }
+
+    fn finish(&mut self) {
+        let sp = self.cpu.sp;
+        self.run_until(|cpu, word| instructions::is_return(word, cpu.isa()) && (cpu.sp.wrapping_sub(sp) as i16) > 0);
+    }
+
+    fn execute(&mut self, line: &str) -> bool {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return true;
+        }
+        let command = args[0];
+        let args = &args[1..];
+
+        match command {
+            "s" | "step" => {
+                let n = match args.get(0) {
+                    Some(s) => match s.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => { println!("Invalid count: {}", s); return true; },
+                    },
+                    None => 1,
+                };
+                self.step(n);
+            },
+            "n" | "next" => self.next(),
+            "finish" => self.finish(),
+            "c" | "continue" => self.run_until(|_, _| false),
+            "bs" | "back" => {
+                let n = match args.get(0) {
+                    Some(s) => match s.parse() {
+                        Ok(n) => n,
+                        Err(_) => { println!("Invalid count: {}", s); return true; },
+                    },
+                    None => 1,
+                };
+                if self.cpu.step_back(n) < n {
+                    println!("Reached the start of the recorded history");
+                }
+                self.print_location();
+            },
+            "bc" | "reverse-continue" => {
+                let reason = self.cpu.run_back();
+                if reason.is_none() {
+                    println!("Reached the start of the recorded history");
+                }
+                self.report(reason);
+            },
+            "lastwrite" => {
+                match args.get(0).and_then(|s| self.parse_location(s)) {
+                    Some(address) => {
+                        if self.cpu.run_back_to_write(address) {
+                            self.print_location();
+                        } else {
+                            println!("No write to 0x{:04x} in the recorded history", address);
+                        }
+                    },
+                    None => println!("Usage: lastwrite LOC"),
+                }
+            },
+            "b" | "break" => {
+                match args.get(0).and_then(|s| self.parse_location(s)) {
+                    Some(address) => {
+                        self.cpu.add_breakpoint(address);
+                        println!("Breakpoint at {}", self.symbols.describe(address));
+                    },
+                    None => println!("Usage: break LOC"),
+                }
+            },
+            "d" | "delete" => {
+                match args.get(0) {
+                    Some(s) => {
+                        match self.parse_location(s) {
+                            Some(address) => {
+                                if !self.cpu.remove_breakpoint(address) {
+                                    println!("No breakpoint at {}", self.symbols.describe(address));
+                                }
+                            },
+                            None => println!("Invalid location: {}", s),
+                        }
+                    },
+                    None => self.cpu.clear_breakpoints(),
+                }
+            },
+            "w" | "watch" => {
+                let mut args = args.to_vec();
+                let kind = match args.last().map(|s| &s[..]) {
+                    Some("r") => Some(WatchKind::Read),
+                    Some("w") => Some(WatchKind::Write),
+                    Some("rw") => Some(WatchKind::ReadWrite),
+                    _ => None,
+                };
+                if kind.is_some() {
+                    args.pop();
+                }
+                let kind = kind.unwrap_or(WatchKind::Write);
+                let from = args.get(0).and_then(|s| self.parse_location(s));
+                let to = match args.get(1) {
+                    Some(s) => self.parse_location(s),
+                    None => from,
+                };
+                match (from, to) {
+                    (Some(from), Some(to)) if from <= to => {
+                        let id = self.cpu.add_watchpoint(from, to, kind);
+                        self.watches.push(Watch { id: id, from: from, to: to, kind: kind });
+                        println!("Watchpoint {}: {:04x}..{:04x} {:?}", id, from, to, kind);
+                    },
+                    _ => println!("Usage: watch LOC [END] [r|w|rw]"),
+                }
+            },
+            "unwatch" => {
+                match args.get(0).and_then(|s| s.parse().ok()) {
+                    Some(id) => {
+                        if self.cpu.remove_watchpoint(id) {
+                            self.watches.retain(|w| w.id != id);
+                        } else {
+                            println!("No watchpoint {}", id);
+                        }
+                    },
+                    None => println!("Usage: unwatch ID"),
+                }
+            },
+            "info" => {
+                for address in self.cpu.breakpoints() {
+                    println!("Breakpoint at {:04x} ({})", address, self.symbols.describe(address));
+                }
+                for w in self.watches.iter() {
+                    println!("Watchpoint {}: {:04x}..{:04x} {:?}", w.id, w.from, w.to, w.kind);
+                }
+            },
+            "r" | "regs" => self.print_registers(),
+            "set" => {
+                if args.len() != 2 {
+                    println!("Usage: set REG VALUE");
+                    return true;
+                }
+                let value = match self.parse_location(args[1]) {
+                    Some(v) => v,
+                    None => { println!("Invalid value: {}", args[1]); return true; },
+                };
+                match &args[0].to_uppercase()[..] {
+                    "PC" => self.cpu.pc = value,
+                    "SP" => self.cpu.sp = value,
+                    "EX" => self.cpu.ex = value,
+                    "IA" => self.cpu.ia = value,
+                    name => match register_index(name) {
+                        Some(i) => self.cpu.reg[i] = value,
+                        None => println!("Unknown register: {}", args[0]),
+                    },
+                }
+            },
+            "x" => {
+                let address = match args.get(0).and_then(|s| self.parse_location(s)) {
+                    Some(a) => a,
+                    None => { println!("Usage: x LOC [N]"); return true; },
+                };
+                let n = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(EXAMINE_WORDS);
+                self.examine(address, n);
+            },
+            "poke" => {
+                let address = match args.get(0).and_then(|s| self.parse_location(s)) {
+                    Some(a) => a,
+                    None => { println!("Usage: poke LOC VALUE..."); return true; },
+                };
+                for (i, s) in args[1..].iter().enumerate() {
+                    match self.parse_location(s) {
+                        Some(v) => self.cpu.mem[address.wrapping_add(i as u16) as usize] = v,
+                        None => { println!("Invalid value: {}", s); return true; },
+                    }
+                }
+            },
+            "l" | "list" => {
+                let n = args.get(0).and_then(|s| s.parse().ok()).unwrap_or(LIST_AFTER);
+                self.print_listing(n);
+            },
+            "save" => {
+                match args.get(0) {
+                    Some(path) => {
+                        if let Err(why) = self.cpu.save_snapshot(Path::new(path)) {
+                            println!("Could not save snapshot: {}", why);
+                        }
+                    },
+                    None => println!("Usage: save PATH"),
+                }
+            },
+            "restore" => {
+                match args.get(0) {
+                    Some(path) => {
+                        match self.cpu.load_snapshot(Path::new(path)) {
+                            Ok(()) => self.print_listing(0),
+                            Err(why) => println!("Could not restore snapshot: {}", why),
+                        }
+                    },
+                    None => println!("Usage: restore PATH"),
+                }
+            },
+            "reset" => {
+                match args.get(0) {
+                    None => self.cpu.soft_reset(true),
+                    Some(&"clear") => self.cpu.soft_reset(false),
+                    Some(_) => {
+                        println!("Usage: reset [clear]");
+                        return true;
+                    },
+                }
+                self.print_listing(0);
+            },
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return false,
+            _ => println!("Unknown command: {} (try 'help')", command),
+        }
+        true
+    }
+}
+
+fn main() {
+    let mut opts = Options::new();
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    opts.optopt("s", "source", "assembly source of the program (also provides labels)", "PATH");
+    opts.optopt("y", "symbols", "symbol map written by the assembler", "PATH");
+    opts.optopt("", "isa", "instruction set revision: 1.1 or 1.7 (default: 1.7)", "VERSION");
+    opts.optflag("z", "halt-on-zero", "halt when a 0x0000 word is executed (e.g. DAT 0)");
+    opts.optflag("m", "no-color", "do not use ANSI colors in output");
+    opts.optopt("", "history", &format!("number of instructions that can be stepped back (default {})", HISTORY), "N");
+    opts.optflag("v", "version", "print version");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m },
+        Err(why) => {
+            println!("{}", why);
+            exit(1);
+        },
+    };
+
+    if matches.opt_present("h") {
+        cli::print_usage(&program, "FILE", opts, &["program.bin",
+                                                   "-s program.asm program.bin",
+                                                   "-y program.sym program.bin"]);
+        return;
+    }
+
+    if matches.opt_present("v") {
+        cli::print_version(&program);
+        return;
+    }
+
+    if matches.free.len() != 1 {
+        println!("Please input file");
+        exit(1);
+    }
+    let ref filename = matches.free[0];
+
+    let isa = match matches.opt_str("isa") {
+        Some(s) => match s.parse() {
+            Ok(isa) => isa,
+            Err(_) => {
+                println!("Invalid instruction set: {} (expected 1.1 or 1.7)", s);
+                exit(1);
+            },
+        },
+        None => Isa::V1_7,
+    };
+
+    let mut cpu = DCPU::new();
+    cpu.set_isa(isa);
+    let path = Path::new(filename);
+    match cpu.load_from_binary_file(&path) {
+        Ok(()) => {},
+        Err(why) => {
+            println!("Could load file {}: {}", path.display(), why);
+            exit(1);
+        },
+    }
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.set_halt_on_zero(matches.opt_present("halt-on-zero"));
+
+    let history = match matches.opt_str("history") {
+        Some(s) => match s.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                println!("Invalid history size: {}", s);
+                exit(1);
+            },
+        },
+        None => HISTORY,
+    };
+    cpu.set_history_limit(history);
+
+    let mut symbols = match matches.opt_str("symbols") {
+        Some(s) => match SymbolMap::load(&Path::new(&s)) {
+            Ok(symbols) => Some(symbols),
+            Err(why) => {
+                println!("Could not load symbol map {}: {}", s, why);
+                exit(1);
+            },
+        },
+        None => None,
+    };
+
+    let source = match matches.opt_str("source") {
+        Some(s) => {
+            let lines = read_lines(&Path::new(&s));
+            let mut pcpu = assembler::PCPU::new();
+            pcpu.set_isa(isa);
+            if let Err(err) = assembler::parse(&lines, &mut pcpu) {
+                assembler::print_parse_error(&pcpu, &lines[err.line as usize][..], err);
+                exit(1);
+            }
+            if (0..pcpu.pc as usize).any(|i| pcpu.mem[i] != cpu.mem[i]) {
+                println!("Warning: {} does not match {}", s, filename);
+            }
+            if symbols.is_none() {
+                symbols = Some(SymbolMap::from_assembler(&pcpu));
+            }
+            Some(Source { lines: lines, ranges: pcpu.line_ranges().to_vec() })
+        },
+        None => None,
+    };
+
+    let mut debugger = Debugger {
+        cpu: cpu,
+        symbols: symbols.unwrap_or_else(SymbolMap::new),
+        source: source,
+        watches: Vec::new(),
+        color: !matches.opt_present("m"),
+    };
+
+    debugger.print_location();
+
+    let stdin = io::stdin();
+    let mut last_line = String::new();
+    loop {
+        print!("(dcpu16) ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(_) => break,
+        }
+        if line.trim().is_empty() {
+            line = last_line.clone();
+        } else {
+            last_line = line.clone();
+        }
+        if !debugger.execute(&line) {
+            break;
+        }
+    }
+}
@@ -11,6 +11,7 @@ use std::path::Path;
 use getopts::Options;
 use dcpu16::dcpu;
 use dcpu16::disassembler;
+use dcpu16::instructions::Isa;
 use std::process::exit;
 
 fn main() {
@@ -19,6 +20,7 @@ fn main() {
     let program = args[0].clone();
 
     opts.optflag("m", "no-color", "do not use ANSI colors in output");
+    opts.optopt("", "isa", "instruction set revision: 1.1 or 1.7 (default: 1.7)", "VERSION");
     opts.optflag("v", "version", "print version");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
@@ -46,7 +48,19 @@ fn main() {
     let color = !matches.opt_present("m");
     let ref filename = matches.free[0];
 
+    let isa = match matches.opt_str("isa") {
+        Some(s) => match s.parse() {
+            Ok(isa) => isa,
+            Err(_) => {
+                println!("Invalid instruction set: {} (expected 1.1 or 1.7)", s);
+                exit(1);
+            },
+        },
+        None => Isa::V1_7,
+    };
+
     let mut cpu = dcpu::DCPU::new();
+    cpu.set_isa(isa);
 
     let path = Path::new(filename);
     let mut file = match File::open(&path) {
@@ -5,16 +5,30 @@ mod cli;
 
 use std::vec::Vec;
 use std::path::Path;
-use std::{env, thread, time};
-use dcpu16::dcpu;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::{env, time};
+use dcpu16::dcpu::{self, StopReason};
 use dcpu16::disassembler;
+use dcpu16::gdb::GdbStub;
+use dcpu16::instructions::Isa;
+use dcpu16::scheduler::{Scheduler, Speed};
+use dcpu16::symbols::SymbolMap;
+use std::net::TcpListener;
 //use dcpu16::bin::cli;
 use getopts::Options;
 use std::process::exit;
 
 use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::exit::{DeviceExit, EXIT_LIMIT, EXIT_OVERFLOW, exit_code};
+use dcpu16::devices::console::DeviceConsole;
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk, FloppyTiming, ImageFormat};
 
 const FPS: usize = 30;
+// Rows in each table of the profile report
+const PROFILE_ROWS: usize = 30;
+// Instructions that a GDB client can step back
+const GDB_HISTORY: usize = 100_000;
 
 fn main() {
     let mut opts = Options::new();
@@ -22,6 +36,36 @@ fn main() {
     let program = args[0].clone();
 
     opts.optflag("p", "print", "print CPU info each tick");
+    opts.optopt("", "isa", "instruction set revision: 1.1 or 1.7 (default: 1.7)", "VERSION");
+    opts.optflag("z", "halt-on-zero", "halt when a 0x0000 word is executed (e.g. DAT 0)");
+    opts.optflag("b", "headless", "run as fast as possible, without keeping real-time pace");
+    opts.optopt("", "speed", "run at FACTOR times real time, or unlimited (default: 1)", "FACTOR");
+    opts.optopt("", "max-cycles", "stop after N cycles", "N");
+    opts.optopt("", "timeout", "stop after SECONDS of wall-clock time", "SECONDS");
+    opts.optflag("", "exit-device", "attach an exit device, which lets the program set the exit status");
+    opts.optflag("", "console", "attach a console device, which gives the program the terminal's stdin and stdout");
+    opts.optopt("", "floppy", "attach an M35FD floppy drive with this disk image (created if missing), \
+                               and write changed sectors back to it on exit", "PATH");
+    opts.optflag("", "write-protect", "insert the --floppy disk write-protected");
+    opts.optflag("", "instant-floppy", "make --floppy reads and writes take no time, instead of the M35FD's seek \
+                                        and transfer times");
+    opts.optflag("", "list-devices", "print the attached devices as HWQ reports them, and exit (FILE is optional)");
+    opts.optopt("", "dump", "write registers and memory (see --dump-range) to a file on exit", "PATH");
+    opts.optmulti("", "dump-range", "memory to include in --dump (inclusive, can be repeated)", "FROM:TO");
+    opts.optopt("", "rom", "load a firmware image as ROM and boot from it", "PATH");
+    opts.optopt("", "rom-address", "where to load the ROM (default: end of memory)", "ADDRESS");
+    opts.optflag("", "rom-fault", "stop if the program writes to ROM (default: ignore the write)");
+    opts.optopt("", "faults", "what to do on reserved opcodes, literal writes and interrupt queue \
+                               overflow: ignore, log, halt or fire (default: halt on overflow only)", "POLICY");
+    opts.optopt("r", "restore", "resume from a machine snapshot instead of starting fresh", "PATH");
+    opts.optopt("s", "snapshot", "save a machine snapshot when the program terminates", "PATH");
+    opts.optopt("t", "trace", "write an execution trace (compare traces with dcpu16-trace-diff)", "PATH");
+    opts.optopt("", "profile", "write a report of where cycles were spent", "PATH");
+    opts.optopt("", "folded", "write profiled call stacks in folded format (for flamegraphs)", "PATH");
+    opts.optopt("", "coverage", "write line and branch coverage in lcov format (needs --symbols)", "PATH");
+    opts.optopt("y", "symbols", "symbol map from the assembler (for --profile and --coverage)", "PATH");
+    opts.optopt("g", "gdb", "wait for a GDB remote protocol client on localhost:PORT", "PORT");
+    opts.optopt("", "gdb-socket", "wait for a GDB remote protocol client on a Unix socket", "PATH");
     opts.optflag("v", "version", "print version");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
@@ -33,7 +77,12 @@ fn main() {
     };
 
     if matches.opt_present("h") {
-        cli::print_usage(&program, "FILE", opts, &["-p output.bin"]);
+        cli::print_usage(&program, "FILE", opts, &["-p output.bin", "--gdb 1234 output.bin",
+                                                    "--headless --max-cycles 1000000 test.bin"]);
+        println!("The exit status is the value of a in HLT a, register A if the program halts on\n\
+                  0x0000 (--halt-on-zero), the status given to the exit device, or {} if\n\
+                  --max-cycles or --timeout is reached. Values above 255, and {} itself, exit\n\
+                  with {}.", EXIT_LIMIT, EXIT_LIMIT, EXIT_OVERFLOW);
         return;
     }
 
@@ -42,22 +91,78 @@ fn main() {
         return;
     }
 
-    if matches.free.len() != 1 {
+    let list_devices = matches.opt_present("list-devices");
+    if matches.free.len() != 1 && !(list_devices && matches.free.is_empty()) {
         println!("Please input file");
         return;
     }
     let print = matches.opt_present("p");
-    let ref filename = matches.free[0];
+    let speed = match matches.opt_str("speed") {
+        Some(ref s) if s == "unlimited" => Speed::Unlimited,
+        Some(s) => match s.parse::<f64>() {
+            Ok(f) if f > 0.0 => Speed::Factor(f),
+            _ => {
+                println!("Invalid speed: {}", s);
+                exit(1);
+            },
+        },
+        None if matches.opt_present("headless") => Speed::Unlimited,
+        None => Speed::Factor(1.0),
+    };
+    let max_cycles = match matches.opt_str("max-cycles") {
+        Some(s) => match s.parse::<u64>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                println!("Invalid cycle count: {}", s);
+                exit(1);
+            },
+        },
+        None => None,
+    };
+    let deadline = match matches.opt_str("timeout") {
+        Some(s) => match s.parse::<f64>() {
+            Ok(t) if t >= 0.0 => Some(time::Instant::now() + time::Duration::from_millis((t * 1000.0) as u64)),
+            _ => {
+                println!("Invalid timeout: {}", s);
+                exit(1);
+            },
+        },
+        None => None,
+    };
+    let mut dump_ranges = Vec::new();
+    for s in matches.opt_strs("dump-range") {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().and_then(parse_address), parts.next().and_then(parse_address)) {
+            (Some(from), Some(to)) if from <= to => dump_ranges.push((from, to)),
+            _ => {
+                println!("Invalid memory range: {}", s);
+                exit(1);
+            },
+        }
+    }
 
+    let isa = match matches.opt_str("isa") {
+        Some(s) => match s.parse() {
+            Ok(isa) => isa,
+            Err(_) => {
+                println!("Invalid instruction set: {} (expected 1.1 or 1.7)", s);
+                exit(1);
+            },
+        },
+        None => Isa::V1_7,
+    };
     let mut cpu = dcpu::DCPU::new();
+    cpu.set_isa(isa);
 
-    let path = Path::new(filename);
-    match cpu.load_from_binary_file(&path) {
-        Ok(()) => {},
-        Err(why) => {
-            println!("Could load file {}: {}", path.display(), why);
-            exit(1);
-        },
+    if let Some(filename) = matches.free.get(0) {
+        let path = Path::new(filename);
+        match cpu.load_from_binary_file(&path) {
+            Ok(()) => {},
+            Err(why) => {
+                println!("Could load file {}: {}", path.display(), why);
+                exit(1);
+            },
+        }
     }
 
     // Connect hardware
@@ -70,25 +175,355 @@ fn main() {
     */
 
     let clock = DeviceClockGeneric::new();
-    cpu.add_device(Box::new(clock));
+    cpu.add_device(Box::new(clock)).unwrap();
+    if matches.opt_present("exit-device") {
+        cpu.add_device(Box::new(DeviceExit::new())).unwrap();
+    }
+    if matches.opt_present("console") {
+        cpu.add_device(Box::new(DeviceConsole::stdio())).unwrap();
+    }
+    if let Some(image) = matches.opt_str("floppy") {
+        let path = Path::new(&image);
+        let result = if path.exists() {
+            FloppyDisk::load(path)
+        } else {
+            let mut disk = FloppyDisk::new();
+            disk.save(path, ImageFormat::Raw).map(|_| disk)
+        };
+        let mut disk = match result {
+            Ok(disk) => disk,
+            Err(why) => {
+                println!("Could not load floppy image {}: {}", image, why);
+                exit(1);
+            },
+        };
+        if matches.opt_present("write-protect") {
+            disk.write_protected = true;
+        }
+        let mut floppy = DeviceFloppyM35FD::new();
+        if matches.opt_present("instant-floppy") {
+            floppy.set_timing(FloppyTiming::instant());
+        }
+        floppy.insert(disk);
+        cpu.add_device(Box::new(floppy)).unwrap();
+    }
+    if list_devices {
+        print_devices(&cpu);
+        return;
+    }
+    cpu.set_halt_on_zero(matches.opt_present("halt-on-zero"));
 
-    // If printing is turned on, CPU will tick through (without proper timing)
-    if print {
-        while !cpu.terminate {
-            cpu.tick();
+    if let Some(rom_filename) = matches.opt_str("rom") {
+        let rom = match dcpu::read_binary_file(Path::new(&rom_filename)) {
+            Ok(rom) => rom,
+            Err(why) => {
+                println!("Could not load ROM {}: {}", rom_filename, why);
+                exit(1);
+            },
+        };
+        let address = match matches.opt_str("rom-address") {
+            Some(s) => match parse_address(&s) {
+                Some(a) => a,
+                None => {
+                    println!("Invalid ROM address: {}", s);
+                    exit(1);
+                },
+            },
+            None => (dcpu::MEMORY_SIZE - rom.len().min(dcpu::MEMORY_SIZE)) as u16,
+        };
+        if let Err(why) = cpu.load_rom(address, &rom) {
+            println!("Could not map ROM {}: {}", rom_filename, why);
+            exit(1);
+        }
+        cpu.set_reset_vector(address);
+        if matches.opt_present("rom-fault") {
+            cpu.set_rom_write_policy(dcpu::RomWritePolicy::Stop);
+        }
+    }
+
+    if let Some(policy) = matches.opt_str("faults") {
+        let policy = match &policy[..] {
+            "ignore" => dcpu::FaultPolicy::Ignore,
+            "log" => dcpu::FaultPolicy::Log,
+            "halt" => dcpu::FaultPolicy::Halt,
+            "fire" => dcpu::FaultPolicy::Fire,
+            _ => {
+                println!("Invalid fault policy: {}", policy);
+                exit(1);
+            },
+        };
+        for &kind in [dcpu::FaultKind::InterruptQueueOverflow, dcpu::FaultKind::ReservedOpcode,
+                      dcpu::FaultKind::LiteralWrite].iter() {
+            cpu.set_fault_policy(kind, policy);
+        }
+    }
+
+    if let Some(snapshot) = matches.opt_str("restore") {
+        if let Err(why) = cpu.load_snapshot(Path::new(&snapshot)) {
+            println!("Could not restore snapshot {}: {}", snapshot, why);
+            exit(1);
+        }
+    }
+
+    if let Some(trace) = matches.opt_str("trace") {
+        let result = File::create(&trace).and_then(|f| cpu.start_trace(Box::new(BufWriter::new(f))));
+        if let Err(why) = result {
+            println!("Could not write trace {}: {}", trace, why);
+            exit(1);
+        }
+    }
+
+    let symbols = match matches.opt_str("symbols") {
+        Some(s) => match SymbolMap::load(&Path::new(&s)) {
+            Ok(symbols) => Some(symbols),
+            Err(why) => {
+                println!("Could not load symbol map {}: {}", s, why);
+                exit(1);
+            },
+        },
+        None => None,
+    };
+
+    if matches.opt_present("profile") || matches.opt_present("folded") {
+        cpu.start_profiling();
+    }
+
+    if matches.opt_present("coverage") {
+        if symbols.is_none() {
+            println!("Coverage needs a symbol map (--symbols)");
+            exit(1);
+        }
+        cpu.start_coverage();
+    }
+
+    if matches.opt_present("gdb") || matches.opt_present("gdb-socket") {
+        cpu.set_history_limit(GDB_HISTORY);
+    }
+
+    let mut stop = None;
+    // Set if --max-cycles or --timeout was reached
+    let mut limit = None;
+    let start_cycle = cpu.cycle();
+    if let Some(port) = matches.opt_str("gdb") {
+        let listener = match TcpListener::bind(("127.0.0.1", port.parse().unwrap_or(0))) {
+            Ok(l) => l,
+            Err(why) => {
+                println!("Could not listen on port {}: {}", port, why);
+                exit(1);
+            },
+        };
+        println!("Waiting for GDB on {}", listener.local_addr().unwrap());
+        let stream = match listener.accept() {
+            Ok((s, _)) => s,
+            Err(why) => {
+                println!("Could not accept connection: {}", why);
+                exit(1);
+            },
+        };
+        if let Err(why) = GdbStub::new(stream).serve(&mut cpu) {
+            println!("GDB connection failed: {}", why);
+            flush_floppy(&mut cpu);
+            exit(1);
+        }
+    } else if let Some(path) = matches.opt_str("gdb-socket") {
+        serve_gdb_socket(&path, &mut cpu);
+    } else if print { // If printing is turned on, CPU will tick through (without proper timing)
+        while stop.is_none() {
+            limit = check_limits(cpu.cycle() - start_cycle, max_cycles, deadline);
+            if limit.is_some() {
+                break;
+            }
+            stop = cpu.tick().stop_reason();
             let (_, s) = disassembler::disassemble_instruction(&cpu, true);
             println!("---------------------------------------------");
             println!("::: {}", s);
             cpu.print();
         }
-    } else { // If printing is not on, then the CPU will run roughly at 100 kHz
-        let cycles = dcpu::CYCLE_HZ / FPS;
-        while !cpu.terminate {
-            //let now = time::Instant::now();
-            cpu.run(cycles);
-            //let elapsed = now.elapsed();
-            // TODO: Use elapsed to sleep slightly shorter to get timing right
-            thread::sleep(time::Duration::from_millis((1000 / FPS) as u64));
+    } else { // If printing is not on, then the CPU will run at 100 kHz times the speed
+        cpu.set_device_batch(dcpu::REALTIME_DEVICE_BATCH);
+        let mut scheduler = Scheduler::new();
+        scheduler.set_speed(speed);
+        scheduler.set_slice(time::Duration::from_millis((1000 / FPS) as u64));
+        while stop.is_none() {
+            let cycles = cpu.cycle() - start_cycle;
+            limit = check_limits(cycles, max_cycles, deadline);
+            if limit.is_some() {
+                break;
+            }
+            let due = scheduler.due_cycles();
+            let n = match max_cycles {
+                Some(max) => due.min(max - cycles),
+                None => due,
+            };
+            stop = scheduler.run(&mut cpu, n).stop_reason();
+            scheduler.wait();
+        }
+    }
+
+    if let Some(why) = limit {
+        println!("Stopped after {} cycles: {}", cpu.cycle() - start_cycle, why);
+    }
+
+    match stop {
+        Some(StopReason::RomWrite(address)) => {
+            println!("Program wrote to ROM at 0x{:04x} (PC 0x{:04x})", address, cpu.pc);
+        },
+        Some(StopReason::Fault(fault)) => {
+            println!("DCPU halted: {}", fault);
+        },
+        _ => {},
+    }
+
+    flush_floppy(&mut cpu);
+
+    if let Err(why) = cpu.stop_trace() {
+        println!("Could not write trace: {}", why);
+        exit(1);
+    }
+
+    if let Some(profiler) = cpu.stop_profiling() {
+        if let Some(path) = matches.opt_str("profile") {
+            let result = File::create(&path).and_then(|mut f| {
+                profiler.write_report(&mut f, symbols.as_ref(), PROFILE_ROWS)
+            });
+            if let Err(why) = result {
+                println!("Could not write profile {}: {}", path, why);
+                exit(1);
+            }
+        }
+        if let Some(path) = matches.opt_str("folded") {
+            let result = File::create(&path).and_then(|mut f| {
+                profiler.write_folded(&mut f, symbols.as_ref())
+            });
+            if let Err(why) = result {
+                println!("Could not write folded stacks {}: {}", path, why);
+                exit(1);
+            }
+        }
+    }
+
+    if let (Some(coverage), Some(symbols)) = (cpu.stop_coverage(), symbols.as_ref()) {
+        let path = matches.opt_str("coverage").unwrap();
+        let result = File::create(&path).and_then(|mut f| {
+            coverage.write_lcov(&mut f, symbols, None, &cpu.mem[..])
+        });
+        if let Err(why) = result {
+            println!("Could not write coverage {}: {}", path, why);
+            exit(1);
         }
     }
+
+    if let Some(snapshot) = matches.opt_str("snapshot") {
+        if let Err(why) = cpu.save_snapshot(Path::new(&snapshot)) {
+            println!("Could not save snapshot {}: {}", snapshot, why);
+            exit(1);
+        }
+    }
+
+    if let Some(path) = matches.opt_str("dump") {
+        let result = File::create(&path).and_then(|mut f| write_dump(&mut f, &cpu, &dump_ranges));
+        if let Err(why) = result {
+            println!("Could not write dump {}: {}", path, why);
+            exit(1);
+        }
+    }
+
+    if limit.is_some() {
+        exit(EXIT_LIMIT);
+    }
+    match stop {
+        Some(StopReason::Halt(status)) | Some(StopReason::DeviceStop(status)) => exit(exit_code(status)),
+        Some(StopReason::ZeroWord(_)) => exit(exit_code(cpu.reg[dcpu::REG_A])),
+        Some(_) => exit(1),
+        None => {},
+    }
+}
+
+// Describes which limit was reached, if any
+fn check_limits(cycles: u64, max_cycles: Option<u64>, deadline: Option<time::Instant>) -> Option<&'static str> {
+    if max_cycles.map_or(false, |max| cycles >= max) {
+        Some("cycle limit reached")
+    } else if deadline.map_or(false, |d| time::Instant::now() >= d) {
+        Some("timeout")
+    } else {
+        None
+    }
+}
+
+// Writes what the program changed on the floppy disk back to its image file (see --floppy). Has to
+// happen before exiting once the program has run.
+fn flush_floppy(cpu: &mut dcpu::DCPU) {
+    if let Some(Err(why)) = cpu.with_device(|floppy: &mut DeviceFloppyM35FD, _| floppy.flush()) {
+        println!("Could not write floppy image: {}", why);
+        exit(1);
+    }
+}
+
+fn print_devices(cpu: &dcpu::DCPU) {
+    println!("Index  Hardware ID  Version  Manufacturer  Name");
+    for (i, info) in cpu.device_info().into_iter().enumerate() {
+        let info = info.unwrap();
+        println!("{:5}  0x{:08x}   0x{:04x}   0x{:08x}    {}",
+                 i, info.hardware_id, info.version, info.manufacturer_id, info.name);
+    }
+}
+
+fn write_dump<W: Write>(w: &mut W, cpu: &dcpu::DCPU, ranges: &[(u16, u16)]) -> io::Result<()> {
+    let names = ["A", "B", "C", "X", "Y", "Z", "I", "J"];
+    for (name, value) in names.iter().zip(cpu.reg.iter()) {
+        writeln!(w, "{:<2} {:04x}", name, value)?;
+    }
+    writeln!(w, "PC {:04x}", cpu.pc)?;
+    writeln!(w, "SP {:04x}", cpu.sp)?;
+    writeln!(w, "EX {:04x}", cpu.ex)?;
+    writeln!(w, "IA {:04x}", cpu.ia)?;
+    for &(from, to) in ranges {
+        writeln!(w, "")?;
+        let mut address = from as usize;
+        while address <= to as usize {
+            let end = (address + 8).min(to as usize + 1);
+            let words: Vec<String> = cpu.mem[address..end].iter().map(|v| format!("{:04x}", v)).collect();
+            writeln!(w, "{:04x}: {}", address, words.join(" "))?;
+            address = end;
+        }
+    }
+    Ok(())
+}
+
+fn parse_address(s: &str) -> Option<u16> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u16::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+#[cfg(unix)]
+fn serve_gdb_socket(path: &str, cpu: &mut dcpu::DCPU) {
+    use std::os::unix::net::UnixListener;
+
+    let listener = match UnixListener::bind(path) {
+        Ok(l) => l,
+        Err(why) => {
+            println!("Could not listen on {}: {}", path, why);
+            exit(1);
+        },
+    };
+    println!("Waiting for GDB on {}", path);
+    let result = match listener.accept() {
+        Ok((stream, _)) => GdbStub::new(stream).serve(cpu),
+        Err(why) => Err(why),
+    };
+    std::fs::remove_file(path).ok();
+    if let Err(why) = result {
+        println!("GDB connection failed: {}", why);
+        flush_floppy(cpu);
+        exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_gdb_socket(_: &str, _: &mut dcpu::DCPU) {
+    println!("Unix sockets are not supported on this platform");
+    exit(1);
 }
@@ -0,0 +1,126 @@
+extern crate dcpu16;
+extern crate getopts;
+
+mod cli;
+
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use getopts::Options;
+use dcpu16::dcpu::DCPU;
+use dcpu16::disassembler;
+use dcpu16::instructions::Isa;
+use dcpu16::trace::{self, TraceReader, TraceRecord};
+use std::process::exit;
+
+fn open_trace(filename: &str) -> TraceReader<BufReader<File>> {
+    let path = Path::new(filename);
+    let file = match File::open(&path) {
+        Ok(f) => f,
+        Err(why) => {
+            println!("Could not open file {}: {}", path.display(), why);
+            exit(2);
+        },
+    };
+    match TraceReader::new(BufReader::new(file)) {
+        Ok(t) => t,
+        Err(why) => {
+            println!("Could not read trace {}: {}", path.display(), why);
+            exit(2);
+        },
+    }
+}
+
+fn print_record(name: &str, record: &Option<TraceRecord>, isa: Isa, cpu: &mut DCPU, color: bool) {
+    let r = match *record {
+        Some(ref r) => r,
+        None => {
+            println!("{}: <end of trace>", name);
+            return;
+        },
+    };
+    cpu.set_isa(isa);
+    for (i, w) in r.instruction.iter().enumerate() {
+        cpu.mem[r.pc.wrapping_add(i as u16) as usize] = *w;
+    }
+    let (_, s) = disassembler::disassemble_instruction_at(cpu, r.pc, color);
+    println!("{}: {:04x}: {}  (cycle {})", name, r.pc, s, r.cycle);
+    if !r.registers.is_empty() {
+        let regs: Vec<String> = r.registers.iter()
+            .map(|&(i, v)| format!("{}={:04x}", trace::REGISTER_NAMES[i], v)).collect();
+        println!("    registers: {}", regs.join(" "));
+    }
+    if !r.writes.is_empty() {
+        let writes: Vec<String> = r.writes.iter()
+            .map(|&(address, v)| format!("[{:04x}]={:04x}", address, v)).collect();
+        println!("    writes:    {}", writes.join(" "));
+    }
+    if !r.interrupts.is_empty() {
+        let interrupts: Vec<String> = r.interrupts.iter().map(|v| format!("{:04x}", v)).collect();
+        println!("    interrupts: {}", interrupts.join(" "));
+    }
+}
+
+fn main() {
+    let mut opts = Options::new();
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    opts.optflag("m", "no-color", "do not use ANSI colors in output");
+    opts.optflag("v", "version", "print version");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m },
+        Err(why) => {
+            println!("{}", why);
+            exit(2);
+        },
+    };
+
+    if matches.opt_present("h") {
+        cli::print_usage(&program, "TRACE TRACE", opts, &["good.trace new.trace"]);
+        return;
+    }
+
+    if matches.opt_present("v") {
+        cli::print_version(&program);
+        return;
+    }
+
+    if matches.free.len() != 2 {
+        println!("Please input two trace files");
+        exit(2);
+    }
+    let color = !matches.opt_present("m");
+    let ref name_a = matches.free[0];
+    let ref name_b = matches.free[1];
+
+    let mut a = open_trace(name_a);
+    let mut b = open_trace(name_b);
+    let (isa_a, isa_b) = (a.isa(), b.isa());
+    let divergence = match trace::first_divergence(&mut a, &mut b) {
+        Ok(d) => d,
+        Err(why) => {
+            println!("Could not read traces: {}", why);
+            exit(2);
+        },
+    };
+
+    match divergence {
+        None => println!("Traces are identical"),
+        Some(d) => {
+            // Disassembly needs a DCPU to read from
+            let mut cpu = DCPU::new();
+            println!("Traces diverge at record {}", d.index);
+            if d.previous.is_some() {
+                println!("Last matching record:");
+                print_record("both", &d.previous, isa_a, &mut cpu, color);
+            }
+            println!("First differing record:");
+            print_record(name_a, &d.a, isa_a, &mut cpu, color);
+            print_record(name_b, &d.b, isa_b, &mut cpu, color);
+            exit(1);
+        },
+    }
+}
@@ -0,0 +1,46 @@
+// Built-in boot ROM.
+//
+// The bootloader looks for the first M35FD floppy drive, reads sector 0 of the inserted disk into
+// 0x0000 and jumps there. When the program starts, I holds the device number of the drive and SP
+// is set to the start of the ROM, so that the stack grows below it. If there is no drive, no disk
+// or the read fails, the DCPU halts with `HLT 1`.
+//
+// The code is position independent apart from setting SP, so the ROM can be placed anywhere:
+//
+//     let rom = bootrom::bootloader(bootrom::BOOTLOADER_ADDRESS);
+//     cpu.load_rom(bootrom::BOOTLOADER_ADDRESS, &rom).unwrap();
+//     cpu.set_reset_vector(bootrom::BOOTLOADER_ADDRESS);
+
+/// Suggested address for the bootloader, at the top of memory.
+pub const BOOTLOADER_ADDRESS: u16 = 0xffe0;
+
+const BOOTLOADER: [u16; 24] = [
+    0x7f61, 0x0000, //        SET SP, <rom address>
+    0x1a00,         //        HWN I
+    0x84d2,         // :find  IFE I, 0
+    0xcf82,         //          ADD PC, 18          ; to fail
+    0x88c3,         //        SUB I, 1
+    0x1a20,         //        HWQ I
+    0x7c12, 0x24c5, //        IFE A, 0x24c5         ; M35FD hardware ID
+    0x7c32, 0x4fd5, //          IFE B, 0x4fd5
+    0x8b82,         //            ADD PC, 1         ; to found
+    0xaf83,         //        SUB PC, 10            ; to find
+    0x8c01,         // :found SET A, 2              ; read sector
+    0x8461,         //        SET X, 0
+    0x8481,         //        SET Y, 0
+    0x1a40,         //        HWI I
+    0x8401,         // :wait  SET A, 0              ; poll
+    0x1a40,         //        HWI I
+    0x9032,         //        IFE B, 3              ; busy
+    0x9783,         //          SUB PC, 4           ; to wait
+    0x8452,         //        IFE C, 0              ; no error
+    0x8781,         //          SET PC, 0
+    0x8aa0,         // :fail  HLT 1
+];
+
+/// The bootloader, set up to be loaded at `address`.
+pub fn bootloader(address: u16) -> Vec<u16> {
+    let mut rom = BOOTLOADER.to_vec();
+    rom[1] = address;
+    rom
+}
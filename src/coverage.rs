@@ -0,0 +1,138 @@
+// Code coverage.
+//
+// Records how many times each address was executed, and for conditional instructions (IFB, IFC,
+// IFE, IFN, IFG, IFA, IFL, IFU) how many times the condition held (the next instruction ran) and
+// how many times it failed (the next instruction was skipped). Together with the line ranges of a
+// symbol map, this is written as an lcov tracefile:
+//
+//     $ dcpu16-assembler program.asm -o program.bin -s program.sym
+//     $ dcpu16 --coverage program.info -y program.sym program.bin
+//
+// Coverage is started with `DCPU::start_coverage`.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use instructions::{self, Isa};
+use symbols::SymbolMap;
+
+/// Outcomes of a conditional instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct BranchStats {
+    /// The condition held, so the next instruction was executed.
+    pub taken: u64,
+    /// The condition failed, so the next instruction was skipped.
+    pub skipped: u64,
+}
+
+pub struct Coverage {
+    hits: Vec<u64>,
+    branches: HashMap<u16, BranchStats>,
+    // Used to tell which instructions are conditional
+    isa: Isa,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::for_isa(Isa::V1_7)
+    }
+
+    /// Coverage of a program written for `isa`.
+    pub fn for_isa(isa: Isa) -> Coverage {
+        Coverage {
+            hits: vec![0; 0x10000],
+            branches: HashMap::new(),
+            isa: isa,
+        }
+    }
+
+    /// Records that the instruction at `pc` was executed. `condition` is the outcome if it was a
+    /// conditional instruction.
+    pub fn record(&mut self, pc: u16, condition: Option<bool>) {
+        self.hits[pc as usize] += 1;
+        if let Some(truth) = condition {
+            let b = self.branches.entry(pc).or_insert(BranchStats::default());
+            if truth {
+                b.taken += 1;
+            } else {
+                b.skipped += 1;
+            }
+        }
+    }
+
+    /// Number of times the instruction at `address` was executed.
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[address as usize]
+    }
+
+    /// Outcomes of the conditional instruction at `address`, if it was executed.
+    pub fn branch(&self, address: u16) -> Option<BranchStats> {
+        self.branches.get(&address).cloned()
+    }
+
+    /// Writes an lcov tracefile for the source file named in `symbols` (or `source` if given).
+    /// Only lines with instructions count, and a line is hit if its first word was executed.
+    /// Conditional instructions are found by looking at `mem`, which should hold the program.
+    pub fn write_lcov<W: Write>(&self, writer: &mut W, symbols: &SymbolMap, source: Option<&str>,
+                                mem: &[u16]) -> io::Result<()> {
+        let source = source.or(symbols.source()).unwrap_or("");
+        writeln!(writer, "TN:")?;
+        writeln!(writer, "SF:{}", source)?;
+
+        let code: Vec<_> = symbols.lines().iter().filter(|l| !l.data).collect();
+
+        // Labels that point at instructions are reported as functions
+        let functions: Vec<(&str, u16, usize)> = symbols.labels().iter().filter_map(|&(ref name, address)| {
+            code.iter().find(|l| l.start == address).map(|l| (&name[..], address, l.line))
+        }).collect();
+        for &(name, _, line) in functions.iter() {
+            writeln!(writer, "FN:{},{}", line + 1, name)?;
+        }
+        let mut functions_hit = 0;
+        for &(name, address, _) in functions.iter() {
+            let hits = self.hits(address);
+            writeln!(writer, "FNDA:{},{}", hits, name)?;
+            if hits > 0 {
+                functions_hit += 1;
+            }
+        }
+        writeln!(writer, "FNF:{}", functions.len())?;
+        writeln!(writer, "FNH:{}", functions_hit)?;
+
+        let mut branches = 0;
+        let mut branches_hit = 0;
+        for l in code.iter() {
+            if !mem.get(l.start as usize).map_or(false, |&w| instructions::is_conditional(w, self.isa)) {
+                continue;
+            }
+            match self.branch(l.start) {
+                Some(b) => {
+                    writeln!(writer, "BRDA:{},0,0,{}", l.line + 1, b.taken)?;
+                    writeln!(writer, "BRDA:{},0,1,{}", l.line + 1, b.skipped)?;
+                    branches_hit += (b.taken > 0) as usize + (b.skipped > 0) as usize;
+                },
+                None => {
+                    // Never executed
+                    writeln!(writer, "BRDA:{},0,0,-", l.line + 1)?;
+                    writeln!(writer, "BRDA:{},0,1,-", l.line + 1)?;
+                },
+            }
+            branches += 2;
+        }
+        writeln!(writer, "BRF:{}", branches)?;
+        writeln!(writer, "BRH:{}", branches_hit)?;
+
+        let mut lines_hit = 0;
+        for l in code.iter() {
+            let hits = self.hits(l.start);
+            writeln!(writer, "DA:{},{}", l.line + 1, hits)?;
+            if hits > 0 {
+                lines_hit += 1;
+            }
+        }
+        writeln!(writer, "LF:{}", code.len())?;
+        writeln!(writer, "LH:{}", lines_hit)?;
+        writeln!(writer, "end_of_record")?;
+        Ok(())
+    }
+}
@@ -2,13 +2,20 @@
 
 use std::path::Path;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Write};
 use std::io::Result;
 use std::any::Any;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::collections::{HashSet, VecDeque};
 
 use instructions::*;
+use extensions::Extensions;
+use snapshot::{self, StateWriter, StateReader};
+use trace::{self, TraceWriter};
+use profiler::Profiler;
+use coverage::Coverage;
 
 // Note: this can't be changed willy-nilly, since the PC is naturally wrapped around, so it will
 // not wrap around correctly if this is changed.
@@ -25,22 +32,345 @@ pub const REG_J: usize = 7;
 
 pub const CYCLE_HZ: usize = 100_000;
 
+/// Device batch (see `DCPU::set_device_batch`) for running in real time: devices run once per
+/// millisecond of emulated time, which is finer than any of the included devices need.
+pub const REALTIME_DEVICE_BATCH: u64 = (CYCLE_HZ / 1000) as u64;
+
+/// Emulated time that `cycles` take at `CYCLE_HZ`.
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    let hz = CYCLE_HZ as u64;
+    Duration::new(cycles / hz, ((cycles % hz) * 1_000_000_000 / hz) as u32)
+}
+
+/// Number of cycles that run in `duration` of emulated time, rounded down.
+pub fn duration_to_cycles(duration: Duration) -> u64 {
+    let hz = CYCLE_HZ as u64;
+    duration.as_secs() * hz + (duration.subsec_nanos() as u64) * hz / 1_000_000_000
+}
+
 const SHOW_ROWS_RADIUS: usize = 1;
 
-pub trait Device {
-    fn info_hardware_id_upper(&self) -> u16;
-    fn info_hardware_id_lower(&self) -> u16;
-    fn info_manufacturer_id_upper(&self) -> u16;
-    fn info_manufacturer_id_lower(&self) -> u16;
-    fn info_version(&self) -> u16;
+// Default seed of the random number generator used for fire damage
+const FIRE_SEED: u32 = 0x2545f491;
+
+const SNAPSHOT_MAGIC: &'static [u8] = b"DCPU16SS";
+const SNAPSHOT_VERSION: u16 = 3;
+
+/// Number of interrupts that can be queued. According to the spec, the DCPU-16 catches fire if
+/// the queue grows longer than this.
+pub const MAX_QUEUED_INTERRUPTS: usize = 256;
+
+/// Number of devices that can be attached, since `HWN` counts them in one word.
+pub const MAX_DEVICES: usize = 0xffff;
+
+/// How a device identifies itself, as reported by `HWQ`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Human-readable name, for tools. Programs never see it.
+    pub name: &'static str,
+    pub hardware_id: u32,
+    pub version: u16,
+    pub manufacturer_id: u32,
+}
+
+/// Hardware attached to the DCPU. Each device is locked while its methods run, so lookups such as
+/// `DCPU::find_device` can not see the device that calls them (see `DCPU::device_info`).
+pub trait Device: Send {
+    fn info(&self) -> DeviceInfo;
     fn process_interrupt(&mut self, cpu: &mut DCPU) -> ();
     fn run(&mut self, cpu: &mut DCPU, cycle: usize) -> ();
+    /// Writes the internal state of the device, so that it can be restored by `load_state`.
+    fn save_state(&self, state: &mut StateWriter) -> ();
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()>;
+    /// Returns the device to its power-on state, as part of `DCPU::soft_reset`. Anything outside
+    /// of the machine, such as an inserted disk or the host's terminal, is kept.
+    fn reset(&mut self) -> () {
+    }
+    /// Called when the DCPU reads from memory that is mapped to the device (see
+    /// `DCPU::map_device`). The device should use `cpu.mem` directly if it needs to access RAM.
+    fn memory_read(&mut self, _cpu: &mut DCPU, _address: u16) -> u16 {
+        0
+    }
+    /// Called when the DCPU writes to memory that is mapped to the device.
+    fn memory_write(&mut self, _cpu: &mut DCPU, _address: u16, _value: u16) -> () {
+    }
     fn as_any(&self) -> &Any;
     fn as_any_mut(&mut self) -> &mut Any;
 }
 
+/// Which kind of memory access a watchpoint reacts to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// A single memory access, as reported by a triggered watchpoint.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Watchpoint {
+    id: usize,
+    from: u16,
+    to: u16,
+    kind: WatchKind,
+}
+
+/// Outcome of `run` and `tick`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RunStatus {
+    /// The instruction was executed and nothing stopped execution (only returned by `tick`).
+    Running,
+    /// `run` used up the cycles it was given.
+    CycleBudget,
+    /// Execution stopped early.
+    Stopped(StopReason),
+}
+
+impl RunStatus {
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        match *self {
+            RunStatus::Stopped(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop_reason().is_some()
+    }
+}
+
+/// Reason why `run` or `tick` returned early.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// PC reached a breakpoint. The instruction at this address has not been executed yet.
+    Breakpoint(u16),
+    /// The watchpoint with the given ID was triggered by an access to `address`. The instruction
+    /// that made the access has been completed.
+    Watchpoint { id: usize, address: u16, access: MemoryAccess },
+    /// An instruction tried to write to ROM at `address` (only with `RomWritePolicy::Stop`). The
+    /// write was ignored and the instruction has been completed.
+    RomWrite(u16),
+    /// A fault with `FaultPolicy::Halt` happened. The DCPU stays halted until `clear_fault`.
+    Fault(CpuFault),
+    /// `HLT a` was executed, with this value of `a`. The DCPU stays halted until `resume`.
+    Halt(u16),
+    /// A 0x0000 word at this address was executed while `set_halt_on_zero` is on. The DCPU stays
+    /// halted until `resume`.
+    ZeroWord(u16),
+    /// A device called `DCPU::request_stop` with this code. The DCPU is not halted.
+    DeviceStop(u16),
+}
+
+/// Something the program did that the DCPU-16 spec forbids or leaves undefined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CpuFault {
+    /// An interrupt with this message was triggered while `MAX_QUEUED_INTERRUPTS` were already
+    /// queued. The interrupt is dropped.
+    InterruptQueueOverflow(u16),
+    /// The instruction `word` at `pc` has a reserved opcode. It is executed as a 1-cycle no-op.
+    ReservedOpcode { pc: u16, word: u16 },
+    /// The instruction at `pc` tried to write to a literal operand. The write is ignored.
+    LiteralWrite { pc: u16 },
+}
+
+/// The kinds of `CpuFault`, for configuring them with `DCPU::set_fault_policy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FaultKind {
+    InterruptQueueOverflow,
+    ReservedOpcode,
+    LiteralWrite,
+}
+
+impl CpuFault {
+    pub fn kind(&self) -> FaultKind {
+        match *self {
+            CpuFault::InterruptQueueOverflow(_) => FaultKind::InterruptQueueOverflow,
+            CpuFault::ReservedOpcode { .. } => FaultKind::ReservedOpcode,
+            CpuFault::LiteralWrite { .. } => FaultKind::LiteralWrite,
+        }
+    }
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuFault::InterruptQueueOverflow(message) => {
+                write!(f, "Interrupt queue overflow (dropped message 0x{:04x})", message)
+            },
+            CpuFault::ReservedOpcode { pc, word } => {
+                write!(f, "Reserved opcode 0x{:04x} at 0x{:04x}", word, pc)
+            },
+            CpuFault::LiteralWrite { pc } => write!(f, "Write to literal at 0x{:04x}", pc),
+        }
+    }
+}
+
+/// What happens when a `CpuFault` occurs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Nothing, apart from what the fault itself does (see `CpuFault`).
+    Ignore,
+    /// The fault is written to stderr, and execution continues.
+    Log,
+    /// `run` and `tick` stop with `StopReason::Fault`, and the DCPU halts until `clear_fault`.
+    Halt,
+    /// The DCPU catches fire, and a random memory word is corrupted every tick from then on,
+    /// until `clear_fault`. Execution continues.
+    Fire,
+}
+
+/// What an address range of the memory bus is mapped to. Unmapped addresses are plain RAM.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// Reads and writes go to the device with this index.
+    Device(usize),
+    /// Read-only memory. Reads come from `mem`, and writes made by the DCPU are ignored.
+    Rom,
+}
+
+/// What happens when an instruction writes to ROM.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RomWritePolicy {
+    /// The write is silently ignored.
+    Ignore,
+    /// The write is ignored, and `run` and `tick` stop with `StopReason::RomWrite`.
+    Stop,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryMapError {
+    /// The range overlaps the already mapped range `from..to` (inclusive).
+    Overlap { from: u16, to: u16 },
+    /// There is no device with this index.
+    NoSuchDevice(usize),
+    /// The end of the range is before the start.
+    InvalidRange,
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryMapError::Overlap { from, to } => {
+                write!(f, "Overlaps mapped memory 0x{:04x}-0x{:04x}", from, to)
+            },
+            MemoryMapError::NoSuchDevice(i) => write!(f, "No device {}", i),
+            MemoryMapError::InvalidRange => write!(f, "Invalid memory range"),
+        }
+    }
+}
+
+/// Why a device could not be attached or detached.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    /// There is no device with this index.
+    NoSuchDevice(usize),
+    /// `MAX_DEVICES` are already attached.
+    TooManyDevices,
+    /// The device with this index is mapped into memory (see `map_device`), and needs to be
+    /// unmapped first.
+    Mapped(usize),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeviceError::NoSuchDevice(i) => write!(f, "No device {}", i),
+            DeviceError::TooManyDevices => write!(f, "Too many devices (at most {})", MAX_DEVICES),
+            DeviceError::Mapped(i) => write!(f, "Device {} is mapped into memory", i),
+        }
+    }
+}
+
+/// A device attached to a DCPU. Devices are shared, so that they can be attached and detached
+/// while the DCPU is using them.
+pub type DeviceRef = Arc<Mutex<Box<Device>>>;
+
+#[derive(Debug, Copy, Clone)]
+struct Mapping {
+    from: u16,
+    to: u16,
+    region: MemoryRegion,
+}
+
+// An instruction word split into its fields. Entries in the decode cache are only used while the
+// word in memory is unchanged, which covers every way memory can be written: direct writes to
+// `mem`, the memory bus, device DMA, restoring a snapshot and stepping back.
+#[derive(Debug, Copy, Clone)]
+struct Decoded {
+    word: u16,
+    opcode: u8,
+    id_b: u8,
+    id_a: u8,
+    // Words that the operands take after the instruction word
+    next_words: u8,
+}
+
+impl Decoded {
+    // For 1.1, `id_b` is the target (or the non-basic opcode) and `id_a` the source, which is
+    // where they are in 1.7 as well
+    fn new(word: u16, isa: Isa) -> Decoded {
+        let w = word as usize;
+        let (opcode, id_b, id_a) = match isa {
+            Isa::V1_1 => (w & 0xf, (w >> 4) & 0x3f, (w >> 10) & 0x3f),
+            Isa::V1_7 => (w & 0x1f, (w >> 5) & 0x1f, (w >> 10) & 0x3f),
+        };
+        let mut next_words = 0;
+        if takes_next(id_a, isa) {
+            next_words += 1;
+        }
+        if opcode != 0 && takes_next(id_b, isa) {
+            next_words += 1;
+        }
+        Decoded {
+            word: word,
+            opcode: opcode as u8,
+            id_b: id_b as u8,
+            id_a: id_a as u8,
+            next_words: next_words,
+        }
+    }
+}
+
+// Whether an operand is followed by a next word
+fn takes_next(id: usize, isa: Isa) -> bool {
+    id >= 0x10 && id <= 0x17 || id == 0x1e || id == 0x1f || (id == 0x1a && isa == Isa::V1_7)
+}
+
+// Where the value of a 1.1 operand is, once any next word has been read
+#[derive(Debug, Copy, Clone)]
+enum Operand {
+    Register(usize),
+    Memory(u16),
+    Sp,
+    Pc,
+    Overflow,
+    Literal(u16),
+}
+
+// Everything needed to undo a single tick
+struct HistoryEntry {
+    reg: [u16; 8],
+    pc: u16,
+    sp: u16,
+    ex: u16,
+    ia: u16,
+    interrupt_queueing: bool,
+    interrupt_queue: Vec<u16>,
+    skip_next: bool,
+    halted: Option<StopReason>,
+    on_fire: bool,
+    cycles: u64,
+    // Address and previous value of every memory write, in the order they happened
+    writes: Vec<(u16, u16)>,
+}
+
 pub struct DCPU {
-    pub terminate: bool,
     pub reg: [u16; 8],
     pub mem: [u16; MEMORY_SIZE],
     pub pc: u16,
@@ -50,16 +380,60 @@ pub struct DCPU {
     interrupt_queueing: bool,
     interrupt_queue: Vec<u16>,
     skip_next: bool,
-    cycle: usize,
-    overshot_cycles: isize,
+    // Total cycles since the DCPU was created or reset. Never wraps.
+    cycle: u64,
+    // Cycles executed beyond the budget of the last call to `run`
+    overshot_cycles: u64,
     inside_run: bool,
-    pub devices: Rc<Vec<RefCell<Box<Device>>>>,
+    breakpoints: HashSet<u16>,
+    // Breakpoint that execution last stopped on. Resuming executes it, rather than stopping again.
+    resume_breakpoint: Option<u16>,
+    watchpoints: Vec<Watchpoint>,
+    next_watchpoint_id: usize,
+    stop_reason: Option<StopReason>,
+    history: VecDeque<HistoryEntry>,
+    history_limit: usize,
+    history_writes: Vec<(u16, u16)>,
+    // Message of the interrupt taken during the current tick
+    interrupt_taken: Option<u16>,
+    tracer: Option<TraceWriter>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    // Outcome of the conditional instruction executed during the current tick
+    condition: Option<bool>,
+    // Sorted by address, and never overlapping
+    mappings: Vec<Mapping>,
+    rom_writes: RomWritePolicy,
+    reset_vector: u16,
+    queue_overflow: FaultPolicy,
+    reserved_opcode: FaultPolicy,
+    literal_write: FaultPolicy,
+    // Set while halted by a fault or by HLT
+    halted: Option<StopReason>,
+    halt_on_zero: bool,
+    on_fire: bool,
+    fire_rng: u32,
+    // Address of the instruction being executed
+    instruction_pc: u16,
+    // Decode cache, indexed by address. Empty until the first instruction is executed.
+    decoded: Vec<Decoded>,
+    decode_cache: bool,
+    isa: Isa,
+    device_batch: u64,
+    // Cycles that devices have not been run for yet
+    pending_device_cycles: u64,
+    extensions: Arc<Extensions>,
+    // Where OUT and OUV print to
+    output: Box<Write + Send>,
+    // Message of the interrupt sent when devices are attached or detached
+    hotplug_interrupt: Option<u16>,
+    // Replaced rather than changed, so that it can be iterated over while devices are added
+    pub devices: Arc<Vec<DeviceRef>>,
 }
 
 impl DCPU {
     pub fn new() -> DCPU {
         DCPU {
-            terminate: false,
             reg: [0; 8],
             mem: [0; MEMORY_SIZE],
             pc: 0,
@@ -72,65 +446,703 @@ impl DCPU {
             cycle: 0,
             overshot_cycles: 0,
             inside_run: false,
-            devices: Rc::new(Vec::new()),
+            breakpoints: HashSet::new(),
+            resume_breakpoint: None,
+            watchpoints: Vec::new(),
+            next_watchpoint_id: 0,
+            stop_reason: None,
+            history: VecDeque::new(),
+            history_limit: 0,
+            history_writes: Vec::new(),
+            interrupt_taken: None,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            condition: None,
+            mappings: Vec::new(),
+            rom_writes: RomWritePolicy::Ignore,
+            reset_vector: 0,
+            queue_overflow: FaultPolicy::Halt,
+            reserved_opcode: FaultPolicy::Ignore,
+            literal_write: FaultPolicy::Ignore,
+            halted: None,
+            halt_on_zero: false,
+            on_fire: false,
+            fire_rng: FIRE_SEED,
+            instruction_pc: 0,
+            decoded: Vec::new(),
+            decode_cache: true,
+            isa: Isa::V1_7,
+            device_batch: 1,
+            pending_device_cycles: 0,
+            extensions: Arc::new(Extensions::standard()),
+            output: Box::new(io::stdout()),
+            hotplug_interrupt: None,
+            devices: Arc::new(Vec::new()),
         }
     }
 
-    // Run multiple ticks until cycles have been met
-    // Resets cycle count, so that it won't overflow
-    pub fn run(&mut self, cycles: usize) {
+    // Run multiple ticks until cycles have been met, or until execution is stopped (see
+    // `StopReason`). Cycles that went beyond the budget are subtracted from the next call.
+    pub fn run(&mut self, cycles: usize) -> RunStatus {
+        let cycles = cycles as u64;
         self.inside_run = true;
-        if self.overshot_cycles > cycles as isize {
-            self.overshot_cycles -= cycles as isize;
-            return;
+        if self.overshot_cycles > cycles {
+            self.overshot_cycles -= cycles;
+            self.inside_run = false;
+            return RunStatus::CycleBudget;
         }
 
-        let end_cycle = ((self.cycle + cycles) as isize - self.overshot_cycles) as usize;
+        let end_cycle = self.cycle + cycles - self.overshot_cycles;
 
+        let mut reason = None;
         while self.cycle < end_cycle {
-            self.tick();
+            reason = self.tick().stop_reason();
+            if reason.is_some() {
+                break;
+            }
         }
-        self.overshot_cycles = self.cycle as isize - end_cycle as isize;
-
-        // Pretend cycle is u16 and let it overflow safely
-        while self.cycle > 0xffff {
-            self.cycle -= 0xffff;
+        // Let devices catch up, so that they are current between calls
+        if reason.is_none() && self.pending_device_cycles > 0 {
+            self.run_devices();
+            reason = self.stop_reason.take();
         }
+        // If we stopped early, the remaining cycles are forfeited
+        self.overshot_cycles = if reason.is_some() {
+            0
+        } else {
+            self.cycle - end_cycle
+        };
         self.inside_run = false;
+        match reason {
+            Some(reason) => RunStatus::Stopped(reason),
+            None => RunStatus::CycleBudget,
+        }
+    }
+
+    /// Runs devices (`Device::run`) once every `cycles` cycles rather than after every instruction,
+    /// which is the default (1). Larger batches make emulation faster, but device timing and
+    /// interrupts from devices get coarser. Devices always catch up before `HWI` and before `run`
+    /// returns.
+    pub fn set_device_batch(&mut self, cycles: u64) {
+        self.device_batch = cycles.max(1);
+    }
+
+    pub fn device_batch(&self) -> u64 {
+        self.device_batch
+    }
+
+    /// Selects the instruction set that programs are written for (1.7 by default).
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+        self.decoded = Vec::new();
+    }
+
+    /// Turns the decoded instruction cache on or off (it is on by default). Only useful for
+    /// measuring how much it helps, since the result is the same either way.
+    pub fn set_decode_cache(&mut self, on: bool) {
+        self.decode_cache = on;
+        if !on {
+            self.decoded = Vec::new();
+        }
+    }
+
+    pub fn decode_cache(&self) -> bool {
+        self.decode_cache
+    }
+
+    // Looks up the instruction at `address` in the decode cache
+    fn decode(&mut self, address: u16) -> Decoded {
+        let word = self.mem[address as usize];
+        if !self.decode_cache {
+            return Decoded::new(word, self.isa);
+        }
+        if self.decoded.is_empty() {
+            self.decoded = vec![Decoded::new(0, self.isa); MEMORY_SIZE];
+        }
+        let entry = &mut self.decoded[address as usize];
+        if entry.word != word {
+            *entry = Decoded::new(word, self.isa);
+        }
+        *entry
+    }
+
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Sets the extension instructions (`Extensions::standard` by default). Share the registry
+    /// with `assembler::PCPU::set_extensions`, so that both know the same instructions.
+    pub fn set_extensions(&mut self, extensions: Arc<Extensions>) {
+        self.extensions = extensions;
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Sets where extension instructions such as `OUT` print to (stdout by default).
+    pub fn set_output(&mut self, output: Box<Write + Send>) {
+        self.output = output;
+    }
+
+    pub fn output(&mut self) -> &mut Write {
+        &mut *self.output
+    }
+
+    fn run_devices(&mut self) {
+        let cycles = self.pending_device_cycles as usize;
+        self.pending_device_cycles = 0;
+        if self.devices.is_empty() {
+            return;
+        }
+        let devices = self.devices.clone();
+        for dref in devices.iter() {
+            let mut device = dref.lock().unwrap();
+            device.run(self, cycles);
+        }
     }
 
-    /// Get cycle count
-    pub fn cycle(&self) -> usize {
+    /// Total number of cycles since the DCPU was created or reset. This never wraps, so devices
+    /// can use it as an absolute timeline. Inside `Device::run`, it includes the instruction that
+    /// was just executed.
+    pub fn cycle(&self) -> u64 {
         self.cycle
     }
 
+    /// Emulated time since the DCPU was created or reset, based on `CYCLE_HZ`.
+    pub fn elapsed(&self) -> Duration {
+        cycles_to_duration(self.cycle)
+    }
+
     /// Halts the DCPU for a specified number of cycles.
     pub fn halt(&mut self, cycles: usize) -> () {
-        if self.inside_run {
-            self.cycle += cycles;
-        } else {
-            self.overshot_cycles += cycles as isize;
+        self.cycle += cycles as u64;
+        if !self.inside_run {
+            // Taken from the budget of the next `run`
+            self.overshot_cycles += cycles as u64;
+        }
+    }
+
+    /// Stops execution before the instruction at `address` is executed.
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns `false` if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Sorted list of breakpoint addresses.
+    pub fn breakpoints(&self) -> Vec<u16> {
+        let mut v: Vec<u16> = self.breakpoints.iter().cloned().collect();
+        v.sort();
+        v
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Stops execution after an instruction accesses memory in the inclusive range `from..to`.
+    /// Only data accesses count, so fetching instructions and their next-word operands does not
+    /// trigger a watchpoint. Returns an ID that is reported back in `StopReason::Watchpoint`.
+    pub fn add_watchpoint(&mut self, from: u16, to: u16, kind: WatchKind) -> usize {
+        let id = self.next_watchpoint_id;
+        self.next_watchpoint_id += 1;
+        self.watchpoints.push(Watchpoint { id: id, from: from, to: to, kind: kind });
+        id
+    }
+
+    /// Returns `false` if there was no watchpoint with this ID.
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.id != id);
+        self.watchpoints.len() != len
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    fn check_watchpoints(&mut self, address: u16, access: MemoryAccess) {
+        if self.stop_reason.is_some() {
+            return;
+        }
+        for w in self.watchpoints.iter() {
+            let matches_kind = match (w.kind, access) {
+                (WatchKind::ReadWrite, _) => true,
+                (WatchKind::Read, MemoryAccess::Read) => true,
+                (WatchKind::Write, MemoryAccess::Write) => true,
+                _ => false,
+            };
+            if matches_kind && address >= w.from && address <= w.to {
+                self.stop_reason = Some(StopReason::Watchpoint {
+                    id: w.id,
+                    address: address,
+                    access: access,
+                });
+                break;
+            }
+        }
+    }
+
+    /// Maps the inclusive range `from..to` of the memory bus to a device, so that data reads and
+    /// writes made by the DCPU in that range go to `Device::memory_read` and
+    /// `Device::memory_write` instead of RAM. Instructions are always fetched from RAM.
+    pub fn map_device(&mut self, device: usize, from: u16, to: u16) -> ::std::result::Result<(), MemoryMapError> {
+        if device >= self.devices.len() {
+            return Err(MemoryMapError::NoSuchDevice(device));
+        }
+        self.map(from, to, MemoryRegion::Device(device))
+    }
+
+    fn map(&mut self, from: u16, to: u16, region: MemoryRegion) -> ::std::result::Result<(), MemoryMapError> {
+        if to < from {
+            return Err(MemoryMapError::InvalidRange);
+        }
+        if let Some(m) = self.mappings.iter().find(|m| from <= m.to && to >= m.from) {
+            return Err(MemoryMapError::Overlap { from: m.from, to: m.to });
+        }
+        self.mappings.push(Mapping { from: from, to: to, region: region });
+        self.mappings.sort_by_key(|m| m.from);
+        Ok(())
+    }
+
+    /// Copies a firmware image into memory at `address`, and maps it as ROM so that instructions
+    /// can not change it. Devices and embedders can still change it through `mem`.
+    pub fn load_rom(&mut self, address: u16, image: &[u16]) -> ::std::result::Result<(), MemoryMapError> {
+        if image.is_empty() || address as usize + image.len() > MEMORY_SIZE {
+            return Err(MemoryMapError::InvalidRange);
+        }
+        let end = address as usize + image.len();
+        self.map(address, (end - 1) as u16, MemoryRegion::Rom)?;
+        self.mem[address as usize..end].copy_from_slice(image);
+        Ok(())
+    }
+
+    pub fn set_rom_write_policy(&mut self, policy: RomWritePolicy) {
+        self.rom_writes = policy;
+    }
+
+    pub fn rom_write_policy(&self) -> RomWritePolicy {
+        self.rom_writes
+    }
+
+    /// Sets where the DCPU starts executing after a reset, for example the start of a boot ROM.
+    /// Also moves PC there.
+    pub fn set_reset_vector(&mut self, address: u16) {
+        self.reset_vector = address;
+        self.pc = address;
+    }
+
+    pub fn reset_vector(&self) -> u16 {
+        self.reset_vector
+    }
+
+    /// Sets what happens on faults of the given kind. By default, an interrupt queue overflow
+    /// halts, and reserved opcodes and literal writes are ignored.
+    pub fn set_fault_policy(&mut self, kind: FaultKind, policy: FaultPolicy) {
+        match kind {
+            FaultKind::InterruptQueueOverflow => self.queue_overflow = policy,
+            FaultKind::ReservedOpcode => self.reserved_opcode = policy,
+            FaultKind::LiteralWrite => self.literal_write = policy,
+        }
+    }
+
+    pub fn fault_policy(&self, kind: FaultKind) -> FaultPolicy {
+        match kind {
+            FaultKind::InterruptQueueOverflow => self.queue_overflow,
+            FaultKind::ReservedOpcode => self.reserved_opcode,
+            FaultKind::LiteralWrite => self.literal_write,
+        }
+    }
+
+    /// The fault that halted the DCPU, if any.
+    pub fn fault(&self) -> Option<CpuFault> {
+        match self.halted {
+            Some(StopReason::Fault(fault)) => Some(fault),
+            _ => None,
+        }
+    }
+
+    pub fn is_on_fire(&self) -> bool {
+        self.on_fire
+    }
+
+    /// Resumes after a halting fault, and puts out any fire.
+    pub fn clear_fault(&mut self) {
+        if self.fault().is_some() {
+            self.halted = None;
+        }
+        self.on_fire = false;
+    }
+
+    /// Why the DCPU is halted, if it is. A halted DCPU does not execute anything, and `tick`
+    /// returns the reason again.
+    pub fn halted(&self) -> Option<StopReason> {
+        self.halted
+    }
+
+    /// Resumes after `HLT`, a 0x0000 word or a halting fault.
+    pub fn resume(&mut self) {
+        self.halted = None;
+    }
+
+    /// Makes the DCPU halt when it executes a 0x0000 word, which is convenient for programs that
+    /// end with `DAT 0`. Off by default, in which case 0x0000 is a reserved opcode.
+    pub fn set_halt_on_zero(&mut self, halt: bool) {
+        self.halt_on_zero = halt;
+    }
+
+    pub fn halt_on_zero(&self) -> bool {
+        self.halt_on_zero
+    }
+
+    /// Makes `run` and `tick` stop with `StopReason::DeviceStop` after the current instruction.
+    /// Meant for devices, for example to end a test run.
+    pub fn request_stop(&mut self, code: u16) {
+        if self.stop_reason.is_none() {
+            self.stop_reason = Some(StopReason::DeviceStop(code));
+        }
+    }
+
+    /// Halts the DCPU like `HLT` does, with `StopReason::Halt(status)`. Meant for extension
+    /// instructions.
+    pub fn halt_with_status(&mut self, status: u16) {
+        self.enter_halt(StopReason::Halt(status));
+    }
+
+    fn enter_halt(&mut self, reason: StopReason) {
+        if self.halted.is_none() {
+            self.halted = Some(reason);
+        }
+        if self.stop_reason.is_none() {
+            self.stop_reason = Some(reason);
+        }
+    }
+
+    /// Seeds the random number generator that decides which memory a fire damages.
+    pub fn set_fire_seed(&mut self, seed: u32) {
+        // Xorshift gets stuck at zero
+        self.fire_rng = if seed == 0 { FIRE_SEED } else { seed };
+    }
+
+    fn raise_fault(&mut self, fault: CpuFault) {
+        match self.fault_policy(fault.kind()) {
+            FaultPolicy::Ignore => {},
+            FaultPolicy::Log => {
+                eprintln!("DCPU fault: {}", fault);
+            },
+            FaultPolicy::Halt => {
+                self.enter_halt(StopReason::Fault(fault));
+            },
+            FaultPolicy::Fire => {
+                self.on_fire = true;
+            },
+        }
+    }
+
+    // Flips random bits of a random memory word
+    fn burn(&mut self) {
+        let mut x = self.fire_rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.fire_rng = x;
+        let address = (x >> 16) as u16;
+        let damage = (x as u16) | 1;
+        if self.recording() {
+            self.history_writes.push((address, self.mem[address as usize]));
+        }
+        self.mem[address as usize] ^= damage;
+    }
+
+    /// Removes the mapping that starts at `from`. Returns `false` if there was none.
+    pub fn unmap(&mut self, from: u16) -> bool {
+        let len = self.mappings.len();
+        self.mappings.retain(|m| m.from != from);
+        self.mappings.len() != len
+    }
+
+    /// Mapped ranges as `(from, to, region)`, sorted by address.
+    pub fn mappings(&self) -> Vec<(u16, u16, MemoryRegion)> {
+        self.mappings.iter().map(|m| (m.from, m.to, m.region)).collect()
+    }
+
+    /// What `address` is mapped to, or `None` if it is plain RAM.
+    pub fn mapping_at(&self, address: u16) -> Option<MemoryRegion> {
+        self.mappings.iter().find(|m| address >= m.from && address <= m.to).map(|m| m.region)
+    }
+
+    /// Reads a word through the memory bus, the same way a DCPU instruction would (but without
+    /// triggering watchpoints). Use `mem` directly to access RAM.
+    pub fn read_memory(&mut self, address: u16) -> u16 {
+        if !self.mappings.is_empty() {
+            if let Some(MemoryRegion::Device(i)) = self.mapping_at(address) {
+                let devices = self.devices.clone();
+                let mut device = devices[i].lock().unwrap();
+                return device.memory_read(self, address);
+            }
+        }
+        self.mem[address as usize]
+    }
+
+    /// Writes a word through the memory bus (see `read_memory`).
+    pub fn write_memory(&mut self, address: u16, value: u16) {
+        if !self.mappings.is_empty() {
+            match self.mapping_at(address) {
+                Some(MemoryRegion::Device(i)) => {
+                    let devices = self.devices.clone();
+                    let mut device = devices[i].lock().unwrap();
+                    device.memory_write(self, address, value);
+                    return;
+                },
+                Some(MemoryRegion::Rom) => return,
+                None => {},
+            }
+        }
+        self.mem[address as usize] = value;
+    }
+
+    // Data reads and writes go through these, so that watchpoints can see them
+    fn read_mem(&mut self, address: u16) -> u16 {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, MemoryAccess::Read);
         }
+        self.read_memory(address)
     }
 
-    fn reset(&mut self) {
-        self.terminate = false;
+    fn write_mem(&mut self, address: u16, value: u16) {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, MemoryAccess::Write);
+        }
+        if self.recording() {
+            self.history_writes.push((address, self.mem[address as usize]));
+        }
+        if self.rom_writes == RomWritePolicy::Stop && self.stop_reason.is_none() &&
+           self.mapping_at(address) == Some(MemoryRegion::Rom) {
+            self.stop_reason = Some(StopReason::RomWrite(address));
+        }
+        self.write_memory(address, value);
+    }
+
+    /// Presses the reset button: registers and interrupts go back to how they were at power-on,
+    /// and every device is reset (see `Device::reset`). Devices stay attached, and memory mappings
+    /// stay in place. The cycle count keeps running, so it never goes backwards. With
+    /// `keep_memory`, RAM is left alone, otherwise it is cleared (apart from ROM). Breakpoints,
+    /// watchpoints and recording tools are kept, but the history is cleared, since it can not be
+    /// undone past a reset.
+    pub fn soft_reset(&mut self, keep_memory: bool) {
+        if !keep_memory {
+            for address in 0..MEMORY_SIZE {
+                if self.mapping_at(address as u16) != Some(MemoryRegion::Rom) {
+                    self.mem[address] = 0;
+                }
+            }
+        }
+        self.reset_state();
+        let devices = self.devices.clone();
+        for dref in devices.iter() {
+            dref.lock().unwrap().reset();
+        }
+    }
+
+    /// Back to how `new` left things, apart from settings: memory is cleared, the cycle count
+    /// goes back to 0, and every mapping (including ROM) and device is removed.
+    pub fn hard_reset(&mut self) {
         for i in 0..MEMORY_SIZE {
             self.mem[i] = 0;
         }
+        self.reset_state();
+        self.cycle = 0;
+        self.mappings.clear();
+        self.devices = Arc::new(Vec::new());
+    }
+
+    fn reset_state(&mut self) {
         for i in 0..8 {
             self.reg[i] = 0;
         }
-        self.pc = 0;
+        self.pc = self.reset_vector;
         self.sp = 0;
         self.ex = 0;
         self.ia = 0;
-        self.cycle = 0;
         self.interrupt_queue = Vec::new();
         self.interrupt_queueing = false;
         self.overshot_cycles = 0;
+        self.pending_device_cycles = 0;
+        self.halted = None;
+        self.on_fire = false;
         self.skip_next = false;
-        self.devices = Rc::new(Vec::new());
+        self.condition = None;
+        self.stop_reason = None;
+        self.resume_breakpoint = None;
+        self.history.clear();
+    }
+
+    /// Keeps the last `instructions` ticks, so that they can be undone with `step_back`. Setting
+    /// it to 0 turns recording off, which is the default.
+    ///
+    /// Only the DCPU itself is rewound. Devices keep their current state, and memory that devices
+    /// write to directly (such as the floppy drive's DMA) is not restored.
+    pub fn set_history_limit(&mut self, instructions: usize) {
+        self.history_limit = instructions;
+        while self.history.len() > instructions {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn history_limit(&self) -> usize {
+        self.history_limit
+    }
+
+    /// Number of ticks that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Starts writing a trace record for every tick to `writer` (see the `trace` module). Any
+    /// trace already in progress is stopped first. The trace records the current `isa`.
+    pub fn start_trace(&mut self, writer: Box<Write + Send>) -> Result<()> {
+        self.stop_trace()?;
+        self.tracer = Some(TraceWriter::new(writer, self.isa)?);
+        Ok(())
+    }
+
+    /// Stops tracing and flushes the trace. Returns any error that happened while writing it.
+    pub fn stop_trace(&mut self) -> Result<()> {
+        match self.tracer.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    /// Starts collecting cycles and hit counts for every executed address (see the `profiler`
+    /// module). Any profile already in progress is discarded.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler::new(self.pc));
+    }
+
+    /// Stops profiling and returns the collected profile.
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// The profile collected so far, if profiling.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Starts recording which instructions are executed and which way conditional instructions go
+    /// (see the `coverage` module). Any coverage already in progress is discarded.
+    pub fn start_coverage(&mut self) {
+        self.coverage = Some(Coverage::for_isa(self.isa));
+    }
+
+    /// Stops recording coverage and returns what was recorded.
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// The coverage recorded so far, if recording.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Message of the interrupt that was taken during the last tick, if any.
+    pub fn interrupt_taken(&self) -> Option<u16> {
+        self.interrupt_taken
+    }
+
+    // Whether memory writes need to be recorded during a tick
+    fn recording(&self) -> bool {
+        self.history_limit > 0 || self.tracer.is_some()
+    }
+
+    fn trace_registers(&self) -> [u16; trace::NUM_REGISTERS] {
+        let r = &self.reg;
+        [r[0], r[1], r[2], r[3], r[4], r[5], r[6], r[7], self.pc, self.sp, self.ex, self.ia]
+    }
+
+    fn instruction_length(&self, pc: u16) -> usize {
+        1 + Decoded::new(self.mem[pc as usize], self.isa).next_words as usize
+    }
+
+    fn undo(&mut self) -> Option<HistoryEntry> {
+        let entry = match self.history.pop_back() {
+            Some(e) => e,
+            None => return None,
+        };
+        for &(address, value) in entry.writes.iter().rev() {
+            self.mem[address as usize] = value;
+        }
+        self.reg = entry.reg;
+        self.pc = entry.pc;
+        self.sp = entry.sp;
+        self.ex = entry.ex;
+        self.ia = entry.ia;
+        self.interrupt_queueing = entry.interrupt_queueing;
+        self.interrupt_queue = entry.interrupt_queue.clone();
+        self.skip_next = entry.skip_next;
+        self.halted = entry.halted;
+        self.on_fire = entry.on_fire;
+        self.cycle = self.cycle.saturating_sub(entry.cycles);
+        Some(entry)
+    }
+
+    /// Undoes up to `n` ticks. Returns how many were undone, which is less than `n` if the
+    /// history ran out.
+    pub fn step_back(&mut self, n: usize) -> usize {
+        let mut undone = 0;
+        while undone < n && self.undo().is_some() {
+            undone += 1;
+        }
+        undone
+    }
+
+    /// Undoes ticks until PC lands on a breakpoint, or until an undone instruction wrote to memory
+    /// covered by a write watchpoint. Returns `None` if the start of the history was reached.
+    /// Read watchpoints are not triggered, since reads are not recorded.
+    pub fn run_back(&mut self) -> Option<StopReason> {
+        while let Some(entry) = self.undo() {
+            for &(address, _) in entry.writes.iter() {
+                self.check_watchpoints(address, MemoryAccess::Write);
+            }
+            if self.stop_reason.is_none() && self.breakpoints.contains(&self.pc) {
+                self.stop_reason = Some(StopReason::Breakpoint(self.pc));
+                self.resume_breakpoint = Some(self.pc);
+            }
+            if self.stop_reason.is_some() {
+                return self.stop_reason.take();
+            }
+        }
+        None
+    }
+
+    /// Rewinds to just before the most recent recorded instruction that wrote to `address`, so
+    /// that PC points at it. Nothing is undone and `false` is returned if no such write is in the
+    /// history.
+    pub fn run_back_to_write(&mut self, address: u16) -> bool {
+        let found = self.history.iter().rev().position(|e| {
+            e.writes.iter().any(|&(a, _)| a == address)
+        });
+        match found {
+            Some(i) => {
+                self.step_back(i + 1);
+                true
+            },
+            None => false,
+        }
     }
 
     fn pcplus(&mut self, movepc: bool) -> u16 {
@@ -147,25 +1159,27 @@ impl DCPU {
             0x08 ... 0x0f => {
                 self.cycle += 1;
                 let pos = self.reg[(identifier - 0x08) as usize];
-                self.mem[pos as usize] = value;
+                self.write_mem(pos, value);
             },
             0x10 ... 0x17 => {
                 self.cycle += 1;
                 let pos = self.reg[(identifier - 0x10) as usize];
                 let offset = self.mem[self.pcplus(true) as usize];
-                self.mem[pos.wrapping_add(offset) as usize] = value;
+                self.write_mem(pos.wrapping_add(offset), value);
             },
             0x18 => {
                 self.sp = self.sp.wrapping_sub(1);
-                self.mem[self.sp as usize] = value;
+                let sp = self.sp;
+                self.write_mem(sp, value);
             },
             0x19 => {
-                self.mem[self.sp as usize] = value;
+                let sp = self.sp;
+                self.write_mem(sp, value);
             },
             0x1a => {
                 self.cycle += 1;
                 let pos = self.sp.wrapping_add(self.mem[self.pcplus(true) as usize]);
-                self.mem[pos as usize] = value;
+                self.write_mem(pos, value);
             },
             0x1b => { self.sp = value; },
             0x1c => { self.pc = value; },
@@ -173,10 +1187,19 @@ impl DCPU {
             0x1e => {
                 self.cycle += 1;
                 let pos = self.mem[self.pcplus(true) as usize];
-                self.mem[pos as usize] = value;
+                self.write_mem(pos, value);
             }
-            // Instructions 0x1f - 0x3f are not possible (silently ignore)
-            _ => {},
+            0x1f => {
+                // The literal is still the next word, so it needs to be skipped
+                self.cycle += 1;
+                self.pcplus(true);
+                let pc = self.instruction_pc;
+                self.raise_fault(CpuFault::LiteralWrite { pc: pc });
+            },
+            _ => {
+                let pc = self.instruction_pc;
+                self.raise_fault(CpuFault::LiteralWrite { pc: pc });
+            },
         }
     }
 
@@ -185,29 +1208,33 @@ impl DCPU {
             0x00 ... 0x07  => { self.reg[identifier as usize] },
             0x08 ... 0x0f => {
                 let pos = self.reg[(identifier - 0x08) as usize];
-                self.mem[pos as usize]
+                self.read_mem(pos)
             },
             0x10 ... 0x17 => {
                 self.cycle += 1;
                 let pos = self.reg[(identifier - 0x10) as usize];
                 let offset = self.mem[self.pcplus(movepc) as usize];
-                self.mem[pos.wrapping_add(offset) as usize]
+                self.read_mem(pos.wrapping_add(offset))
             },
             0x18 => {
                 if is_a {
                     let oldsp = self.sp;
                     self.sp = self.sp.wrapping_add(1);
-                    self.mem[oldsp as usize]
+                    self.read_mem(oldsp)
                 } else {
                     self.sp = self.sp.wrapping_sub(1);
-                    self.mem[self.sp as usize]
+                    let sp = self.sp;
+                    self.read_mem(sp)
                 }
             },
-            0x19 => { self.mem[self.sp as usize] },
+            0x19 => {
+                let sp = self.sp;
+                self.read_mem(sp)
+            },
             0x1a => {
                 self.cycle += 1;
                 let pos = self.sp.wrapping_add(self.mem[self.pcplus(movepc) as usize]);
-                self.mem[pos as usize]
+                self.read_mem(pos)
             },
             0x1b => { self.sp },
             0x1c => { self.pc },
@@ -215,7 +1242,7 @@ impl DCPU {
             0x1e => {
                 self.cycle += 1;
                 let pos = self.mem[self.pcplus(movepc) as usize];
-                self.mem[pos as usize]
+                self.read_mem(pos)
             },
             0x1f => {
                 self.cycle += 1;
@@ -236,49 +1263,226 @@ impl DCPU {
         v as i16
     }
 
-    fn takes_next(&self, id: usize) -> bool {
-        id >= 0x10 && id <= 0x17 || id == 0x1a || id == 0x1e || id == 0x1f
-    }
-
     fn process_conditional(&mut self, truth: bool) {
+        self.condition = Some(truth);
         if !truth {
             self.skip_next = true;
             self.cycle += 1;
         }
     }
 
-    /// Connect device
-    pub fn add_device(&mut self, device: Box<Device>) -> () {
-        if let Some(devices) = Rc::get_mut(&mut self.devices) {
-            devices.push(RefCell::new(device));
+    /// Attaches a device, and returns its index (as used by `HWQ` and `HWI`). This can be done at
+    /// any time, including from inside device callbacks. Programs see the new device through `HWN`
+    /// and the hot-plug interrupt (see `set_hotplug_interrupt`).
+    pub fn add_device(&mut self, device: Box<Device>) -> ::std::result::Result<usize, DeviceError> {
+        if self.devices.len() >= MAX_DEVICES {
+            return Err(DeviceError::TooManyDevices);
         }
+        let mut devices = (*self.devices).clone();
+        devices.push(Arc::new(Mutex::new(device)));
+        self.devices = Arc::new(devices);
+        self.hotplug();
+        Ok(self.devices.len() - 1)
     }
 
-    /// Queues up interrupt. Can be used from hardware.
-    pub fn interrupt(&mut self, message: u16) -> () {
-        self.interrupt_queue.push(message);
+    /// Detaches the device at `index`, and hands it back. Devices after it move down one index.
+    /// Mapped devices (see `map_device`) need to be unmapped first.
+    pub fn remove_device(&mut self, index: usize) -> ::std::result::Result<DeviceRef, DeviceError> {
+        if index >= self.devices.len() {
+            return Err(DeviceError::NoSuchDevice(index));
+        }
+        if self.mappings.iter().any(|m| m.region == MemoryRegion::Device(index)) {
+            return Err(DeviceError::Mapped(index));
+        }
+        for m in self.mappings.iter_mut() {
+            if let MemoryRegion::Device(ref mut i) = m.region {
+                if *i > index {
+                    *i -= 1;
+                }
+            }
+        }
+        let mut devices = (*self.devices).clone();
+        let device = devices.remove(index);
+        self.devices = Arc::new(devices);
+        self.hotplug();
+        Ok(device)
     }
 
-    pub fn tick(&mut self) {
-        let word = self.mem[self.pcplus(true) as usize] as usize;
-        let opcode = word & 0x1f;
-        let id_b = (word >> 5) & 0x1f;
-        let id_a = (word >> 10) & 0x3f;
-        let old_cycle = self.cycle;
+    /// Identification of every attached device, in `HWQ` order. Devices are looked at without
+    /// waiting for their locks, so that devices can call this from their callbacks: a device that
+    /// is locked (such as the one making the call) is `None`.
+    pub fn device_info(&self) -> Vec<Option<DeviceInfo>> {
+        self.devices.iter().map(|dref| dref.try_lock().ok().map(|device| device.info())).collect()
+    }
 
-        if self.skip_next {
-            if self.takes_next(id_a) {
-                self.pcplus(true);
+    /// Index of the first attached device of type `T`. Like in `device_info`, locked devices are
+    /// skipped.
+    pub fn find_device<T: Device + 'static>(&self) -> Option<usize> {
+        self.devices.iter().position(|dref| match dref.try_lock() {
+            Ok(device) => device.as_any().is::<T>(),
+            Err(_) => false,
+        })
+    }
+
+    /// Index of the first attached device with this hardware ID (e.g. 0x7349f615 for a LEM1802).
+    /// Like in `device_info`, locked devices are skipped.
+    pub fn find_device_by_id(&self, hardware_id: u32) -> Option<usize> {
+        self.devices.iter().position(|dref| match dref.try_lock() {
+            Ok(device) => device.info().hardware_id == hardware_id,
+            Err(_) => false,
+        })
+    }
+
+    /// Calls `f` with the first attached device of type `T` (found with `find_device`), and
+    /// returns what it returns, or `None` if there is no such device.
+    pub fn with_device<T, R, F>(&mut self, f: F) -> Option<R>
+        where T: Device + 'static, F: FnOnce(&mut T, &mut DCPU) -> R
+    {
+        let dref = match self.find_device::<T>() {
+            Some(index) => self.devices[index].clone(),
+            None => return None,
+        };
+        let mut device = dref.lock().unwrap();
+        let device = device.as_any_mut().downcast_mut::<T>().unwrap();
+        Some(f(device, self))
+    }
+
+    /// Makes the DCPU interrupt itself with `message` whenever a device is attached or detached,
+    /// so that programs know to enumerate devices again. Off (`None`) by default.
+    pub fn set_hotplug_interrupt(&mut self, message: Option<u16>) {
+        self.hotplug_interrupt = message;
+    }
+
+    pub fn hotplug_interrupt(&self) -> Option<u16> {
+        self.hotplug_interrupt
+    }
+
+    fn hotplug(&mut self) {
+        if let Some(message) = self.hotplug_interrupt {
+            self.interrupt(message);
+        }
+    }
+
+    /// Queues up interrupt. Can be used from hardware. If the queue is full, the interrupt is
+    /// dropped and `CpuFault::InterruptQueueOverflow` is raised.
+    pub fn interrupt(&mut self, message: u16) -> () {
+        if self.interrupt_queue.len() >= MAX_QUEUED_INTERRUPTS {
+            self.raise_fault(CpuFault::InterruptQueueOverflow(message));
+        } else {
+            self.interrupt_queue.push(message);
+        }
+    }
+
+    /// Executes a single instruction (skipped instructions are included).
+    ///
+    /// Returns `RunStatus::Stopped` if a watchpoint was triggered, if PC has landed on a
+    /// breakpoint, or if the DCPU is halted. Nothing is executed if the DCPU is halted, or if PC
+    /// is on a breakpoint, unless execution stopped on that breakpoint last time (so that it can
+    /// be resumed).
+    pub fn tick(&mut self) -> RunStatus {
+        if let Some(reason) = self.halted {
+            self.stop_reason = None;
+            return RunStatus::Stopped(reason);
+        }
+        let resuming = self.resume_breakpoint.take() == Some(self.pc);
+        if !resuming && self.breakpoints.contains(&self.pc) {
+            self.resume_breakpoint = Some(self.pc);
+            return RunStatus::Stopped(StopReason::Breakpoint(self.pc));
+        }
+        self.interrupt_taken = None;
+        self.condition = None;
+        let (pc, cycle, skipping) = (self.pc, self.cycle, self.skip_next);
+        if self.recording() {
+            self.recorded_tick();
+        } else {
+            self.execute();
+        }
+        if let Some(mut profiler) = self.profiler.take() {
+            if skipping {
+                profiler.record(pc, self.cycle - cycle);
+            } else {
+                let word = self.mem[pc as usize];
+                profiler.record_instruction(self, pc, word, self.cycle - cycle);
             }
+            self.profiler = Some(profiler);
+        }
+        if !skipping {
+            if let Some(ref mut coverage) = self.coverage {
+                coverage.record(pc, self.condition);
+            }
+        }
+        if self.stop_reason.is_none() && self.breakpoints.contains(&self.pc) {
+            self.stop_reason = Some(StopReason::Breakpoint(self.pc));
+            self.resume_breakpoint = Some(self.pc);
+        }
+        match self.stop_reason.take() {
+            Some(reason) => RunStatus::Stopped(reason),
+            None => RunStatus::Running,
+        }
+    }
 
-            if opcode != 0 && self.takes_next(id_b) {
-                self.pcplus(true);
+    // Executes like `tick`, but keeps what is needed for the history and the trace
+    fn recorded_tick(&mut self) {
+        let mut entry = HistoryEntry {
+            reg: self.reg,
+            pc: self.pc,
+            sp: self.sp,
+            ex: self.ex,
+            ia: self.ia,
+            interrupt_queueing: self.interrupt_queueing,
+            interrupt_queue: self.interrupt_queue.clone(),
+            skip_next: self.skip_next,
+            halted: self.halted,
+            on_fire: self.on_fire,
+            cycles: self.cycle,
+            writes: Vec::new(),
+        };
+        let before = self.trace_registers();
+        let pc = self.pc;
+        let instruction: Vec<u16> = (0..self.instruction_length(pc))
+            .map(|i| self.mem[pc.wrapping_add(i as u16) as usize]).collect();
+
+        self.execute();
+        entry.cycles = self.cycle - entry.cycles;
+        entry.writes = self.history_writes.split_off(0);
+        let interrupts: Vec<u16> = self.interrupt_taken.into_iter().collect();
+
+        if let Some(mut tracer) = self.tracer.take() {
+            let after = self.trace_registers();
+            let writes: Vec<(u16, u16)> = entry.writes.iter()
+                .map(|&(address, _)| (address, self.mem[address as usize])).collect();
+            tracer.record(&before, &after, &instruction, &writes, &interrupts, entry.cycles);
+            self.tracer = Some(tracer);
+        }
+
+        if self.history_limit > 0 {
+            if self.history.len() >= self.history_limit {
+                self.history.pop_front();
             }
+            self.history.push_back(entry);
+        }
+    }
 
-            self.skip_next = opcode >= 0x10 && opcode <= 0x17;
+    fn execute(&mut self) {
+        self.instruction_pc = self.pc;
+        let decoded = self.decode(self.pc);
+        self.pcplus(true);
+        let word = decoded.word as usize;
+        let opcode = decoded.opcode as usize;
+        let id_b = decoded.id_b as usize;
+        let id_a = decoded.id_a as usize;
+        let old_cycle = self.cycle;
+
+        if self.skip_next {
+            self.pc = self.pc.wrapping_add(decoded.next_words as u16);
+
+            // Only 1.7 skips chained conditionals
+            self.skip_next = self.isa == Isa::V1_7 && opcode >= 0x10 && opcode <= 0x17;
             if self.skip_next {
                 self.cycle += 1;
             }
+        } else if self.isa == Isa::V1_1 {
+            self.execute_v1_1(decoded);
         } else {
             match opcode {
                 0 => {
@@ -508,50 +1712,61 @@ impl DCPU {
                     self.reg[REG_I] = v_i.wrapping_sub(1);
                     self.reg[REG_J] = v_j.wrapping_sub(1);
                 },
-                _ => {},
+                _ => {
+                    self.cycle += 1;
+                    let pc = self.instruction_pc;
+                    self.raise_fault(CpuFault::ReservedOpcode { pc: pc, word: word as u16 });
+                },
             }
         }
         if self.skip_next {
-            self.tick();
+            self.execute();
         } else {
             if !self.interrupt_queue.is_empty() && !self.interrupt_queueing {
                 let message = self.interrupt_queue.remove(0);
                 self.cycle += 4;
+                self.interrupt_taken = Some(message);
 
                 if self.ia != 0 {
                     self.interrupt_queueing = true;
+                    let (pc, a) = (self.pc, self.reg[REG_A]);
                     self.sp = self.sp.wrapping_sub(1);
-                    self.mem[self.sp as usize] = self.pc;
+                    let sp = self.sp;
+                    self.write_mem(sp, pc);
                     self.sp = self.sp.wrapping_sub(1);
-                    self.mem[self.sp as usize] = self.reg[REG_A];
+                    let sp = self.sp;
+                    self.write_mem(sp, a);
 
                     self.pc = self.ia;
                     self.reg[REG_A] = message;
                 }
             }
 
-            // Tick devices. This allows devices to act asynchronously.
-            // As an optimization, we might want to do this less frequently in the future.
-            let devices = self.devices.clone();
-            let delta_cycle = self.cycle - old_cycle;
-            for dref in devices.iter() {
-                let mut device = dref.borrow_mut();
-                device.run(self, delta_cycle);
+            if self.on_fire {
+                self.burn();
+            }
+
+            // Tick devices. This allows devices to act asynchronously. They are run in batches
+            // (see `set_device_batch`).
+            self.pending_device_cycles += self.cycle - old_cycle;
+            if self.pending_device_cycles >= self.device_batch {
+                self.run_devices();
             }
         }
     }
 
     fn process_special_opcode(&mut self, spec_opcode: usize, id_a: usize) {
         match spec_opcode {
-            0 => {
-                // Terminate if a 0x00 is processed as an instruction
-                self.terminate = true;
+            0 if self.halt_on_zero && id_a == 0 => {
+                let pc = self.instruction_pc;
+                self.enter_halt(StopReason::ZeroWord(pc));
             },
             JSR => {
                 self.cycle += 3;
                 self.sp = self.sp.wrapping_sub(1);
                 let new_pc = self.value(id_a, true, true);
-                self.mem[self.sp as usize] = self.pc;
+                let (sp, pc) = (self.sp, self.pc);
+                self.write_mem(sp, pc);
                 self.pc = new_pc;
             },
             INT => {
@@ -570,10 +1785,11 @@ impl DCPU {
             RFI => {
                 self.cycle += 3;
                 self.interrupt_queueing = false;
-                let stack_a = self.mem[self.sp as usize];
-                self.reg[REG_A] = stack_a;
+                let sp = self.sp;
+                self.reg[REG_A] = self.read_mem(sp);
                 self.sp = self.sp.wrapping_add(1);
-                self.pc = self.mem[self.sp as usize];
+                let sp = self.sp;
+                self.pc = self.read_mem(sp);
                 self.sp = self.sp.wrapping_add(1);
             },
             IAQ => {
@@ -589,53 +1805,228 @@ impl DCPU {
             HWQ => {
                 self.cycle += 4;
                 let device_id = self.value(id_a, true, true) as usize;
-                let (i1, i2, i3, i4, i5) = match self.devices.get(device_id) {
+                let (hardware_id, version, manufacturer_id) = match self.devices.get(device_id) {
                     Some(dref) => {
-                        let d = dref.borrow();
-                        (d.info_hardware_id_lower(),
-                         d.info_hardware_id_upper(),
-                         d.info_version(),
-                         d.info_manufacturer_id_lower(),
-                         d.info_manufacturer_id_upper())
+                        let info = dref.lock().unwrap().info();
+                        (info.hardware_id, info.version, info.manufacturer_id)
                     },
                     None => {
-                        (0, 0, 0, 0, 0)
+                        (0, 0, 0)
                     },
                 };
-                self.reg[REG_A] = i1;
-                self.reg[REG_B] = i2;
-                self.reg[REG_C] = i3;
-                self.reg[REG_X] = i4;
-                self.reg[REG_Y] = i5;
+                self.reg[REG_A] = hardware_id as u16;
+                self.reg[REG_B] = (hardware_id >> 16) as u16;
+                self.reg[REG_C] = version;
+                self.reg[REG_X] = manufacturer_id as u16;
+                self.reg[REG_Y] = (manufacturer_id >> 16) as u16;
             },
             HWI => {
                 self.cycle += 4;
                 let device_id = self.value(id_a, true, true) as usize;
                 if device_id < self.devices.len() {
+                    if self.pending_device_cycles > 0 {
+                        self.run_devices();
+                    }
                     let devices = self.devices.clone();
-                    let mut device = devices.get(device_id).unwrap().borrow_mut();
+                    let mut device = devices.get(device_id).unwrap().lock().unwrap();
                     device.process_interrupt(self);
                 }
             },
-            // Extensions
-            OUT => {
-                // Temporary printing
-                // OUT p  (prints memory address p as a null-terminated string)
-                let a = self.value(id_a, true, true);
-                for i in 0..MEMORY_SIZE {
-                    let c = self.mem[a.wrapping_add(i as u16) as usize];
-                    if c == 0 {
-                        break;
-                    } else {
-                        print!("{}", ((c & 0xff) as u8) as char);
-                    }
+            _ => {
+                let extensions = self.extensions.clone();
+                if let Some(extension) = extensions.by_opcode(spec_opcode) {
+                    let a = self.value(id_a, true, true);
+                    self.cycle += extension.cycles();
+                    extension.execute(self, a);
+                    return;
                 }
+                self.cycle += 1;
+                let (pc, word) = (self.instruction_pc, self.mem[self.instruction_pc as usize]);
+                self.raise_fault(CpuFault::ReservedOpcode { pc: pc, word: word });
+            },
+        }
+    }
+
+    // Executes a 1.1 instruction (see `Isa::V1_1`). Operands are resolved target first, since
+    // that is the order of their next words.
+    fn execute_v1_1(&mut self, decoded: Decoded) {
+        let (opcode, id_b, id_a) = (decoded.opcode as usize, decoded.id_b as usize, decoded.id_a as usize);
+        if opcode == 0 {
+            match id_b {
+                0 if self.halt_on_zero && id_a == 0 => {
+                    let pc = self.instruction_pc;
+                    self.enter_halt(StopReason::ZeroWord(pc));
+                },
+                v1_1::JSR => {
+                    self.cycle += 2;
+                    let target = self.operand_v1_1(id_a);
+                    let new_pc = self.read_operand(target);
+                    self.sp = self.sp.wrapping_sub(1);
+                    let (sp, pc) = (self.sp, self.pc);
+                    self.write_mem(sp, pc);
+                    self.pc = new_pc;
+                },
+                _ => {
+                    let extensions = self.extensions.clone();
+                    if let Some(extension) = extensions.by_opcode(id_b) {
+                        let operand = self.operand_v1_1(id_a);
+                        let a = self.read_operand(operand);
+                        self.cycle += extension.cycles();
+                        extension.execute(self, a);
+                        return;
+                    }
+                    self.cycle += 1;
+                    let pc = self.instruction_pc;
+                    self.raise_fault(CpuFault::ReservedOpcode { pc: pc, word: decoded.word });
+                },
             }
-            OUV => {
-                let a = self.value(id_a, true, true);
-                println!("{}", a);
+            return;
+        }
+
+        let target = self.operand_v1_1(id_b);
+        let source = self.operand_v1_1(id_a);
+        let b = self.read_operand(source);
+        if opcode == v1_1::SET {
+            self.cycle += 1;
+            self.write_operand(target, b);
+            return;
+        }
+        let a = self.read_operand(target);
+        match opcode {
+            v1_1::ADD => {
+                self.cycle += 2;
+                let v = a as u32 + b as u32;
+                self.ex = (v >> 16) as u16;
+                self.write_operand(target, v as u16);
+            },
+            v1_1::SUB => {
+                self.cycle += 2;
+                self.ex = if b > a { 0xffff } else { 0 };
+                self.write_operand(target, a.wrapping_sub(b));
+            },
+            v1_1::MUL => {
+                self.cycle += 2;
+                let v = a as u32 * b as u32;
+                self.ex = (v >> 16) as u16;
+                self.write_operand(target, v as u16);
+            },
+            v1_1::DIV => {
+                self.cycle += 3;
+                let v = if b == 0 {
+                    self.ex = 0;
+                    0
+                } else {
+                    self.ex = (((a as u32) << 16) / b as u32) as u16;
+                    a / b
+                };
+                self.write_operand(target, v);
+            },
+            v1_1::MOD => {
+                self.cycle += 3;
+                let v = if b == 0 { 0 } else { a % b };
+                self.write_operand(target, v);
+            },
+            v1_1::SHL => {
+                self.cycle += 2;
+                let v = if b < 32 { (a as u64) << b } else { 0 };
+                self.ex = (v >> 16) as u16;
+                self.write_operand(target, v as u16);
+            },
+            v1_1::SHR => {
+                self.cycle += 2;
+                let v = if b < 32 { ((a as u64) << 16) >> b } else { 0 };
+                self.ex = v as u16;
+                self.write_operand(target, (v >> 16) as u16);
+            },
+            v1_1::AND => {
+                self.cycle += 1;
+                self.write_operand(target, a & b);
+            },
+            v1_1::BOR => {
+                self.cycle += 1;
+                self.write_operand(target, a | b);
+            },
+            v1_1::XOR => {
+                self.cycle += 1;
+                self.write_operand(target, a ^ b);
+            },
+            v1_1::IFE => {
+                self.cycle += 2;
+                self.process_conditional(a == b);
+            },
+            v1_1::IFN => {
+                self.cycle += 2;
+                self.process_conditional(a != b);
+            },
+            v1_1::IFG => {
+                self.cycle += 2;
+                self.process_conditional(a > b);
+            },
+            _ => {
+                // IFB
+                self.cycle += 2;
+                self.process_conditional(a & b != 0);
             },
-            _ => {},
+        }
+    }
+
+    // Reads the next word if the operand has one, and applies POP and PUSH to SP
+    fn operand_v1_1(&mut self, id: usize) -> Operand {
+        match id {
+            0x00 ... 0x07 => Operand::Register(id),
+            0x08 ... 0x0f => Operand::Memory(self.reg[id - 0x08]),
+            0x10 ... 0x17 => {
+                self.cycle += 1;
+                let offset = self.mem[self.pcplus(true) as usize];
+                Operand::Memory(self.reg[id - 0x10].wrapping_add(offset))
+            },
+            0x18 => {
+                // POP
+                let sp = self.sp;
+                self.sp = sp.wrapping_add(1);
+                Operand::Memory(sp)
+            },
+            0x19 => Operand::Memory(self.sp),
+            0x1a => {
+                // PUSH
+                self.sp = self.sp.wrapping_sub(1);
+                Operand::Memory(self.sp)
+            },
+            0x1b => Operand::Sp,
+            0x1c => Operand::Pc,
+            0x1d => Operand::Overflow,
+            0x1e => {
+                self.cycle += 1;
+                Operand::Memory(self.mem[self.pcplus(true) as usize])
+            },
+            0x1f => {
+                self.cycle += 1;
+                Operand::Literal(self.mem[self.pcplus(true) as usize])
+            },
+            _ => Operand::Literal((id - 0x20) as u16),
+        }
+    }
+
+    fn read_operand(&mut self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Register(r) => self.reg[r],
+            Operand::Memory(address) => self.read_mem(address),
+            Operand::Sp => self.sp,
+            Operand::Pc => self.pc,
+            Operand::Overflow => self.ex,
+            Operand::Literal(v) => v,
+        }
+    }
+
+    // Writes to literals fail silently in 1.1
+    fn write_operand(&mut self, operand: Operand, value: u16) {
+        match operand {
+            Operand::Register(r) => { self.reg[r] = value; },
+            Operand::Memory(address) => { self.write_mem(address, value); },
+            Operand::Sp => { self.sp = value; },
+            Operand::Pc => { self.pc = value; },
+            Operand::Overflow => { self.ex = value; },
+            Operand::Literal(_) => {},
         }
     }
 
@@ -661,6 +2052,192 @@ impl DCPU {
         Ok(())
     }
 
+    /// Writes the entire machine state, including attached devices, to a snapshot.
+    /// Breakpoints and watchpoints are not included.
+    pub fn write_snapshot<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut state = StateWriter::new();
+        state.push(SNAPSHOT_VERSION);
+        for i in 0..8 {
+            state.push(self.reg[i]);
+        }
+        state.push(self.pc);
+        state.push(self.sp);
+        state.push(self.ex);
+        state.push(self.ia);
+        state.push_bool(self.interrupt_queueing);
+        state.push_bool(self.skip_next);
+        match self.halted {
+            None => state.push_slice(&[]),
+            Some(StopReason::Fault(CpuFault::InterruptQueueOverflow(message))) => state.push_slice(&[0, message]),
+            Some(StopReason::Fault(CpuFault::ReservedOpcode { pc, word })) => state.push_slice(&[1, pc, word]),
+            Some(StopReason::Fault(CpuFault::LiteralWrite { pc })) => state.push_slice(&[2, pc]),
+            Some(StopReason::Halt(a)) => state.push_slice(&[3, a]),
+            Some(StopReason::ZeroWord(pc)) => state.push_slice(&[4, pc]),
+            // Other reasons do not halt
+            Some(_) => state.push_slice(&[]),
+        }
+        state.push_bool(self.on_fire);
+        state.push_u32(self.fire_rng);
+        state.push_u64(self.cycle);
+        state.push_u64(self.overshot_cycles);
+        state.push_slice(&self.interrupt_queue);
+        state.push_slice(&self.mem[..]);
+        state.push(self.mappings.len() as u16);
+        for m in self.mappings.iter() {
+            state.push(m.from);
+            state.push(m.to);
+            match m.region {
+                MemoryRegion::Device(i) => {
+                    state.push(0);
+                    state.push(i as u16);
+                },
+                MemoryRegion::Rom => {
+                    state.push(1);
+                    state.push(0);
+                },
+            }
+        }
+
+        state.push(self.devices.len() as u16);
+        for dref in self.devices.iter() {
+            let device = dref.lock().unwrap();
+            let mut device_state = StateWriter::new();
+            device.save_state(&mut device_state);
+            let hardware_id = device.info().hardware_id;
+            state.push((hardware_id >> 16) as u16);
+            state.push(hardware_id as u16);
+            state.push_slice(device_state.words());
+        }
+
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        snapshot::write_words(writer, state.words())
+    }
+
+    /// Restores a snapshot written by `write_snapshot`. The same kinds of devices need to be
+    /// attached in the same order as when the snapshot was taken.
+    pub fn read_snapshot<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[..] != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a DCPU-16 snapshot"));
+        }
+        let words = snapshot::read_words(reader)?;
+        let mut state = StateReader::new(&words);
+        let version = state.next()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Unsupported snapshot version {}", version)));
+        }
+
+        let mut reg = [0u16; 8];
+        for i in 0..8 {
+            reg[i] = state.next()?;
+        }
+        let pc = state.next()?;
+        let sp = state.next()?;
+        let ex = state.next()?;
+        let ia = state.next()?;
+        let interrupt_queueing = state.next_bool()?;
+        let skip_next = state.next_bool()?;
+        let halted = match state.next_slice()? {
+            &[] => None,
+            &[0, message] => Some(StopReason::Fault(CpuFault::InterruptQueueOverflow(message))),
+            &[1, pc, word] => Some(StopReason::Fault(CpuFault::ReservedOpcode { pc: pc, word: word })),
+            &[2, pc] => Some(StopReason::Fault(CpuFault::LiteralWrite { pc: pc })),
+            &[3, a] => Some(StopReason::Halt(a)),
+            &[4, pc] => Some(StopReason::ZeroWord(pc)),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid halt state")),
+        };
+        let on_fire = state.next_bool()?;
+        let fire_rng = state.next_u32()?;
+        let cycle = state.next_u64()?;
+        let overshot_cycles = state.next_u64()?;
+        let interrupt_queue = state.next_slice()?.to_vec();
+        let mem = state.next_slice()?;
+        if mem.len() != MEMORY_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid memory size"));
+        }
+        let n_mappings = state.next()? as usize;
+        let mut mappings = Vec::with_capacity(n_mappings);
+        for _ in 0..n_mappings {
+            let from = state.next()?;
+            let to = state.next()?;
+            let region = match (state.next()?, state.next()?) {
+                (0, i) if (i as usize) < self.devices.len() => MemoryRegion::Device(i as usize),
+                (1, _) => MemoryRegion::Rom,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid memory mapping")),
+            };
+            mappings.push(Mapping { from: from, to: to, region: region });
+        }
+
+        // Check that the devices match before changing anything
+        let n_devices = state.next()? as usize;
+        if n_devices != self.devices.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Snapshot has {} devices, but {} are attached",
+                                              n_devices, self.devices.len())));
+        }
+        let mut device_states = Vec::new();
+        for dref in self.devices.iter() {
+            let device = dref.lock().unwrap();
+            let hardware_id = ((state.next()? as u32) << 16) | state.next()? as u32;
+            if hardware_id != device.info().hardware_id {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("Snapshot device {:08x} does not match {:08x}",
+                                                  hardware_id, device.info().hardware_id)));
+            }
+            device_states.push(state.next_slice()?);
+        }
+        state.finish()?;
+
+        // Devices load their state in place, so keep what they had, to put back if one of them
+        // fails and leave the machine as it was
+        let mut backups = Vec::new();
+        for (dref, words) in self.devices.iter().zip(device_states) {
+            let mut device = dref.lock().unwrap();
+            let mut backup = StateWriter::new();
+            device.save_state(&mut backup);
+            backups.push((dref, backup));
+            let mut device_state = StateReader::new(words);
+            let result = device.load_state(&mut device_state).and_then(|_| device_state.finish());
+            drop(device);
+            if let Err(why) = result {
+                for (dref, backup) in backups {
+                    let _ = dref.lock().unwrap().load_state(&mut StateReader::new(backup.words()));
+                }
+                return Err(why);
+            }
+        }
+
+        self.reg = reg;
+        self.pc = pc;
+        self.sp = sp;
+        self.ex = ex;
+        self.ia = ia;
+        self.interrupt_queueing = interrupt_queueing;
+        self.skip_next = skip_next;
+        self.halted = halted;
+        self.on_fire = on_fire;
+        self.fire_rng = fire_rng;
+        self.cycle = cycle;
+        self.overshot_cycles = overshot_cycles;
+        self.interrupt_queue = interrupt_queue;
+        self.mem.copy_from_slice(mem);
+        self.mappings = mappings;
+        self.history.clear();
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self, path: &Path) -> Result<()> {
+        let mut file = File::create(&path)?;
+        self.write_snapshot(&mut file)
+    }
+
+    pub fn load_snapshot(&mut self, path: &Path) -> Result<()> {
+        let mut file = File::open(&path)?;
+        self.read_snapshot(&mut file)
+    }
+
     // This function will assemble the binary for you, so the input file should be a .asm file.
     //pub fn load_from_assembly_file(&mut self, path: &Path) -> Result<()> {
         // TODO
@@ -718,3 +2295,12 @@ impl DCPU {
         */
     }
 }
+
+/// Reads a binary file as big-endian words (same format as `load_from_binary_file`), for example
+/// a ROM image.
+pub fn read_binary_file(path: &Path) -> Result<Vec<u16>> {
+    let mut file = File::open(&path)?;
+    let mut buffer: Vec<u8> = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer.chunks(2).filter(|c| c.len() == 2).map(|c| ((c[0] as u16) << 8) + (c[1] as u16)).collect())
+}
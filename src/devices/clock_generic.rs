@@ -1,5 +1,7 @@
-use dcpu::{self, DCPU, Device};
+use dcpu::{self, DCPU, Device, DeviceInfo};
+use snapshot::{StateWriter, StateReader};
 use std::any::Any;
+use std::io::Result;
 
 // Currently, the clock is based on DCPU-16 cycles, so it could be implemented with software on the
 // DCPU-16 itself.
@@ -24,11 +26,14 @@ impl DeviceClockGeneric {
 }
 
 impl Device for DeviceClockGeneric {
-    fn info_hardware_id_upper(&self) -> u16 { 0x12d0 }
-    fn info_hardware_id_lower(&self) -> u16 { 0xb402 }
-    fn info_manufacturer_id_upper(&self) -> u16 { 0x0 }
-    fn info_manufacturer_id_lower(&self) -> u16 { 0x0 }
-    fn info_version(&self) -> u16 { 1 }
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "Generic Clock",
+            hardware_id: 0x12d0b402,
+            version: 1,
+            manufacturer_id: 0,
+        }
+    }
 
     fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
         let reg_a = cpu.reg[dcpu::REG_A];
@@ -75,6 +80,28 @@ impl Device for DeviceClockGeneric {
         }
     }
 
+    fn reset(&mut self) -> () {
+        *self = DeviceClockGeneric::new();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> () {
+        state.push_bool(self.cycles_between_ticks.is_some());
+        state.push_u64(self.cycles_between_ticks.unwrap_or(0) as u64);
+        state.push_u64(self.cycles_in_current_tick as u64);
+        state.push(self.ticks);
+        state.push_option(self.interrupt_message);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        let enabled = state.next_bool()?;
+        let cycles_between_ticks = state.next_u64()? as usize;
+        self.cycles_between_ticks = if enabled { Some(cycles_between_ticks) } else { None };
+        self.cycles_in_current_tick = state.next_u64()? as usize;
+        self.ticks = state.next()?;
+        self.interrupt_message = state.next_option()?;
+        Ok(())
+    }
+
     fn as_any(&self) -> &Any {
         self
     }
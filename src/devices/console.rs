@@ -0,0 +1,276 @@
+use dcpu::{self, DCPU, Device, DeviceInfo};
+use snapshot::{StateWriter, StateReader};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::{self, Read, Result, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// Text access to the host's stdin and stdout (see specs/console.txt). Created with `new`, input and
+// output are in-memory buffers (`push_input` and `output`), which is what tests want. With `stdio`,
+// a background thread reads stdin, and input is picked up whenever the device runs.
+
+// Input that has not been read by the program. Beyond this, the oldest input is dropped.
+const MAX_BUFFER: usize = 4096;
+
+enum Output {
+    Memory(Vec<u8>),
+    Host(Box<Write + Send>),
+}
+
+pub struct DeviceConsole {
+    // Readable input
+    buffer: VecDeque<u8>,
+    // Input that has arrived since the device last ran
+    arrived: Vec<u8>,
+    host_input: Option<Receiver<Vec<u8>>>,
+    // The input has ended, but this has not been picked up yet
+    closing: bool,
+    ended: bool,
+    line_mode: bool,
+    interrupt_message: Option<u16>,
+    output: Output,
+}
+
+impl DeviceConsole {
+    /// A console with in-memory input and output.
+    pub fn new() -> DeviceConsole {
+        DeviceConsole {
+            buffer: VecDeque::new(),
+            arrived: Vec::new(),
+            host_input: None,
+            closing: false,
+            ended: false,
+            line_mode: false,
+            interrupt_message: None,
+            output: Output::Memory(Vec::new()),
+        }
+    }
+
+    /// A console that reads from `input` on a background thread, and writes to `output`.
+    pub fn with_io<R: Read + Send + 'static>(mut input: R, output: Box<Write + Send>) -> DeviceConsole {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            loop {
+                match input.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if sender.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(_) => break,
+                }
+            }
+        });
+        let mut console = DeviceConsole::new();
+        console.host_input = Some(receiver);
+        console.output = Output::Host(output);
+        console
+    }
+
+    /// A console on the host's stdin and stdout.
+    pub fn stdio() -> DeviceConsole {
+        DeviceConsole::with_io(io::stdin(), Box::new(io::stdout()))
+    }
+
+    /// Adds input, as if it had been typed. The program sees it the next time the device runs.
+    pub fn push_input(&mut self, bytes: &[u8]) -> () {
+        self.arrived.extend_from_slice(bytes);
+    }
+
+    /// Ends the input, as if stdin had been closed.
+    pub fn close_input(&mut self) -> () {
+        self.closing = true;
+    }
+
+    /// What the program has written, if output is in memory (see `new`).
+    pub fn output(&self) -> &[u8] {
+        match self.output {
+            Output::Memory(ref v) => &v[..],
+            Output::Host(_) => &[],
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> () {
+        match self.output {
+            Output::Memory(ref mut v) => v.extend_from_slice(bytes),
+            Output::Host(ref mut w) => {
+                // Flushed right away, since programs often prompt without a newline
+                w.write_all(bytes).and_then(|_| w.flush()).ok();
+            },
+        }
+    }
+
+    fn complete_lines(&self) -> usize {
+        self.buffer.iter().filter(|&&c| c == b'\n').count()
+    }
+
+    // Characters that the program can read
+    fn readable(&self) -> usize {
+        if self.line_mode {
+            self.buffer.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1)
+        } else {
+            self.buffer.len()
+        }
+    }
+
+    // Moves input that has arrived into the buffer. Returns whether there is new readable input.
+    fn receive(&mut self) -> bool {
+        if let Some(receiver) = self.host_input.take() {
+            loop {
+                match receiver.try_recv() {
+                    Ok(bytes) => self.arrived.extend(bytes),
+                    Err(TryRecvError::Empty) => {
+                        self.host_input = Some(receiver);
+                        break;
+                    },
+                    Err(TryRecvError::Disconnected) => {
+                        self.closing = true;
+                        break;
+                    },
+                }
+            }
+        }
+        if self.arrived.is_empty() && !self.closing {
+            return false;
+        }
+
+        let before = self.readable();
+        for c in self.arrived.drain(..) {
+            if c != b'\r' {
+                self.buffer.push_back(c);
+            }
+        }
+        if self.closing && !self.ended {
+            self.closing = false;
+            self.ended = true;
+            // A line cut off by the end of the input counts as complete
+            if self.buffer.back().map_or(false, |&c| c != b'\n') {
+                self.buffer.push_back(b'\n');
+            }
+        }
+        while self.buffer.len() > MAX_BUFFER {
+            self.buffer.pop_front();
+        }
+        self.readable() > before
+    }
+
+    fn read_line(&mut self, cpu: &mut DCPU, address: u16, max: u16) -> u16 {
+        let len = match self.buffer.iter().position(|&c| c == b'\n') {
+            Some(len) => len,
+            None => return 0xffff,
+        };
+        let line: Vec<u8> = self.buffer.drain(..len + 1).take(len).collect();
+        let copied = len.min(max as usize);
+        for (i, &c) in line[..copied].iter().enumerate() {
+            cpu.mem[address.wrapping_add(i as u16) as usize] = c as u16;
+        }
+        if copied < max as usize {
+            cpu.mem[address.wrapping_add(copied as u16) as usize] = 0;
+        }
+        copied as u16
+    }
+}
+
+impl Device for DeviceConsole {
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "Host Console",
+            hardware_id: 0x434f4e53,
+            version: 1,
+            manufacturer_id: 0,
+        }
+    }
+
+    fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
+        let reg_a = cpu.reg[dcpu::REG_A];
+        let reg_b = cpu.reg[dcpu::REG_B];
+        match reg_a {
+            0 => { // Set mode
+                self.line_mode = reg_b != 0;
+            },
+            1 => { // Read character
+                cpu.reg[dcpu::REG_C] = if self.readable() > 0 {
+                    self.buffer.pop_front().unwrap() as u16
+                } else {
+                    0
+                };
+            },
+            2 => { // Read line
+                let max = cpu.reg[dcpu::REG_C];
+                cpu.reg[dcpu::REG_C] = self.read_line(cpu, reg_b, max);
+            },
+            3 => { // Write character
+                self.write(&[reg_b as u8]);
+            },
+            4 => { // Write string
+                let mut s = Vec::new();
+                for i in 0..dcpu::MEMORY_SIZE {
+                    let c = cpu.mem[reg_b.wrapping_add(i as u16) as usize];
+                    if c == 0 {
+                        break;
+                    }
+                    s.push(c as u8);
+                }
+                self.write(&s);
+            },
+            5 => { // Set interrupt
+                self.interrupt_message = if reg_b != 0 {
+                    Some(reg_b)
+                } else {
+                    None
+                };
+            },
+            6 => { // Status
+                cpu.reg[dcpu::REG_C] = if self.line_mode {
+                    self.complete_lines() as u16
+                } else {
+                    self.buffer.len() as u16
+                };
+                cpu.reg[dcpu::REG_B] = (self.ended && self.buffer.is_empty()) as u16;
+            },
+            _ => {}
+        }
+    }
+
+    fn run(&mut self, cpu: &mut DCPU, _: usize) -> () {
+        if self.receive() {
+            if let Some(m) = self.interrupt_message {
+                cpu.interrupt(m);
+            }
+        }
+    }
+
+    fn reset(&mut self) -> () {
+        // Input that has not been read is kept, since it belongs to the host
+        self.line_mode = false;
+        self.interrupt_message = None;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> () {
+        let buffer: Vec<u16> = self.buffer.iter().map(|&c| c as u16).collect();
+        state.push_slice(&buffer);
+        state.push_bool(self.ended);
+        state.push_bool(self.line_mode);
+        state.push_option(self.interrupt_message);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.buffer = state.next_slice()?.iter().map(|&c| c as u8).collect();
+        self.ended = state.next_bool()?;
+        self.line_mode = state.next_bool()?;
+        self.interrupt_message = state.next_option()?;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
@@ -0,0 +1,86 @@
+use dcpu::{self, DCPU, Device, DeviceInfo};
+use snapshot::{StateWriter, StateReader};
+use std::any::Any;
+use std::io::Result;
+
+// Lets programs stop the emulator with an exit status, which is useful for automated tests (see
+// specs/exit.txt). The stop is reported as `StopReason::DeviceStop(status)`.
+
+/// Process exit code for when a cycle or time limit stopped the program (same as the `timeout`
+/// command).
+pub const EXIT_LIMIT: i32 = 124;
+/// Process exit code for statuses that do not fit in one (see `exit_code`).
+pub const EXIT_OVERFLOW: i32 = 125;
+
+/// Turns a 16-bit exit status into a process exit code, which only has 8 bits. Statuses 0 to 255
+/// are used as they are, apart from `EXIT_LIMIT`, which like statuses above 255 becomes
+/// `EXIT_OVERFLOW`. A non-zero status therefore never looks like success or a reached limit.
+pub fn exit_code(status: u16) -> i32 {
+    match status as i32 {
+        code @ 0...255 if code != EXIT_LIMIT => code,
+        _ => EXIT_OVERFLOW,
+    }
+}
+
+pub struct DeviceExit {
+    status: Option<u16>,
+}
+
+impl DeviceExit {
+    pub fn new() -> DeviceExit {
+        DeviceExit {
+            status: None,
+        }
+    }
+
+    /// The exit status the program asked for, if it has.
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+}
+
+impl Device for DeviceExit {
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "Exit Device",
+            hardware_id: 0x45584954,
+            version: 1,
+            manufacturer_id: 0,
+        }
+    }
+
+    fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
+        match cpu.reg[dcpu::REG_A] {
+            0 => { // Exit
+                let status = cpu.reg[dcpu::REG_B];
+                self.status = Some(status);
+                cpu.request_stop(status);
+            },
+            _ => {}
+        }
+    }
+
+    fn run(&mut self, _cpu: &mut DCPU, _cycles: usize) -> () {
+    }
+
+    fn reset(&mut self) -> () {
+        self.status = None;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> () {
+        state.push_option(self.status);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.status = state.next_option()?;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
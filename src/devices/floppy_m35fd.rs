@@ -1,23 +1,83 @@
-use dcpu::{self, DCPU, Device};
+use dcpu::{self, DCPU, Device, DeviceInfo};
+use snapshot::{self, StateWriter, StateReader};
 use std::any::Any;
-//use std::cmp;
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Result, Seek, SeekFrom, Write};
+use std::f64;
+use std::path::{Path, PathBuf};
 
 const FLOPPY_SECTOR_SIZE: usize = 512;
 const FLOPPY_NUM_SECTORS: usize = 1440;
 
-// TODO: Calculate these from dcpu::CYCLE_HZ
+// Sectors are numbered track by track, and tracks alternate between the two sides. A track is
+// 18 sectors, so the 80 tracks take up 40 cylinders. Only moving between cylinders takes time.
+const FLOPPY_SECTORS_PER_TRACK: usize = 18;
+const FLOPPY_SIDES: usize = 2;
 
-// Read/write speed is 30700 words/second
-// The DCPU-16 runs at 100 kHz, so a sector of size 512 words will take
-// 512 / 30700 * 100000 = 1667 cycles to complete
-const READ_WRITE_WAIT_CYCLES: usize = 1667;
+// Size of a raw image of a whole disk
+const RAW_IMAGE_BYTES: usize = FLOPPY_NUM_SECTORS * FLOPPY_SECTOR_SIZE * 2;
 
-// Cycles per track change (2.4 ms)
-const READ_WRITE_TRACK_SEEK_CYCLES: usize = 240;
+const SPARSE_MAGIC: &'static [u8] = b"M35FDSPR";
+
+/// How long the drive takes to read and write sectors. Every read or write first moves the head
+/// to the sector's cylinder, and then transfers the sector.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FloppyTiming {
+    /// Seconds to move the head by one cylinder.
+    pub seek_time: f64,
+    /// Transfer speed, in words per second.
+    pub words_per_second: f64,
+}
+
+impl FloppyTiming {
+    /// Timing from the spec: 2.4 ms per track, and 30.7k words per second (about 1667 cycles per
+    /// sector at `dcpu::CYCLE_HZ`). This is the default.
+    pub fn m35fd() -> FloppyTiming {
+        FloppyTiming {
+            seek_time: 0.0024,
+            words_per_second: 30700.0,
+        }
+    }
+
+    /// No delays, so that reads and writes finish the next time the drive runs. Good for tests.
+    pub fn instant() -> FloppyTiming {
+        FloppyTiming {
+            seek_time: 0.0,
+            words_per_second: f64::INFINITY,
+        }
+    }
+
+    /// Cycles that reading or writing `sector` takes, with the head at the cylinder of `from`.
+    pub fn cycles(&self, from: u16, sector: u16) -> usize {
+        let distance = (cylinder(from) as isize - cylinder(sector) as isize).abs() as f64;
+        let seconds = distance * self.seek_time + FLOPPY_SECTOR_SIZE as f64 / self.words_per_second;
+        (seconds * dcpu::CYCLE_HZ as f64).round() as usize
+    }
+}
+
+fn cylinder(sector: u16) -> usize {
+    sector as usize / (FLOPPY_SECTORS_PER_TRACK * FLOPPY_SIDES)
+}
+
+/// How a disk image is stored on the host.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// All 1440 sectors of 512 words, as big-endian words (the byte order of
+    /// `DCPU::load_from_binary_file`). Shorter files are padded with zeros when read.
+    Raw,
+    /// A header (`M35FDSPR`), followed by the sectors that are not all zeros, each as its index
+    /// and then its 512 words. Also big-endian.
+    Sparse,
+}
 
 pub struct FloppyDisk {
     pub sectors: Vec<[u16; FLOPPY_SECTOR_SIZE]>,
     pub write_protected: bool,
+    // Sectors written by the drive since the image file was last brought up to date
+    dirty: BTreeSet<usize>,
+    // Where `flush` writes dirty sectors to
+    image: Option<(PathBuf, ImageFormat)>,
 }
 
 impl FloppyDisk {
@@ -25,6 +85,151 @@ impl FloppyDisk {
         FloppyDisk {
             sectors: Vec::new(),
             write_protected: false,
+            dirty: BTreeSet::new(),
+            image: None,
+        }
+    }
+
+    /// Reads a disk image in either format. Sparse images are recognized by their header.
+    pub fn read_image<R: Read>(reader: &mut R) -> Result<FloppyDisk> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        FloppyDisk::parse_image(&bytes).map(|(disk, _)| disk)
+    }
+
+    /// Loads a disk image file. Sectors that the drive writes are written back to it by `flush`.
+    /// The disk is write-protected if the file is read-only.
+    pub fn load(path: &Path) -> Result<FloppyDisk> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let (mut disk, format) = FloppyDisk::parse_image(&bytes)?;
+        let read_only = match OpenOptions::new().write(true).open(path) {
+            Ok(_) => fs::metadata(path)?.permissions().readonly(),
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => true,
+            Err(e) => return Err(e),
+        };
+        disk.write_protected = read_only;
+        disk.image = Some((path.to_path_buf(), format));
+        Ok(disk)
+    }
+
+    pub fn write_image<W: Write>(&self, writer: &mut W, format: ImageFormat) -> Result<()> {
+        match format {
+            ImageFormat::Raw => {
+                for i in 0..FLOPPY_NUM_SECTORS {
+                    match self.sectors.get(i) {
+                        Some(sector) => snapshot::write_words(writer, &sector[..])?,
+                        None => writer.write_all(&[0; FLOPPY_SECTOR_SIZE * 2])?,
+                    }
+                }
+            },
+            ImageFormat::Sparse => {
+                writer.write_all(SPARSE_MAGIC)?;
+                for (i, sector) in self.sectors.iter().enumerate() {
+                    if sector.iter().any(|&w| w != 0) {
+                        snapshot::write_words(writer, &[i as u16])?;
+                        snapshot::write_words(writer, &sector[..])?;
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Writes the whole disk to a new image file, which `flush` then writes back to.
+    pub fn save(&mut self, path: &Path, format: ImageFormat) -> Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        self.write_image(&mut file, format)?;
+        file.flush()?;
+        self.image = Some((path.to_path_buf(), format));
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Writes sectors that have changed since the disk was loaded or saved back to its image
+    /// file. Raw images are updated in place. Does nothing if the disk has no image file.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        match self.image.clone() {
+            Some((ref path, ImageFormat::Raw)) => {
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                if file.metadata()?.len() < RAW_IMAGE_BYTES as u64 {
+                    file.set_len(RAW_IMAGE_BYTES as u64)?;
+                }
+                let zero = [0u16; FLOPPY_SECTOR_SIZE];
+                for &i in self.dirty.iter() {
+                    file.seek(SeekFrom::Start((i * FLOPPY_SECTOR_SIZE * 2) as u64))?;
+                    snapshot::write_words(&mut file, &self.sectors.get(i).unwrap_or(&zero)[..])?;
+                }
+            },
+            Some((ref path, ImageFormat::Sparse)) => {
+                self.save(path, ImageFormat::Sparse)?;
+            },
+            None => return Ok(()),
+        }
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Whether sectors have been written that `flush` has not written back yet.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    // Replaces the contents, for example from a snapshot. Sectors that are now different from the
+    // image file are marked dirty.
+    fn restore(&mut self, sectors: Vec<[u16; FLOPPY_SECTOR_SIZE]>) {
+        let zero = [0u16; FLOPPY_SECTOR_SIZE];
+        for i in 0..self.sectors.len().max(sectors.len()) {
+            // Sectors that were already dirty stay dirty, since the file still has older contents
+            if self.sectors.get(i).unwrap_or(&zero)[..] != sectors.get(i).unwrap_or(&zero)[..] {
+                self.dirty.insert(i);
+            }
+        }
+        self.sectors = sectors;
+    }
+
+    fn write_sector(&mut self, index: usize, sector: [u16; FLOPPY_SECTOR_SIZE]) {
+        while self.sectors.len() <= index {
+            self.sectors.push([0; FLOPPY_SECTOR_SIZE]);
+        }
+        self.sectors[index] = sector;
+        self.dirty.insert(index);
+    }
+
+    fn parse_image(bytes: &[u8]) -> Result<(FloppyDisk, ImageFormat)> {
+        let mut disk = FloppyDisk::new();
+        // A full raw image can start with anything, including the sparse header
+        if bytes.starts_with(SPARSE_MAGIC) && bytes.len() != RAW_IMAGE_BYTES {
+            let words: Vec<u16> = bytes[SPARSE_MAGIC.len()..].chunks(2)
+                .filter(|c| c.len() == 2)
+                .map(|c| ((c[0] as u16) << 8) + (c[1] as u16)).collect();
+            for entry in words.chunks(FLOPPY_SECTOR_SIZE + 1) {
+                let index = entry[0] as usize;
+                if entry.len() != FLOPPY_SECTOR_SIZE + 1 || index >= FLOPPY_NUM_SECTORS {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid sparse disk image"));
+                }
+                let mut sector = [0u16; FLOPPY_SECTOR_SIZE];
+                sector.copy_from_slice(&entry[1..]);
+                disk.write_sector(index, sector);
+            }
+            disk.dirty.clear();
+            Ok((disk, ImageFormat::Sparse))
+        } else {
+            if bytes.len() > RAW_IMAGE_BYTES {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("Disk image is larger than {} bytes", RAW_IMAGE_BYTES)));
+            }
+            for chunk in bytes.chunks(FLOPPY_SECTOR_SIZE * 2) {
+                let mut sector = [0u16; FLOPPY_SECTOR_SIZE];
+                for (i, c) in chunk.chunks(2).filter(|c| c.len() == 2).enumerate() {
+                    sector[i] = ((c[0] as u16) << 8) + (c[1] as u16);
+                }
+                disk.sectors.push(sector);
+            }
+            Ok((disk, ImageFormat::Raw))
         }
     }
 }
@@ -47,6 +252,7 @@ pub struct DeviceFloppyM35FD {
     rw_sector: u16, // Also doubles as "last sector" before read/write
     rw_dcpu_address: u16,
     rw_wait_cycles: usize,
+    timing: FloppyTiming,
 
     interrupt_queued: bool,
 }
@@ -76,11 +282,20 @@ impl DeviceFloppyM35FD {
             rw_sector: 0, // Floppies generally start a cylinder 0
             rw_dcpu_address: 0x0,
             rw_wait_cycles: 0,
+            timing: FloppyTiming::m35fd(),
 
             interrupt_queued: false,
         }
     }
 
+    pub fn set_timing(&mut self, timing: FloppyTiming) -> () {
+        self.timing = timing;
+    }
+
+    pub fn timing(&self) -> FloppyTiming {
+        self.timing
+    }
+
     pub fn state(&self) -> u16 {
         self.state
     }
@@ -111,22 +326,34 @@ impl DeviceFloppyM35FD {
 
     pub fn insert(&mut self, disk: FloppyDisk) {
         // TODO: Check to see if floppy is already inserted?
+        let state = if disk.write_protected { STATE_READY_WP } else { STATE_READY };
         self.disk = Some(disk);
-        self.set_state(STATE_READY);
+        self.set_state(state);
     }
 
     pub fn eject(&mut self) -> Option<FloppyDisk> {
         self.set_state(STATE_NO_MEDIA);
         self.disk.take()
     }
+
+    /// Writes changed sectors back to the inserted disk's image file (see `FloppyDisk::flush`).
+    pub fn flush(&mut self) -> Result<()> {
+        match self.disk {
+            Some(ref mut disk) => disk.flush(),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Device for DeviceFloppyM35FD {
-    fn info_hardware_id_upper(&self) -> u16 { 0x4fd5 }
-    fn info_hardware_id_lower(&self) -> u16 { 0x24c5 }
-    fn info_manufacturer_id_upper(&self) -> u16 { 0x1eb3 }
-    fn info_manufacturer_id_lower(&self) -> u16 { 0x7e91 }
-    fn info_version(&self) -> u16 { 0x000b }
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "Mackapar M35FD Floppy Drive",
+            hardware_id: 0x4fd524c5,
+            version: 0x000b,
+            manufacturer_id: 0x1eb37e91,
+        }
+    }
 
     fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
         let a = cpu.reg[dcpu::REG_A];
@@ -151,18 +378,9 @@ impl Device for DeviceFloppyM35FD {
                         if (x as usize) < FLOPPY_NUM_SECTORS {
                             // Issue sector read, will be performed after delay in run()
                             self.internal_state = FloppyInternalState::WaitToRead;
-                            let track1 = (self.rw_sector / 18) as isize;
+                            self.rw_wait_cycles = self.timing.cycles(self.rw_sector, x);
                             self.rw_sector = x;
-                            let track2 = (self.rw_sector / 18) as isize;
                             self.rw_dcpu_address = y;
-                            self.rw_wait_cycles = READ_WRITE_WAIT_CYCLES;
-                            let track_diff = (track1 - track2).abs() as usize;
-                            // Code if we want to switch to two-sided floppy with 160 tracks
-                            // We assume a two-sided floppy (without independent seeks), so seek
-                            // distance is zero between tracks 0 and 79, and similarly 5 and 75.
-                            //let track_diff = cmp::min((track1 - track2).abs(),
-                            //                          (159 - track1 - track2).abs()) as usize;
-                            self.rw_wait_cycles += track_diff * READ_WRITE_TRACK_SEEK_CYCLES;
                             self.set_state(STATE_BUSY);
                             ERROR_NONE
                         } else {
@@ -185,9 +403,9 @@ impl Device for DeviceFloppyM35FD {
                         if (x as usize) < FLOPPY_NUM_SECTORS {
                             // Issue sector write, will be performed after delay in run()
                             self.internal_state = FloppyInternalState::WaitToWrite;
+                            self.rw_wait_cycles = self.timing.cycles(self.rw_sector, x);
                             self.rw_sector = x;
                             self.rw_dcpu_address = y;
-                            self.rw_wait_cycles = READ_WRITE_WAIT_CYCLES;
                             self.set_state(STATE_BUSY);
                             ERROR_NONE
                         } else {
@@ -217,14 +435,15 @@ impl Device for DeviceFloppyM35FD {
                     match self.disk {
                         Some(ref floppy_disk) => {
                             match floppy_disk.sectors.get(self.rw_sector as usize) {
+                                // Goes through the memory bus, so that ROM is not overwritten
                                 Some(s) => {
                                     for i in 0..FLOPPY_SECTOR_SIZE {
-                                        cpu.mem[self.rw_dcpu_address.wrapping_add(i as u16) as usize] = s[i];
+                                        cpu.write_memory(self.rw_dcpu_address.wrapping_add(i as u16), s[i]);
                                     }
                                 },
                                 None => {
                                     for i in 0..FLOPPY_SECTOR_SIZE {
-                                        cpu.mem[self.rw_dcpu_address.wrapping_add(i as u16) as usize] = 0;
+                                        cpu.write_memory(self.rw_dcpu_address.wrapping_add(i as u16), 0);
                                     }
                                 },
                             }
@@ -252,20 +471,12 @@ impl Device for DeviceFloppyM35FD {
                     let error;
                     match self.disk {
                         Some(ref mut floppy_disk) => {
-                            // Make sure sector exists
-                            while floppy_disk.sectors.len() <= (self.rw_sector as usize) {
-                                floppy_disk.sectors.push([0; FLOPPY_SECTOR_SIZE]);
-                            }
-                            match floppy_disk.sectors.get_mut(self.rw_sector as usize) {
-                                Some(ref mut s) => {
-                                    for i in 0..FLOPPY_SECTOR_SIZE {
-                                        s[i as usize] = cpu.mem[self.rw_dcpu_address.wrapping_add(i as u16) as usize];
-                                    }
-                                },
-                                None => {
-                                    unreachable!();
-                                },
+                            // Goes through the memory bus, so that memory-mapped devices are seen
+                            let mut sector = [0; FLOPPY_SECTOR_SIZE];
+                            for i in 0..FLOPPY_SECTOR_SIZE {
+                                sector[i] = cpu.read_memory(self.rw_dcpu_address.wrapping_add(i as u16));
                             }
+                            floppy_disk.write_sector(self.rw_sector as usize, sector);
 
                             // TODO: Potentially do a sector clean-up if trailing sectors
                             // are all-zero
@@ -295,6 +506,106 @@ impl Device for DeviceFloppyM35FD {
         }
     }
 
+    fn reset(&mut self) -> () {
+        // The disk stays in the drive, but an operation in progress is lost
+        self.state = match self.disk {
+            Some(ref disk) if disk.write_protected => STATE_READY_WP,
+            Some(_) => STATE_READY,
+            None => STATE_NO_MEDIA,
+        };
+        self.error = ERROR_NONE;
+        self.interrupt_message = 0;
+        self.internal_state = FloppyInternalState::Idle;
+        self.rw_sector = 0;
+        self.rw_dcpu_address = 0;
+        self.rw_wait_cycles = 0;
+        self.interrupt_queued = false;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> () {
+        state.push(self.state);
+        state.push(self.error);
+        state.push(self.interrupt_message);
+        state.push(match self.internal_state {
+            FloppyInternalState::Idle => 0,
+            FloppyInternalState::WaitToRead => 1,
+            FloppyInternalState::WaitToWrite => 2,
+        });
+        state.push(self.rw_sector);
+        state.push(self.rw_dcpu_address);
+        state.push_u64(self.rw_wait_cycles as u64);
+        state.push_bool(self.interrupt_queued);
+        match self.disk {
+            Some(ref disk) => {
+                state.push_bool(true);
+                state.push_bool(disk.write_protected);
+                state.push_u32(disk.sectors.len() as u32);
+                for sector in disk.sectors.iter() {
+                    state.push_slice(&sector[..]);
+                }
+            },
+            None => {
+                state.push_bool(false);
+            },
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        let floppy_state = state.next()?;
+        let error = state.next()?;
+        let interrupt_message = state.next()?;
+        let internal_state = match state.next()? {
+            0 => FloppyInternalState::Idle,
+            1 => FloppyInternalState::WaitToRead,
+            2 => FloppyInternalState::WaitToWrite,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid floppy state")),
+        };
+        let rw_sector = state.next()?;
+        let rw_dcpu_address = state.next()?;
+        let rw_wait_cycles = state.next_u64()? as usize;
+        let interrupt_queued = state.next_bool()?;
+        let disk = if state.next_bool()? {
+            let write_protected = state.next_bool()?;
+            let n_sectors = state.next_u32()? as usize;
+            if n_sectors > FLOPPY_NUM_SECTORS {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Too many sectors"));
+            }
+            let mut sectors = Vec::with_capacity(n_sectors);
+            for _ in 0..n_sectors {
+                let words = state.next_slice()?;
+                if words.len() != FLOPPY_SECTOR_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid sector size"));
+                }
+                let mut sector = [0u16; FLOPPY_SECTOR_SIZE];
+                sector.copy_from_slice(words);
+                sectors.push(sector);
+            }
+            Some((write_protected, sectors))
+        } else {
+            None
+        };
+
+        self.state = floppy_state;
+        self.error = error;
+        self.interrupt_message = interrupt_message;
+        self.internal_state = internal_state;
+        self.rw_sector = rw_sector;
+        self.rw_dcpu_address = rw_dcpu_address;
+        self.rw_wait_cycles = rw_wait_cycles;
+        self.interrupt_queued = interrupt_queued;
+        match disk {
+            Some((write_protected, sectors)) => {
+                // The inserted disk keeps its image file, so that writes still end up there
+                let mut disk = self.disk.take().unwrap_or_else(FloppyDisk::new);
+                disk.restore(sectors);
+                disk.write_protected = write_protected;
+                self.disk = Some(disk);
+            },
+            None => self.disk = None,
+        }
+        Ok(())
+    }
+
     fn as_any(&self) -> &Any {
         self
     }
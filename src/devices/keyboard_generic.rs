@@ -1,5 +1,7 @@
-use dcpu::{self, DCPU, Device};
+use dcpu::{self, DCPU, Device, DeviceInfo};
+use snapshot::{StateWriter, StateReader};
 use std::any::Any;
+use std::io::Result;
 
 // If the queue (buffer) fills up, it will start to drop old entries
 const MAX_BUFFER: usize = 256;
@@ -88,11 +90,14 @@ impl DeviceKeyboardGeneric {
 }
 
 impl Device for DeviceKeyboardGeneric {
-    fn info_hardware_id_upper(&self) -> u16 { 0x30cf }
-    fn info_hardware_id_lower(&self) -> u16 { 0x7406 }
-    fn info_manufacturer_id_upper(&self) -> u16 { 0x0 }
-    fn info_manufacturer_id_lower(&self) -> u16 { 0x0 }
-    fn info_version(&self) -> u16 { 1 }
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "Generic Keyboard",
+            hardware_id: 0x30cf7406,
+            version: 1,
+            manufacturer_id: 0,
+        }
+    }
 
     fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
         let reg_a = cpu.reg[dcpu::REG_A];
@@ -126,6 +131,29 @@ impl Device for DeviceKeyboardGeneric {
 
     fn run(&mut self, _: &mut DCPU, _: usize) -> () {}
 
+    fn reset(&mut self) -> () {
+        // Keys that are held down stay pressed
+        self.buffer.clear();
+        self.interrupt_message = None;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> () {
+        state.push_slice(&self.buffer);
+        state.push_option(self.interrupt_message);
+        for p in self.pressed.iter() {
+            state.push_bool(*p);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.buffer = state.next_slice()?.to_vec();
+        self.interrupt_message = state.next_option()?;
+        for p in self.pressed.iter_mut() {
+            *p = state.next_bool()?;
+        }
+        Ok(())
+    }
+
     fn as_any(&self) -> &Any {
         self
     }
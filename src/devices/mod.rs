@@ -2,3 +2,5 @@ pub mod monitor_lem1802;
 pub mod clock_generic;
 pub mod keyboard_generic;
 pub mod floppy_m35fd;
+pub mod exit;
+pub mod console;
@@ -1,5 +1,7 @@
-use dcpu::{self, DCPU, Device};
+use dcpu::{self, DCPU, Device, DeviceInfo};
+use snapshot::{StateWriter, StateReader};
 use std::any::Any;
+use std::io::Result;
 
 pub struct DeviceMonitorLEM1802 {
     pub connected: bool,
@@ -205,11 +207,14 @@ impl DeviceMonitorLEM1802 {
 
 
 impl Device for DeviceMonitorLEM1802 {
-    fn info_hardware_id_upper(&self) -> u16 { 0x7349 }
-    fn info_hardware_id_lower(&self) -> u16 { 0xf615 }
-    fn info_manufacturer_id_upper(&self) -> u16 { 0x1c6c }
-    fn info_manufacturer_id_lower(&self) -> u16 { 0x8b36 }
-    fn info_version(&self) -> u16 { 0x1802 }
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "NYA Elektriska LEM1802 Monitor",
+            hardware_id: 0x7349f615,
+            version: 0x1802,
+            manufacturer_id: 0x1c6c8b36,
+        }
+    }
 
     fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
         let a = cpu.reg[0];
@@ -259,6 +264,27 @@ impl Device for DeviceMonitorLEM1802 {
 
     fn run(&mut self, _: &mut DCPU, _: usize) -> () {}
 
+    fn reset(&mut self) -> () {
+        *self = DeviceMonitorLEM1802::new();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) -> () {
+        state.push_bool(self.connected);
+        state.push(self.ram_location);
+        state.push_option(self.font_location);
+        state.push_option(self.palette_location);
+        state.push(self.border_color_index);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.connected = state.next_bool()?;
+        self.ram_location = state.next()?;
+        self.font_location = state.next_option()?;
+        self.palette_location = state.next_option()?;
+        self.border_color_index = state.next()?;
+        Ok(())
+    }
+
     fn as_any(&self) -> &Any {
         self
     }
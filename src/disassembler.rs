@@ -101,15 +101,21 @@ fn special_opcode_str(opcode: usize) -> Result<&'static str, ()> {
         HWN => Ok("HWN"),
         HWQ => Ok("HWQ"),
         HWI => Ok("HWI"),
-        // Extra
-        OUT => Ok("OUT"),
-        OUV => Ok("OUV"),
         _ => Err(()),
     }
 }
 
-fn value_str(cpu: &DCPU, value: usize, offset: &mut u16,
+fn value_str(cpu: &DCPU, pc: u16, value: usize, offset: &mut u16,
              lvalue: bool, use_color: bool) -> String {
+    if cpu.isa() == Isa::V1_1 {
+        match value {
+            0x18 => return maybe_colorize("POP".to_string(), COLOR_NAMED, use_color),
+            0x1a => return maybe_colorize("PUSH".to_string(), COLOR_NAMED, use_color),
+            0x1d => return maybe_colorize("O".to_string(), COLOR_NAMED, use_color),
+            0x20 ... 0x3f => return maybe_colorize(format!("{}", value - 0x20), COLOR_NUM_LITERAL, use_color),
+            _ => {},
+        }
+    }
     match value {
         0x00 ... 0x07  => { 
             let s = registry_str(value).to_string();
@@ -121,7 +127,7 @@ fn value_str(cpu: &DCPU, value: usize, offset: &mut u16,
             format!("[{}]", ss)
         },
         0x10 ... 0x17 => {
-            let v = cpu.mem[(cpu.pc + *offset) as usize];
+            let v = cpu.mem[pc.wrapping_add(*offset) as usize];
             let s = registry_str(value-0x10).to_string();
             let ss = maybe_colorize(s, COLOR_REGISTRY, use_color);
             let vv = maybe_colorize(format!("{}", v), COLOR_NUM_LITERAL, use_color);
@@ -139,7 +145,7 @@ fn value_str(cpu: &DCPU, value: usize, offset: &mut u16,
             maybe_colorize("PEEK".to_string(), COLOR_NAMED, use_color)
         },
         0x1a => {
-            let v = cpu.mem[(cpu.pc + *offset) as usize];
+            let v = cpu.mem[pc.wrapping_add(*offset) as usize];
             *offset += 1;
             let ss = maybe_colorize("PICK".to_string(), COLOR_NAMED, use_color);
             let vv = maybe_colorize(format!("{}", v), COLOR_NUM_LITERAL, use_color);
@@ -155,13 +161,13 @@ fn value_str(cpu: &DCPU, value: usize, offset: &mut u16,
             maybe_colorize("EX".to_string(), COLOR_NAMED, use_color)
         },
         0x1e => {
-            let v = cpu.mem[cpu.pc.wrapping_add(*offset) as usize];
+            let v = cpu.mem[pc.wrapping_add(*offset) as usize];
             let ss = maybe_colorize(format!("0x{:04x}", v), COLOR_NUM_LITERAL, use_color);
             *offset = offset.wrapping_add(1);
             format!("[{}]", ss)
         },
         0x1f => { 
-            let v = cpu.mem[cpu.pc.wrapping_add(*offset) as usize];
+            let v = cpu.mem[pc.wrapping_add(*offset) as usize];
             *offset = offset.wrapping_add(1);
             maybe_colorize(format!("0x{:04x}", v), COLOR_NUM_LITERAL, use_color)
         },
@@ -188,16 +194,29 @@ fn registry_str(reg: usize) -> &'static str {
 }
 
 pub fn disassemble_instruction(cpu: &DCPU, use_color: bool) -> (u16, String) {
+    disassemble_instruction_at(cpu, cpu.pc, use_color)
+}
+
+/// Same as `disassemble_instruction`, but for the instruction at `pc` instead of `cpu.pc`.
+pub fn disassemble_instruction_at(cpu: &DCPU, pc: u16, use_color: bool) -> (u16, String) {
     let mut offset = 1u16;
-    let word = cpu.mem[cpu.pc as usize] as usize;
-    let opcode = word & 0x1f;
-    let id_b = (word >> 5) & 0x1f;
-    let id_a = (word >> 10) & 0x3f;
+    let word = cpu.mem[pc as usize] as usize;
+    let v1_1 = cpu.isa() == Isa::V1_1;
+    // Opcodes are translated to their 1.7 numbers, so that the names can be shared
+    let (opcode, id_b, id_a) = if v1_1 {
+        (word & 0xf, (word >> 4) & 0x3f, (word >> 10) & 0x3f)
+    } else {
+        (word & 0x1f, (word >> 5) & 0x1f, (word >> 10) & 0x3f)
+    };
 
     if opcode == 0 {
-        let spec_opcode = (word >> 5) & 0x1f;
-        let s_a = value_str(cpu, id_a, &mut offset, false, use_color);
-        let ret = special_opcode_str(spec_opcode);
+        let spec_opcode = if v1_1 { special_opcode_from_v1_1(id_b) } else { Some(id_b) };
+        let s_a = value_str(cpu, pc, id_a, &mut offset, false, use_color);
+        // Extensions have the same numbers in both revisions
+        let ret = match spec_opcode.map(special_opcode_str) {
+            Some(Ok(s)) => Ok(s.to_string()),
+            _ => cpu.extensions().by_opcode(id_b).map(|e| e.mnemonic().to_string()).ok_or(()),
+        };
         match ret {
             Ok(s) => {
                 let ss = maybe_colorize(s.to_string(), COLOR_INSTRUCTION, use_color);
@@ -210,8 +229,15 @@ pub fn disassemble_instruction(cpu: &DCPU, use_color: bool) -> (u16, String) {
             }
         }
     } else {
-        let s_a = value_str(cpu, id_a, &mut offset, false, use_color);
-        let s_b = value_str(cpu, id_b, &mut offset, true, use_color);
+        // The next word of the target comes first in 1.1
+        let (s_a, s_b) = if v1_1 {
+            let s_b = value_str(cpu, pc, id_b, &mut offset, true, use_color);
+            (value_str(cpu, pc, id_a, &mut offset, false, use_color), s_b)
+        } else {
+            let s_a = value_str(cpu, pc, id_a, &mut offset, false, use_color);
+            (s_a, value_str(cpu, pc, id_b, &mut offset, true, use_color))
+        };
+        let opcode = if v1_1 { basic_opcode_from_v1_1(opcode).unwrap_or(0) } else { opcode };
         let ret = opcode_str(opcode);
         match ret {
             Ok(s) => {
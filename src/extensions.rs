@@ -0,0 +1,169 @@
+// Extension instructions.
+//
+// Extensions are special opcodes that are not part of the DCPU-16 spec, such as `OUT` and `HLT`.
+// They are defined once, in a registry that is shared by the DCPU (which executes them), the
+// assembler and the disassembler:
+//
+//     let mut extensions = Extensions::standard();
+//     extensions.register("BRK", 0x16, 1, |cpu, a| cpu.request_stop(a)).unwrap();
+//     let extensions = Arc::new(extensions);
+//     pcpu.set_extensions(extensions.clone());
+//     cpu.set_extensions(extensions);
+//
+// A handler gets the value of the instruction's operand. Extensions use the same opcode numbers in
+// 1.1 (as non-basic opcodes) as in 1.7.
+
+use std::fmt;
+
+use dcpu::{DCPU, MEMORY_SIZE};
+
+/// Opcodes of the standard extensions (see `Extensions::standard`).
+pub const OUT: usize = 0x13;
+pub const OUV: usize = 0x14;
+pub const HLT: usize = 0x15;
+
+// Mnemonics that the assembler already uses
+const RESERVED: &'static [&'static str] = &[
+    "SET", "ADD", "SUB", "MUL", "MLI", "DIV", "DVI", "MOD", "MDI", "AND", "BOR", "XOR", "SHR", "ASR",
+    "SHL", "IFB", "IFC", "IFE", "IFN", "IFG", "IFA", "IFL", "IFU", "ADX", "SBX", "STI", "STD",
+    "JSR", "INT", "IAG", "IAS", "RFI", "IAQ", "HWN", "HWQ", "HWI", "DAT", "DAF", "POP",
+];
+
+// Special opcodes taken by the spec
+const RESERVED_OPCODES: &'static [usize] = &[0x01, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x10, 0x11, 0x12];
+
+/// Executes an extension, given the value of its operand.
+pub type Handler = Box<Fn(&mut DCPU, u16) + Send + Sync>;
+
+pub struct Extension {
+    mnemonic: String,
+    opcode: usize,
+    cycles: u64,
+    handler: Handler,
+}
+
+impl Extension {
+    /// Upper case, as written by the disassembler. The assembler ignores case.
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+
+    pub fn opcode(&self) -> usize {
+        self.opcode
+    }
+
+    /// Cycles taken, not counting the operand.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn execute(&self, cpu: &mut DCPU, a: u16) -> () {
+        (self.handler)(cpu, a);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExtensionError {
+    /// Mnemonics are three letters, like those of the spec.
+    InvalidMnemonic,
+    /// Opcodes go from 0x01 to 0x1f, and can not be one that the spec uses.
+    InvalidOpcode,
+    MnemonicTaken,
+    OpcodeTaken,
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExtensionError::InvalidMnemonic => write!(f, "mnemonic must be three letters"),
+            ExtensionError::InvalidOpcode => write!(f, "opcode must be a free special opcode (0x01-0x1f)"),
+            ExtensionError::MnemonicTaken => write!(f, "mnemonic is already in use"),
+            ExtensionError::OpcodeTaken => write!(f, "opcode is already in use"),
+        }
+    }
+}
+
+/// Registry of extension instructions.
+pub struct Extensions {
+    list: Vec<Extension>,
+}
+
+impl Extensions {
+    /// A registry without any extensions.
+    pub fn new() -> Extensions {
+        Extensions {
+            list: Vec::new(),
+        }
+    }
+
+    /// The extensions that are available by default:
+    ///
+    /// * `OUT a` prints the null-terminated string at `a` (see `DCPU::set_output`)
+    /// * `OUV a` prints `a` in decimal and then a newline
+    /// * `HLT a` halts the DCPU, with `a` as the exit status
+    pub fn standard() -> Extensions {
+        let mut extensions = Extensions::new();
+        extensions.register("OUT", OUT, 0, |cpu, a| {
+            let mut s = Vec::new();
+            for i in 0..MEMORY_SIZE {
+                let c = cpu.mem[a.wrapping_add(i as u16) as usize];
+                if c == 0 {
+                    break;
+                }
+                s.push((c & 0xff) as u8);
+            }
+            cpu.output().write_all(&s).ok();
+        }).unwrap();
+        extensions.register("OUV", OUV, 0, |cpu, a| {
+            writeln!(cpu.output(), "{}", a).ok();
+        }).unwrap();
+        extensions.register("HLT", HLT, 1, |cpu, a| cpu.halt_with_status(a)).unwrap();
+        extensions
+    }
+
+    /// Adds an extension that takes `cycles` (plus the cost of its operand) and runs `handler`.
+    pub fn register<F>(&mut self, mnemonic: &str, opcode: usize, cycles: u64,
+                       handler: F) -> Result<(), ExtensionError>
+        where F: Fn(&mut DCPU, u16) + Send + Sync + 'static
+    {
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        if mnemonic.len() != 3 || !mnemonic.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(ExtensionError::InvalidMnemonic);
+        }
+        if opcode == 0 || opcode > 0x1f || RESERVED_OPCODES.contains(&opcode) {
+            return Err(ExtensionError::InvalidOpcode);
+        }
+        if RESERVED.contains(&&mnemonic[..]) || self.by_mnemonic(&mnemonic).is_some() {
+            return Err(ExtensionError::MnemonicTaken);
+        }
+        if self.by_opcode(opcode).is_some() {
+            return Err(ExtensionError::OpcodeTaken);
+        }
+        self.list.push(Extension {
+            mnemonic: mnemonic,
+            opcode: opcode,
+            cycles: cycles,
+            handler: Box::new(handler),
+        });
+        Ok(())
+    }
+
+    /// Returns `false` if there was no such extension.
+    pub fn unregister(&mut self, mnemonic: &str) -> bool {
+        let len = self.list.len();
+        self.list.retain(|e| !e.mnemonic.eq_ignore_ascii_case(mnemonic));
+        self.list.len() != len
+    }
+
+    pub fn by_mnemonic(&self, mnemonic: &str) -> Option<&Extension> {
+        self.list.iter().find(|e| e.mnemonic.eq_ignore_ascii_case(mnemonic))
+    }
+
+    pub fn by_opcode(&self, opcode: usize) -> Option<&Extension> {
+        self.list.iter().find(|e| e.opcode == opcode)
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Extension> {
+        self.list.iter()
+    }
+}
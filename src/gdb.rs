@@ -0,0 +1,522 @@
+// GDB remote serial protocol (RSP) stub.
+//
+// This lets GDB-compatible tooling drive a `DCPU` over a byte stream (usually TCP or a Unix
+// socket). The registers are exposed as A, B, C, X, Y, Z, I, J, PC, SP, EX and IA (16 bits each).
+//
+// Memory addresses in the protocol are DCPU-16 word addresses, the same as the values of PC and
+// SP. Lengths are given in bytes, and each word is transferred as two bytes with the most
+// significant byte first, same as binary program files.
+//
+// Reverse stepping and continuing (`bs` and `bc`) are available if history is enabled on the DCPU
+// with `DCPU::set_history_limit`.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use dcpu::{DCPU, StopReason, WatchKind, MemoryAccess, CpuFault};
+
+// Ticks between checks for an interrupt (Ctrl-C) from the client while continuing
+const INTERRUPT_POLL_TICKS: usize = 10_000;
+
+const NUM_REGISTERS: usize = 12;
+
+const TARGET_XML: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.dcpu16.core">
+    <reg name="a" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="b" bitsize="16" type="uint16"/>
+    <reg name="c" bitsize="16" type="uint16"/>
+    <reg name="x" bitsize="16" type="uint16"/>
+    <reg name="y" bitsize="16" type="uint16"/>
+    <reg name="z" bitsize="16" type="uint16"/>
+    <reg name="i" bitsize="16" type="uint16"/>
+    <reg name="j" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="ex" bitsize="16" type="uint16"/>
+    <reg name="ia" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// A connection to a GDB client.
+pub trait GdbConnection: Read + Write {
+    /// Returns `true` if the client has sent an interrupt request (`0x03`). Must not block.
+    fn poll_interrupt(&mut self) -> bool;
+}
+
+impl GdbConnection for TcpStream {
+    fn poll_interrupt(&mut self) -> bool {
+        let mut buf = [0u8; 1];
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = match self.peek(&mut buf) {
+            Ok(1) => buf[0] == 0x03 && self.read(&mut buf).is_ok(),
+            _ => false,
+        };
+        self.set_nonblocking(false).ok();
+        interrupted
+    }
+}
+
+#[cfg(unix)]
+impl GdbConnection for ::std::os::unix::net::UnixStream {
+    fn poll_interrupt(&mut self) -> bool {
+        // Unix sockets have no peek in std, but clients do not send anything else while the
+        // target is running, so it is safe to consume a byte.
+        let mut buf = [0u8; 1];
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = match self.read(&mut buf) {
+            Ok(1) => buf[0] == 0x03,
+            _ => false,
+        };
+        self.set_nonblocking(false).ok();
+        interrupted
+    }
+}
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+enum Action {
+    Reply(String),
+    // The response has already been sent
+    Nothing,
+    Close,
+}
+
+pub struct GdbStub<S: GdbConnection> {
+    stream: S,
+    // Byte that was read ahead while waiting for an ack
+    pushback: Option<u8>,
+    no_ack: bool,
+    // (type, address, length) -> watchpoint ID
+    watchpoints: HashMap<(u8, u16, u16), usize>,
+}
+
+fn hex_u16(v: u16) -> String {
+    format!("{:04x}", v)
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn decode_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    for i in 0..(s.len() / 2) {
+        match u8::from_str_radix(&s[i * 2..i * 2 + 2], 16) {
+            Ok(b) => bytes.push(b),
+            Err(_) => return None,
+        }
+    }
+    Some(bytes)
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b))
+}
+
+impl<S: GdbConnection> GdbStub<S> {
+    pub fn new(stream: S) -> GdbStub<S> {
+        GdbStub {
+            stream: stream,
+            pushback: None,
+            no_ack: false,
+            watchpoints: HashMap::new(),
+        }
+    }
+
+    /// Gives back the underlying connection.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Serves requests until the client detaches, kills the target or closes the connection.
+    pub fn serve(&mut self, cpu: &mut DCPU) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(Packet::Command(p)) => p,
+                // Nothing is running, so there is nothing to interrupt
+                Some(Packet::Interrupt) => continue,
+                None => return Ok(()),
+            };
+            match self.handle(cpu, &packet)? {
+                Action::Reply(response) => self.send_packet(&response)?,
+                Action::Nothing => {},
+                Action::Close => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(b) = self.pushback.take() {
+            return Ok(Some(b));
+        }
+        let mut buf = [0u8; 1];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buf[0])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            // Skip acks and noise until the start of a packet
+            match self.read_byte()? {
+                Some(b'$') => {},
+                Some(0x03) => return Ok(Some(Packet::Interrupt)),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                    None => return Ok(None),
+                }
+            }
+            let mut cs = [0u8; 2];
+            for c in cs.iter_mut() {
+                match self.read_byte()? {
+                    Some(b) => *c = b,
+                    None => return Ok(None),
+                }
+            }
+
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let expected = String::from_utf8_lossy(&cs).into_owned();
+            let valid = parse_hex(&expected) == Some(checksum(&data) as usize);
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                self.stream.flush()?;
+            }
+            if valid {
+                return Ok(Some(Packet::Command(data)));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            self.stream.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'+') => return Ok(()),
+                Some(b'-') => continue,
+                // Be lenient with clients that do not ack
+                b => {
+                    self.pushback = b;
+                    return Ok(());
+                },
+            }
+        }
+    }
+
+    fn register(cpu: &DCPU, n: usize) -> Option<u16> {
+        match n {
+            0 ... 7 => Some(cpu.reg[n]),
+            8 => Some(cpu.pc),
+            9 => Some(cpu.sp),
+            10 => Some(cpu.ex),
+            11 => Some(cpu.ia),
+            _ => None,
+        }
+    }
+
+    fn set_register(cpu: &mut DCPU, n: usize, value: u16) -> bool {
+        match n {
+            0 ... 7 => cpu.reg[n] = value,
+            8 => cpu.pc = value,
+            9 => cpu.sp = value,
+            10 => cpu.ex = value,
+            11 => cpu.ia = value,
+            _ => return false,
+        }
+        true
+    }
+
+    fn stop_reply(&self, reason: Option<StopReason>, interrupted: bool) -> String {
+        match reason {
+            Some(StopReason::Watchpoint { id, address, access }) => {
+                let kind = match self.watchpoints.iter().find(|&(_, v)| *v == id) {
+                    Some((&(3, _, _), _)) => "rwatch",
+                    Some((&(4, _, _), _)) => "awatch",
+                    _ => match access {
+                        MemoryAccess::Read => "rwatch",
+                        MemoryAccess::Write => "watch",
+                    },
+                };
+                format!("T05{}:{:x};", kind, address)
+            },
+            Some(StopReason::Breakpoint(_)) => "S05".to_string(),
+            // SIGSEGV
+            Some(StopReason::RomWrite(_)) => "S0b".to_string(),
+            // SIGILL, SIGSEGV and SIGABRT
+            Some(StopReason::Fault(CpuFault::ReservedOpcode { .. })) => "S04".to_string(),
+            Some(StopReason::Fault(CpuFault::LiteralWrite { .. })) => "S0b".to_string(),
+            Some(StopReason::Fault(CpuFault::InterruptQueueOverflow(_))) => "S06".to_string(),
+            // The program exited
+            Some(StopReason::Halt(status)) => format!("W{:02x}", status & 0xff),
+            Some(StopReason::ZeroWord(_)) => "W00".to_string(),
+            Some(StopReason::DeviceStop(_)) => "S05".to_string(),
+            None if interrupted => "S02".to_string(),
+            None => "S05".to_string(),
+        }
+    }
+
+    fn resume(&mut self, cpu: &mut DCPU, args: &str, single_step: bool) -> String {
+        if !args.is_empty() {
+            if let Some(address) = parse_hex(args) {
+                cpu.pc = address as u16;
+            }
+        }
+        if single_step {
+            let reason = cpu.tick().stop_reason();
+            return self.stop_reply(reason, false);
+        }
+        let mut ticks = 0;
+        loop {
+            let reason = cpu.tick().stop_reason();
+            if reason.is_some() {
+                return self.stop_reply(reason, false);
+            }
+            ticks += 1;
+            if ticks % INTERRUPT_POLL_TICKS == 0 && self.stream.poll_interrupt() {
+                return self.stop_reply(None, true);
+            }
+        }
+    }
+
+    fn resume_backwards(&mut self, cpu: &mut DCPU, single_step: bool) -> String {
+        if cpu.history_len() == 0 {
+            return "T05replaylog:begin;".to_string();
+        }
+        if single_step {
+            cpu.step_back(1);
+            return "S05".to_string();
+        }
+        match cpu.run_back() {
+            Some(reason) => self.stop_reply(Some(reason), false),
+            None => "T05replaylog:begin;".to_string(),
+        }
+    }
+
+    fn read_memory(cpu: &DCPU, args: &str) -> Option<String> {
+        let mut parts = args.splitn(2, ',');
+        let address = parts.next().and_then(parse_hex);
+        let len = parts.next().and_then(parse_hex);
+        match (address, len) {
+            (Some(address), Some(len)) => {
+                let address = address as u16;
+                let mut s = String::new();
+                for i in 0..len {
+                    let word = cpu.mem[address.wrapping_add((i / 2) as u16) as usize];
+                    let byte = if i % 2 == 0 { word >> 8 } else { word & 0xff };
+                    s.push_str(&format!("{:02x}", byte));
+                }
+                Some(s)
+            },
+            _ => None,
+        }
+    }
+
+    fn write_memory(cpu: &mut DCPU, args: &str) -> bool {
+        let mut parts = args.splitn(2, ':');
+        let header = parts.next().unwrap_or("");
+        let data = parts.next().and_then(decode_hex_bytes);
+        let mut header_parts = header.splitn(2, ',');
+        let address = header_parts.next().and_then(parse_hex);
+        let len = header_parts.next().and_then(parse_hex);
+        match (address, len, data) {
+            (Some(address), Some(len), Some(data)) => {
+                if data.len() != len {
+                    return false;
+                }
+                let address = address as u16;
+                for (i, b) in data.iter().enumerate() {
+                    let pos = address.wrapping_add((i / 2) as u16) as usize;
+                    cpu.mem[pos] = if i % 2 == 0 {
+                        (cpu.mem[pos] & 0x00ff) | ((*b as u16) << 8)
+                    } else {
+                        (cpu.mem[pos] & 0xff00) | (*b as u16)
+                    };
+                }
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn breakpoint(&mut self, cpu: &mut DCPU, args: &str, insert: bool) -> String {
+        let parts: Vec<&str> = args.split(',').collect();
+        if parts.len() < 3 {
+            return "E01".to_string();
+        }
+        let (btype, address, len) = match (parts[0].parse::<u8>(), parse_hex(parts[1]), parse_hex(parts[2])) {
+            (Ok(t), Some(a), Some(l)) => (t, a as u16, l as u16),
+            _ => return "E01".to_string(),
+        };
+        match btype {
+            // Software and hardware breakpoints are the same thing here
+            0 | 1 => {
+                if insert {
+                    cpu.add_breakpoint(address);
+                } else {
+                    cpu.remove_breakpoint(address);
+                }
+                "OK".to_string()
+            },
+            2 ... 4 => {
+                let key = (btype, address, len);
+                if insert {
+                    let kind = match btype {
+                        2 => WatchKind::Write,
+                        3 => WatchKind::Read,
+                        _ => WatchKind::ReadWrite,
+                    };
+                    // Length is in bytes, so round up to whole words
+                    let words = ((len as usize + 1) / 2).max(1) as u16;
+                    let id = cpu.add_watchpoint(address, address.saturating_add(words - 1), kind);
+                    self.watchpoints.insert(key, id);
+                } else if let Some(id) = self.watchpoints.remove(&key) {
+                    cpu.remove_watchpoint(id);
+                }
+                "OK".to_string()
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn features(args: &str) -> String {
+        // Format: target.xml:offset,length
+        let mut parts = args.splitn(2, ':');
+        let annex = parts.next().unwrap_or("");
+        if annex != "target.xml" {
+            return "E00".to_string();
+        }
+        let mut range = parts.next().unwrap_or("").splitn(2, ',');
+        let offset = range.next().and_then(parse_hex);
+        let length = range.next().and_then(parse_hex);
+        match (offset, length) {
+            (Some(offset), Some(length)) => {
+                if offset >= TARGET_XML.len() {
+                    return "l".to_string();
+                }
+                let end = (offset + length).min(TARGET_XML.len());
+                let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                format!("{}{}", prefix, &TARGET_XML[offset..end])
+            },
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn handle(&mut self, cpu: &mut DCPU, packet: &str) -> io::Result<Action> {
+        // Everything below slices packets by byte offsets
+        if !packet.is_ascii() {
+            return Ok(Action::Reply("E01".to_string()));
+        }
+        let (command, args) = if packet.is_empty() {
+            ("", "")
+        } else {
+            packet.split_at(1)
+        };
+        let response = match command {
+            "?" => "S05".to_string(),
+            "g" => {
+                (0..NUM_REGISTERS).map(|n| hex_u16(Self::register(cpu, n).unwrap())).collect()
+            },
+            "G" => {
+                if args.len() != NUM_REGISTERS * 4 {
+                    "E01".to_string()
+                } else {
+                    for n in 0..NUM_REGISTERS {
+                        if let Some(v) = parse_hex(&args[n * 4..n * 4 + 4]) {
+                            Self::set_register(cpu, n, v as u16);
+                        }
+                    }
+                    "OK".to_string()
+                }
+            },
+            "p" => {
+                match parse_hex(args).and_then(|n| Self::register(cpu, n)) {
+                    Some(v) => hex_u16(v),
+                    None => "E01".to_string(),
+                }
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(parse_hex);
+                let v = parts.next().and_then(parse_hex);
+                match (n, v) {
+                    (Some(n), Some(v)) if Self::set_register(cpu, n, v as u16) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            },
+            "m" => Self::read_memory(cpu, args).unwrap_or("E01".to_string()),
+            "M" => {
+                if Self::write_memory(cpu, args) {
+                    "OK".to_string()
+                } else {
+                    "E01".to_string()
+                }
+            },
+            "c" => self.resume(cpu, args, false),
+            "s" => self.resume(cpu, args, true),
+            "b" if args == "s" => self.resume_backwards(cpu, true),
+            "b" if args == "c" => self.resume_backwards(cpu, false),
+            "Z" => self.breakpoint(cpu, args, true),
+            "z" => self.breakpoint(cpu, args, false),
+            "H" => "OK".to_string(),
+            "k" => return Ok(Action::Close),
+            "D" => {
+                self.send_packet("OK")?;
+                return Ok(Action::Close);
+            },
+            "q" | "Q" | "v" => {
+                if packet.starts_with("qSupported") {
+                    "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string()
+                } else if packet.starts_with("qXfer:features:read:") {
+                    Self::features(&packet["qXfer:features:read:".len()..])
+                } else if packet == "QStartNoAckMode" {
+                    // The OK is the last packet that gets acked
+                    self.send_packet("OK")?;
+                    self.no_ack = true;
+                    return Ok(Action::Nothing);
+                } else if packet == "qAttached" {
+                    "1".to_string()
+                } else if packet == "qC" {
+                    "QC1".to_string()
+                } else if packet == "qfThreadInfo" {
+                    "m1".to_string()
+                } else if packet == "qsThreadInfo" {
+                    "l".to_string()
+                } else {
+                    String::new()
+                }
+            },
+            _ => String::new(),
+        };
+        Ok(Action::Reply(response))
+    }
+}
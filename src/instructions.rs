@@ -37,6 +37,116 @@ pub const HWN: usize = 0x10;
 pub const HWQ: usize = 0x11;
 pub const HWI: usize = 0x12;
 
-// Extended
-pub const OUT: usize = 0x13;
-pub const OUV: usize = 0x14;
+/// Revision of the DCPU-16 instruction set, shared by `DCPU`, the assembler and the disassembler.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Isa {
+    /// Notch's original 1.1: words are `bbbbbbaaaaaaoooo` with a 4-bit opcode, where `a` is the
+    /// target. `JSR` is the only non-basic instruction (`aaaaaaoooooo0000`). There is `O`
+    /// instead of `EX`, and no interrupts or hardware instructions.
+    V1_1,
+    /// 1.7, the default.
+    V1_7,
+}
+
+/// Opcodes of DCPU-16 1.1. Extensions (see `extensions`) use the same numbers as non-basic
+/// opcodes as they do as special opcodes in 1.7.
+pub mod v1_1 {
+    pub const SET: usize = 0x1;
+    pub const ADD: usize = 0x2;
+    pub const SUB: usize = 0x3;
+    pub const MUL: usize = 0x4;
+    pub const DIV: usize = 0x5;
+    pub const MOD: usize = 0x6;
+    pub const SHL: usize = 0x7;
+    pub const SHR: usize = 0x8;
+    pub const AND: usize = 0x9;
+    pub const BOR: usize = 0xa;
+    pub const XOR: usize = 0xb;
+    pub const IFE: usize = 0xc;
+    pub const IFN: usize = 0xd;
+    pub const IFG: usize = 0xe;
+    pub const IFB: usize = 0xf;
+
+    pub const JSR: usize = 0x01;
+}
+
+/// The 1.1 opcode of a 1.7 basic opcode, if the instruction exists in 1.1.
+pub fn basic_opcode_v1_1(opcode: usize) -> Option<usize> {
+    match opcode {
+        SET => Some(v1_1::SET),
+        ADD => Some(v1_1::ADD),
+        SUB => Some(v1_1::SUB),
+        MUL => Some(v1_1::MUL),
+        DIV => Some(v1_1::DIV),
+        MOD => Some(v1_1::MOD),
+        SHL => Some(v1_1::SHL),
+        SHR => Some(v1_1::SHR),
+        AND => Some(v1_1::AND),
+        BOR => Some(v1_1::BOR),
+        XOR => Some(v1_1::XOR),
+        IFE => Some(v1_1::IFE),
+        IFN => Some(v1_1::IFN),
+        IFG => Some(v1_1::IFG),
+        IFB => Some(v1_1::IFB),
+        _ => None,
+    }
+}
+
+/// The 1.7 opcode with the same meaning as a 1.1 basic opcode (the inverse of
+/// `basic_opcode_v1_1`).
+pub fn basic_opcode_from_v1_1(opcode: usize) -> Option<usize> {
+    (1..0x20).find(|&op| basic_opcode_v1_1(op) == Some(opcode))
+}
+
+/// The 1.1 non-basic opcode of a 1.7 special opcode, if the instruction exists in 1.1.
+pub fn special_opcode_v1_1(opcode: usize) -> Option<usize> {
+    match opcode {
+        JSR => Some(v1_1::JSR),
+        _ => None,
+    }
+}
+
+/// The 1.7 special opcode with the same meaning as a 1.1 non-basic opcode.
+pub fn special_opcode_from_v1_1(opcode: usize) -> Option<usize> {
+    (1..0x20).find(|&op| special_opcode_v1_1(op) == Some(opcode))
+}
+
+/// Whether `word` is `JSR a`.
+pub fn is_call(word: u16, isa: Isa) -> bool {
+    match isa {
+        Isa::V1_1 => word & 0x3ff == (v1_1::JSR << 4) as u16,
+        Isa::V1_7 => word & 0x3ff == (JSR << 5) as u16,
+    }
+}
+
+/// Whether `word` is `SET PC, POP`, the usual way to return from a subroutine.
+pub fn is_return(word: u16, isa: Isa) -> bool {
+    match isa {
+        Isa::V1_1 => word == 0x61c1,
+        Isa::V1_7 => word == 0x6381,
+    }
+}
+
+/// Whether `word` is one of the IF instructions.
+pub fn is_conditional(word: u16, isa: Isa) -> bool {
+    match isa {
+        Isa::V1_1 => word & 0xf >= 0xc,
+        Isa::V1_7 => {
+            let opcode = word & 0x1f;
+            opcode >= 0x10 && opcode <= 0x17
+        },
+    }
+}
+
+impl ::std::str::FromStr for Isa {
+    type Err = ();
+
+    /// Parses `1.1` or `1.7`.
+    fn from_str(s: &str) -> Result<Isa, ()> {
+        match s {
+            "1.1" => Ok(Isa::V1_1),
+            "1.7" => Ok(Isa::V1_7),
+            _ => Err(()),
+        }
+    }
+}
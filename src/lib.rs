@@ -1,5 +1,15 @@
 pub mod dcpu;
 pub mod instructions;
+pub mod extensions;
 pub mod assembler;
 pub mod disassembler;
 pub mod devices;
+pub mod symbols;
+pub mod gdb;
+pub mod snapshot;
+pub mod trace;
+pub mod profiler;
+pub mod coverage;
+pub mod bootrom;
+pub mod scheduler;
+pub mod machine;
@@ -0,0 +1,253 @@
+// Running a DCPU on a background thread.
+//
+// `Machine::spawn` moves a DCPU to a thread of its own, where it runs in real time (see
+// `scheduler`). The returned handle controls it over channels, so that GUI and network front-ends
+// can drive the emulator without sharing its thread:
+//
+//     let machine = Machine::spawn(cpu);
+//     machine.run();
+//     machine.key_event(KeyEvent::Press(0x61));
+//     let frame = machine.monitor_frame(false);
+//     let cpu = machine.shutdown().unwrap();
+//
+// The machine starts out paused. When execution stops (see `StopReason`), the machine pauses and
+// the reason can be picked up with `poll_stop`. Queries return `None` if the machine thread is
+// gone, which only happens if it panicked.
+
+use std::sync::mpsc::{self, Sender, Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+use dcpu::{self, DCPU, Device, DeviceError, DeviceRef, RunStatus, StopReason};
+use devices::keyboard_generic::DeviceKeyboardGeneric;
+use devices::monitor_lem1802::DeviceMonitorLEM1802;
+use scheduler::{Scheduler, Speed};
+
+/// A key going down or up, with a key code as in the generic keyboard spec.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    Press(u16),
+    Release(u16),
+}
+
+/// What the machine is doing, as returned by `Machine::status`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MachineStatus {
+    pub running: bool,
+    pub cycle: u64,
+    pub pc: u16,
+    /// Measured emulated frequency (see `Scheduler::frequency`).
+    pub frequency: Option<f64>,
+}
+
+enum Command {
+    Run,
+    Pause,
+    SetSpeed(Speed),
+    Step(Sender<RunStatus>),
+    ReadMemory(u16, usize, Sender<Vec<u16>>),
+    WriteMemory(u16, Vec<u16>),
+    Key(KeyEvent),
+    Reset(bool),
+    AddDevice(Box<Device>, Sender<Result<usize, DeviceError>>),
+    RemoveDevice(usize, Sender<Result<DeviceRef, DeviceError>>),
+    MonitorFrame(bool, Sender<Option<Vec<u8>>>),
+    Status(Sender<MachineStatus>),
+    Shutdown,
+}
+
+/// Handle to a DCPU running on a background thread.
+pub struct Machine {
+    commands: Sender<Command>,
+    stops: Receiver<StopReason>,
+    // The DCPU is boxed, since it is large (mostly memory) and is moved between threads
+    thread: JoinHandle<Box<DCPU>>,
+}
+
+impl Machine {
+    /// Moves `cpu` to a new thread, paused. Unless the device batch has been changed from the
+    /// default, devices are run in batches of `dcpu::REALTIME_DEVICE_BATCH` cycles.
+    pub fn spawn(mut cpu: DCPU) -> Machine {
+        let (commands, command_receiver) = mpsc::channel();
+        let (stop_sender, stops) = mpsc::channel();
+        if cpu.device_batch() == 1 {
+            cpu.set_device_batch(dcpu::REALTIME_DEVICE_BATCH);
+        }
+        let cpu = Box::new(cpu);
+        let thread = thread::spawn(move || serve(cpu, command_receiver, stop_sender));
+        Machine {
+            commands: commands,
+            stops: stops,
+            thread: thread,
+        }
+    }
+
+    /// Starts running in real time, or continues after a pause.
+    pub fn run(&self) -> () {
+        self.send(Command::Run);
+    }
+
+    pub fn pause(&self) -> () {
+        self.send(Command::Pause);
+    }
+
+    pub fn set_speed(&self, speed: Speed) -> () {
+        self.send(Command::SetSpeed(speed));
+    }
+
+    /// Pauses the machine and executes a single instruction.
+    pub fn step(&self) -> Option<RunStatus> {
+        self.query(Command::Step)
+    }
+
+    /// Reads `len` words starting at `address` (wrapping around the end of memory).
+    pub fn read_memory(&self, address: u16, len: usize) -> Option<Vec<u16>> {
+        self.query(|reply| Command::ReadMemory(address, len, reply))
+    }
+
+    pub fn write_memory(&self, address: u16, words: &[u16]) -> () {
+        self.send(Command::WriteMemory(address, words.to_vec()));
+    }
+
+    /// Passes a key event to the first generic keyboard. Ignored if there is no keyboard.
+    pub fn key_event(&self, event: KeyEvent) -> () {
+        self.send(Command::Key(event));
+    }
+
+    /// Presses the reset button (see `DCPU::soft_reset`). The machine keeps running, or stays
+    /// paused.
+    pub fn reset(&self, keep_memory: bool) -> () {
+        self.send(Command::Reset(keep_memory));
+    }
+
+    /// Attaches a device while the machine runs (see `DCPU::add_device`).
+    pub fn add_device(&self, device: Box<Device>) -> Option<Result<usize, DeviceError>> {
+        self.query(|reply| Command::AddDevice(device, reply))
+    }
+
+    /// Detaches a device while the machine runs (see `DCPU::remove_device`).
+    pub fn remove_device(&self, index: usize) -> Option<Result<DeviceRef, DeviceError>> {
+        self.query(|reply| Command::RemoveDevice(index, reply))
+    }
+
+    /// RGB pixels of the first LEM1802 monitor (see `DeviceMonitorLEM1802::data`). The inner
+    /// `None` means that there is no monitor.
+    pub fn monitor_frame(&self, blinkout: bool) -> Option<Option<Vec<u8>>> {
+        self.query(|reply| Command::MonitorFrame(blinkout, reply))
+    }
+
+    pub fn status(&self) -> Option<MachineStatus> {
+        self.query(Command::Status)
+    }
+
+    /// Why execution stopped, if it has stopped since the last call.
+    pub fn poll_stop(&self) -> Option<StopReason> {
+        self.stops.try_recv().ok()
+    }
+
+    /// Waits until execution stops.
+    pub fn wait_stop(&self) -> Option<StopReason> {
+        self.stops.recv().ok()
+    }
+
+    /// Stops the thread and hands back the DCPU.
+    pub fn shutdown(self) -> Option<DCPU> {
+        self.send(Command::Shutdown);
+        self.thread.join().ok().map(|cpu| *cpu)
+    }
+
+    fn send(&self, command: Command) -> () {
+        // If the thread is gone, there is nothing left to control
+        self.commands.send(command).ok();
+    }
+
+    fn query<T, F: FnOnce(Sender<T>) -> Command>(&self, command: F) -> Option<T> {
+        let (reply, receiver) = mpsc::channel();
+        self.commands.send(command(reply)).ok()?;
+        receiver.recv().ok()
+    }
+}
+
+fn serve(mut cpu: Box<DCPU>, commands: Receiver<Command>, stops: Sender<StopReason>) -> Box<DCPU> {
+    let mut scheduler = Scheduler::new();
+    scheduler.pause();
+    loop {
+        // Block while paused, since there is nothing else to do
+        let command = if scheduler.is_paused() {
+            match commands.recv() {
+                Ok(c) => Some(c),
+                Err(_) => return cpu,
+            }
+        } else {
+            match commands.try_recv() {
+                Ok(c) => Some(c),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return cpu,
+            }
+        };
+
+        match command {
+            Some(Command::Shutdown) => return cpu,
+            Some(command) => {
+                handle(&mut cpu, &mut scheduler, command);
+                continue;
+            },
+            None => {},
+        }
+
+        if let Some(reason) = scheduler.step(&mut cpu).stop_reason() {
+            scheduler.pause();
+            stops.send(reason).ok();
+        }
+        scheduler.wait();
+    }
+}
+
+fn handle(cpu: &mut DCPU, scheduler: &mut Scheduler, command: Command) -> () {
+    // Replies are dropped if the handle stopped waiting for them
+    match command {
+        Command::Run => scheduler.resume(),
+        Command::Pause => scheduler.pause(),
+        Command::SetSpeed(speed) => scheduler.set_speed(speed),
+        Command::Step(reply) => {
+            scheduler.pause();
+            reply.send(cpu.tick()).ok();
+        },
+        Command::ReadMemory(address, len, reply) => {
+            let words = (0..len).map(|i| cpu.mem[(address as usize + i) % dcpu::MEMORY_SIZE]).collect();
+            reply.send(words).ok();
+        },
+        Command::WriteMemory(address, words) => {
+            for (i, word) in words.into_iter().enumerate() {
+                cpu.mem[(address as usize + i) % dcpu::MEMORY_SIZE] = word;
+            }
+        },
+        Command::Key(event) => {
+            cpu.with_device(|keyboard: &mut DeviceKeyboardGeneric, cpu| {
+                match event {
+                    KeyEvent::Press(key) => keyboard.register_press(cpu, key),
+                    KeyEvent::Release(key) => keyboard.register_release(cpu, key),
+                }
+            });
+        },
+        Command::Reset(keep_memory) => cpu.soft_reset(keep_memory),
+        Command::AddDevice(device, reply) => {
+            reply.send(cpu.add_device(device)).ok();
+        },
+        Command::RemoveDevice(index, reply) => {
+            reply.send(cpu.remove_device(index)).ok();
+        },
+        Command::MonitorFrame(blinkout, reply) => {
+            let frame = cpu.with_device(|monitor: &mut DeviceMonitorLEM1802, cpu| monitor.data(cpu, blinkout));
+            reply.send(frame).ok();
+        },
+        Command::Status(reply) => {
+            reply.send(MachineStatus {
+                running: !scheduler.is_paused(),
+                cycle: cpu.cycle(),
+                pc: cpu.pc,
+                frequency: scheduler.frequency(),
+            }).ok();
+        },
+        Command::Shutdown => {},
+    }
+}
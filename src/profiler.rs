@@ -0,0 +1,218 @@
+// Cycle profiler.
+//
+// Collects the number of cycles spent on, and the number of times execution passed, every address.
+// Cycles are also attributed to call frames, which are tracked by following `JSR` (call),
+// `SET PC, POP` (return), interrupts (call to IA) and `RFI` (return). A frame is identified by the
+// address of the subroutine, so with a symbol map from the assembler they show up as labels.
+//
+// Besides a hot-spot report, the profile can be written as folded stacks, which is the input
+// format of flamegraph tools:
+//
+//     start;draw_screen;put_char 1520
+//
+// Profiling is started with `DCPU::start_profiling`.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use dcpu::DCPU;
+use instructions::{self, Isa, RFI};
+use symbols::SymbolMap;
+
+// A node in the call tree. The root node is the code that was running when profiling started.
+struct Frame {
+    parent: usize,
+    function: u16,
+    cycles: u64,
+}
+
+/// Cycles and hits of a single address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AddressStats {
+    pub address: u16,
+    pub hits: u64,
+    pub cycles: u64,
+}
+
+/// Cycles attributed to a subroutine, identified by its address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FunctionStats {
+    pub address: u16,
+    pub calls: u64,
+    /// Cycles spent in the subroutine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in the subroutine and everything it called.
+    pub total_cycles: u64,
+}
+
+pub struct Profiler {
+    hits: Vec<u64>,
+    cycles: Vec<u64>,
+    total_cycles: u64,
+    frames: Vec<Frame>,
+    children: HashMap<(usize, u16), usize>,
+    calls: HashMap<u16, u64>,
+    current: usize,
+}
+
+impl Profiler {
+    /// `entry` names the root frame, usually the PC when profiling starts.
+    pub fn new(entry: u16) -> Profiler {
+        Profiler {
+            hits: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            total_cycles: 0,
+            frames: vec![Frame { parent: 0, function: entry, cycles: 0 }],
+            children: HashMap::new(),
+            calls: HashMap::new(),
+            current: 0,
+        }
+    }
+
+    /// Attributes `cycles` to `pc` without looking at the instruction (used for skipped
+    /// instructions).
+    pub fn record(&mut self, pc: u16, cycles: u64) {
+        self.hits[pc as usize] += 1;
+        self.cycles[pc as usize] += cycles;
+        self.frames[self.current].cycles += cycles;
+        self.total_cycles += cycles;
+    }
+
+    /// Attributes `cycles` to the instruction `word` at `pc`, and follows calls and returns.
+    /// Expects `cpu` to be in the state right after the instruction was executed.
+    pub fn record_instruction(&mut self, cpu: &DCPU, pc: u16, word: u16, cycles: u64) {
+        self.record(pc, cycles);
+
+        let isa = cpu.isa();
+        let is_rfi = isa == Isa::V1_7 && word & 0x3ff == (RFI << 5) as u16;
+        let interrupt = cpu.interrupt_taken().is_some() && cpu.ia != 0;
+        if instructions::is_call(word, isa) {
+            // If an interrupt was taken right after, the call target is what the interrupt pushed
+            let target = if interrupt {
+                cpu.mem[cpu.sp.wrapping_add(1) as usize]
+            } else {
+                cpu.pc
+            };
+            self.call(target);
+        } else if instructions::is_return(word, isa) || is_rfi {
+            self.ret();
+        }
+        if interrupt {
+            let handler = cpu.pc;
+            self.call(handler);
+        }
+    }
+
+    fn call(&mut self, function: u16) {
+        let parent = self.current;
+        let next = self.frames.len();
+        let child = *self.children.entry((parent, function)).or_insert(next);
+        if child == next {
+            self.frames.push(Frame { parent: parent, function: function, cycles: 0 });
+        }
+        *self.calls.entry(function).or_insert(0) += 1;
+        self.current = child;
+    }
+
+    fn ret(&mut self) {
+        // Returning from the root frame is ignored, since there is nothing to attribute it to
+        self.current = self.frames[self.current].parent;
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Addresses that were executed, with the most cycles first.
+    pub fn address_stats(&self) -> Vec<AddressStats> {
+        let mut stats: Vec<AddressStats> = (0..self.hits.len())
+            .filter(|&a| self.hits[a] > 0)
+            .map(|a| AddressStats { address: a as u16, hits: self.hits[a], cycles: self.cycles[a] })
+            .collect();
+        stats.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.address.cmp(&b.address)));
+        stats
+    }
+
+    /// Subroutines (and the root frame), with the most total cycles first.
+    pub fn function_stats(&self) -> Vec<FunctionStats> {
+        let mut stats: HashMap<u16, FunctionStats> = HashMap::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            let mut stack = self.stack(i);
+            // Recursive functions only count the cycles of a frame once
+            stack.sort();
+            stack.dedup();
+            for f in stack {
+                let s = stats.entry(f).or_insert(FunctionStats {
+                    address: f,
+                    calls: self.calls.get(&f).cloned().unwrap_or(0),
+                    self_cycles: 0,
+                    total_cycles: 0,
+                });
+                s.total_cycles += frame.cycles;
+                if f == frame.function {
+                    s.self_cycles += frame.cycles;
+                }
+            }
+        }
+        let mut stats: Vec<FunctionStats> = stats.into_iter().map(|(_, s)| s).collect();
+        stats.sort_by(|a, b| b.total_cycles.cmp(&a.total_cycles).then(a.address.cmp(&b.address)));
+        stats
+    }
+
+    // Functions from the root down to frame `i`
+    fn stack(&self, mut i: usize) -> Vec<u16> {
+        let mut stack = vec![self.frames[i].function];
+        while i != 0 {
+            i = self.frames[i].parent;
+            stack.push(self.frames[i].function);
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// Writes a report of the `limit` hottest addresses and subroutines.
+    pub fn write_report<W: Write>(&self, writer: &mut W, symbols: Option<&SymbolMap>,
+                                  limit: usize) -> io::Result<()> {
+        let total = self.total_cycles.max(1) as f64;
+        writeln!(writer, "Total cycles: {}", self.total_cycles)?;
+        writeln!(writer, "")?;
+        writeln!(writer, "{:>12} {:>6} {:>12} {:>8}  {}", "cycles", "%", "hits", "address", "location")?;
+        for s in self.address_stats().iter().take(limit) {
+            writeln!(writer, "{:>12} {:>6.2} {:>12}     {:04x}  {}", s.cycles,
+                     100.0 * s.cycles as f64 / total, s.hits, s.address,
+                     describe(symbols, s.address))?;
+        }
+        writeln!(writer, "")?;
+        writeln!(writer, "{:>12} {:>6} {:>12} {:>6} {:>8}  {}", "total", "%", "self", "%", "calls", "subroutine")?;
+        for s in self.function_stats().iter().take(limit) {
+            writeln!(writer, "{:>12} {:>6.2} {:>12} {:>6.2} {:>8}  {}", s.total_cycles,
+                     100.0 * s.total_cycles as f64 / total, s.self_cycles,
+                     100.0 * s.self_cycles as f64 / total, s.calls, describe(symbols, s.address))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the call stacks in folded format, one line per distinct stack.
+    pub fn write_folded<W: Write>(&self, writer: &mut W, symbols: Option<&SymbolMap>) -> io::Result<()> {
+        let mut lines: Vec<(String, u64)> = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            if frame.cycles == 0 {
+                continue;
+            }
+            let names: Vec<String> = self.stack(i).iter().map(|&f| describe(symbols, f)).collect();
+            lines.push((names.join(";"), frame.cycles));
+        }
+        lines.sort();
+        for (stack, cycles) in lines {
+            writeln!(writer, "{} {}", stack, cycles)?;
+        }
+        Ok(())
+    }
+}
+
+fn describe(symbols: Option<&SymbolMap>, address: u16) -> String {
+    match symbols {
+        Some(s) => s.describe(address),
+        None => format!("0x{:04x}", address),
+    }
+}
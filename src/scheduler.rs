@@ -0,0 +1,188 @@
+// Real-time pacing.
+//
+// The scheduler decides how many cycles `DCPU::run` should execute, so that emulated time keeps up
+// with wall-clock time (scaled by a speed multiplier). The number of cycles that are due is
+// computed from a fixed starting point, so errors from sleeping too long or too short do not add
+// up over time. If the host can not keep up, the scheduler falls behind by at most `MAX_LAG`
+// instead of trying to catch up all at once.
+//
+//     let mut scheduler = Scheduler::new();
+//     loop {
+//         if scheduler.step(&mut cpu).is_stopped() {
+//             break;
+//         }
+//         scheduler.wait();
+//     }
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dcpu::{self, DCPU, RunStatus};
+
+// How far behind real time the emulation can fall before the backlog is dropped
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+// Cycles per step when running at unlimited speed
+const UNLIMITED_CYCLES: u64 = (dcpu::CYCLE_HZ / 10) as u64;
+
+// How often the measured frequency is updated
+const MEASURE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How fast emulated time runs compared to real time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Speed {
+    /// 1.0 is real time (`CYCLE_HZ`), 2.0 twice as fast, and so on.
+    Factor(f64),
+    /// As fast as possible.
+    Unlimited,
+}
+
+pub struct Scheduler {
+    speed: Speed,
+    paused: bool,
+    slice: Duration,
+    // Cycles are due from this point on
+    origin: Instant,
+    // Cycles given to `DCPU::run` since `origin`
+    given: u64,
+    last_step: Instant,
+    measure_start: Instant,
+    measure_cycle: Option<u64>,
+    frequency: Option<f64>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        let now = Instant::now();
+        Scheduler {
+            speed: Speed::Factor(1.0),
+            paused: false,
+            slice: Duration::from_millis(10),
+            origin: now,
+            given: 0,
+            last_step: now,
+            measure_start: now,
+            measure_cycle: None,
+            frequency: None,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.restart();
+    }
+
+    /// How long `wait` sleeps between steps (10 ms by default).
+    pub fn set_slice(&mut self, slice: Duration) {
+        self.slice = slice;
+    }
+
+    pub fn slice(&self) -> Duration {
+        self.slice
+    }
+
+    /// Stops giving cycles to the DCPU. Emulated time does not advance while paused.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.frequency = None;
+    }
+
+    /// Continues from where `pause` stopped, without trying to catch up.
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.restart();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn restart(&mut self) {
+        let now = Instant::now();
+        self.origin = now;
+        self.given = 0;
+        self.measure_start = now;
+        self.measure_cycle = None;
+    }
+
+    /// Number of cycles that should be executed now to keep up.
+    pub fn due_cycles(&mut self) -> u64 {
+        let factor = match (self.paused, self.speed) {
+            (true, _) => return 0,
+            (false, Speed::Unlimited) => return UNLIMITED_CYCLES,
+            (false, Speed::Factor(f)) => f,
+        };
+        let hz = dcpu::CYCLE_HZ as f64 * factor;
+        let target = (duration_secs(self.origin.elapsed()) * hz) as u64;
+        let max_lag = (duration_secs(MAX_LAG) * hz) as u64;
+        if target > self.given + max_lag {
+            // Too far behind, so forget about the cycles that were missed
+            self.given = target - max_lag;
+        }
+        target.saturating_sub(self.given)
+    }
+
+    /// Runs the DCPU for the cycles that are due (see `due_cycles`).
+    pub fn step(&mut self, cpu: &mut DCPU) -> RunStatus {
+        let cycles = self.due_cycles();
+        self.run(cpu, cycles)
+    }
+
+    /// Runs the DCPU for `cycles`, which should be at most `due_cycles`. Useful when the caller
+    /// needs to run fewer cycles, for example to enforce a limit.
+    pub fn run(&mut self, cpu: &mut DCPU, cycles: u64) -> RunStatus {
+        self.last_step = Instant::now();
+        self.measure(cpu);
+        if cycles == 0 {
+            return RunStatus::CycleBudget;
+        }
+        self.given += cycles;
+        cpu.run(cycles as usize)
+    }
+
+    // Updates the measured frequency if enough time has passed
+    fn measure(&mut self, cpu: &DCPU) {
+        let cycle = match self.measure_cycle {
+            // The cycle count goes backwards on hard_reset, step_back and snapshot restores
+            Some(c) if c <= cpu.cycle() => c,
+            _ => {
+                self.measure_start = self.last_step;
+                self.measure_cycle = Some(cpu.cycle());
+                return;
+            },
+        };
+        let elapsed = self.last_step.duration_since(self.measure_start);
+        if elapsed >= MEASURE_INTERVAL {
+            self.frequency = Some((cpu.cycle() - cycle) as f64 / duration_secs(elapsed));
+            self.measure_start = self.last_step;
+            self.measure_cycle = Some(cpu.cycle());
+        }
+    }
+
+    /// Sleeps until the next step is due. Returns right away at unlimited speed.
+    pub fn wait(&self) {
+        if !self.paused && self.speed == Speed::Unlimited {
+            return;
+        }
+        let spent = self.last_step.elapsed();
+        if spent < self.slice {
+            thread::sleep(self.slice - spent);
+        }
+    }
+
+    /// Emulated cycles per second over the last half second or so, as actually achieved. `None`
+    /// until there has been enough time to measure, and while paused.
+    pub fn frequency(&self) -> Option<f64> {
+        self.frequency
+    }
+}
+
+fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
@@ -0,0 +1,145 @@
+// Helpers for serializing machine state into snapshots.
+//
+// State is stored as a flat sequence of 16-bit words, which is what both the DCPU and the devices
+// naturally deal with. Snapshot files store these words with the most significant byte first, same
+// as binary program files.
+
+use std::io::{self, Read, Write};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+pub struct StateWriter {
+    words: Vec<u16>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            words: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, v: u16) {
+        self.words.push(v);
+    }
+
+    pub fn push_bool(&mut self, v: bool) {
+        self.words.push(v as u16);
+    }
+
+    pub fn push_u32(&mut self, v: u32) {
+        self.words.push((v >> 16) as u16);
+        self.words.push(v as u16);
+    }
+
+    pub fn push_u64(&mut self, v: u64) {
+        self.push_u32((v >> 32) as u32);
+        self.push_u32(v as u32);
+    }
+
+    pub fn push_option(&mut self, v: Option<u16>) {
+        self.push_bool(v.is_some());
+        self.push(v.unwrap_or(0));
+    }
+
+    /// Pushes a length-prefixed slice of words.
+    pub fn push_slice(&mut self, v: &[u16]) {
+        self.push_u32(v.len() as u32);
+        self.words.extend_from_slice(v);
+    }
+
+    pub fn words(&self) -> &[u16] {
+        &self.words[..]
+    }
+
+    pub fn into_words(self) -> Vec<u16> {
+        self.words
+    }
+}
+
+pub struct StateReader<'a> {
+    words: &'a [u16],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(words: &'a [u16]) -> StateReader<'a> {
+        StateReader {
+            words: words,
+            pos: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> io::Result<u16> {
+        match self.words.get(self.pos) {
+            Some(v) => {
+                self.pos += 1;
+                Ok(*v)
+            },
+            None => Err(invalid("Unexpected end of state")),
+        }
+    }
+
+    pub fn next_bool(&mut self) -> io::Result<bool> {
+        Ok(self.next()? != 0)
+    }
+
+    pub fn next_u32(&mut self) -> io::Result<u32> {
+        let hi = self.next()? as u32;
+        let lo = self.next()? as u32;
+        Ok((hi << 16) | lo)
+    }
+
+    pub fn next_u64(&mut self) -> io::Result<u64> {
+        let hi = self.next_u32()? as u64;
+        let lo = self.next_u32()? as u64;
+        Ok((hi << 32) | lo)
+    }
+
+    pub fn next_option(&mut self) -> io::Result<Option<u16>> {
+        let present = self.next_bool()?;
+        let v = self.next()?;
+        Ok(if present { Some(v) } else { None })
+    }
+
+    /// Reads a slice written by `StateWriter::push_slice`.
+    pub fn next_slice(&mut self) -> io::Result<&'a [u16]> {
+        let len = self.next_u32()? as usize;
+        if self.pos + len > self.words.len() {
+            return Err(invalid("Unexpected end of state"));
+        }
+        let s = &self.words[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(s)
+    }
+
+    /// Fails if there are words left, which means the state was not written by the same kind of
+    /// device or version.
+    pub fn finish(&self) -> io::Result<()> {
+        if self.pos == self.words.len() {
+            Ok(())
+        } else {
+            Err(invalid("Unexpected trailing state"))
+        }
+    }
+}
+
+pub fn write_words<W: Write>(writer: &mut W, words: &[u16]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(words.len() * 2);
+    for w in words {
+        bytes.push((w >> 8) as u8);
+        bytes.push((w & 0xff) as u8);
+    }
+    writer.write_all(&bytes)
+}
+
+pub fn read_words<R: Read>(reader: &mut R) -> io::Result<Vec<u16>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() % 2 != 0 {
+        return Err(invalid("Odd number of bytes"));
+    }
+    Ok(bytes.chunks(2).map(|c| ((c[0] as u16) << 8) | (c[1] as u16)).collect())
+}
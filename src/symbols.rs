@@ -0,0 +1,200 @@
+// Symbol maps tie label names to addresses, so that tools working on assembled binaries (such as
+// the debugger) can refer to labels instead of raw addresses. They can also map addresses back to
+// the lines of the assembly source.
+//
+// The file format is plain text with one record per line:
+//
+//     ; Comments start with a semicolon
+//     source program.asm
+//     label 0x0000 start
+//     label 0x0012 loop
+//     line 0x0000 0x0002 3
+//     data 0x0020 0x0024 17
+//
+// `line` (instructions) and `data` records give the words [start, end) that a source line
+// assembled to. Line numbers in the file start at 1. Unknown record types are ignored, so that the
+// format can be extended.
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use assembler::PCPU;
+
+/// Words produced by a single source line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: u16,
+    /// One past the last word.
+    pub end: u16,
+    /// Zero-based line number, same as the assembler uses.
+    pub line: usize,
+    /// Whether the line holds data (DAT, DAF) rather than an instruction.
+    pub data: bool,
+}
+
+pub struct SymbolMap {
+    // Sorted by address
+    labels: Vec<(String, u16)>,
+    // Sorted by address
+    lines: Vec<LineRange>,
+    source: Option<String>,
+}
+
+impl SymbolMap {
+    pub fn new() -> SymbolMap {
+        SymbolMap {
+            labels: Vec::new(),
+            lines: Vec::new(),
+            source: None,
+        }
+    }
+
+    /// Takes the labels and line ranges from an assembled program.
+    pub fn from_assembler(cpu: &PCPU) -> SymbolMap {
+        let lines = cpu.line_ranges().iter().map(|&(start, end, line)| LineRange {
+            start: start,
+            end: end,
+            line: line,
+            data: cpu.is_data_line(line),
+        }).collect();
+        SymbolMap {
+            labels: cpu.labels(),
+            lines: lines,
+            source: None,
+        }
+    }
+
+    /// Path of the assembly source, if known.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_ref().map(|s| &s[..])
+    }
+
+    pub fn set_source(&mut self, path: &str) {
+        self.source = Some(path.to_string());
+    }
+
+    /// Line ranges sorted by address.
+    pub fn lines(&self) -> &[LineRange] {
+        &self.lines[..]
+    }
+
+    /// The source line that produced the word at `address`.
+    pub fn line_at(&self, address: u16) -> Option<&LineRange> {
+        self.lines.iter().find(|l| address >= l.start && address < l.end)
+    }
+
+    pub fn add_label(&mut self, name: &str, address: u16) {
+        self.labels.push((name.to_string(), address));
+        self.labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    }
+
+    /// Labels sorted by address.
+    pub fn labels(&self) -> &[(String, u16)] {
+        &self.labels[..]
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|l| l.0 == name).map(|l| l.1)
+    }
+
+    /// Label defined exactly at `address`.
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.iter().find(|l| l.1 == address).map(|l| &l.0[..])
+    }
+
+    /// Closest label at or before `address`, together with the offset from it.
+    pub fn nearest_label(&self, address: u16) -> Option<(&str, u16)> {
+        self.labels.iter().rev().find(|l| l.1 <= address).map(|l| (&l.0[..], address - l.1))
+    }
+
+    /// Formats an address as `label+offset` if possible, or as a plain hexadecimal otherwise.
+    pub fn describe(&self, address: u16) -> String {
+        match self.nearest_label(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("0x{:04x}", address),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<SymbolMap> {
+        let file = File::open(path)?;
+        let mut map = SymbolMap::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let content = match line.find(';') {
+                Some(p) => &line[..p],
+                None => &line[..],
+            };
+            let parts: Vec<&str> = content.split_whitespace().collect();
+            if parts.is_empty() {
+                continue;
+            }
+            match parts[0] {
+                "label" => {
+                    if parts.len() != 3 {
+                        return Err(invalid_line(i));
+                    }
+                    let address = match parse_address(parts[1]) {
+                        Some(a) => a,
+                        None => return Err(invalid_line(i)),
+                    };
+                    map.labels.push((parts[2].to_string(), address));
+                },
+                "line" | "data" => {
+                    if parts.len() != 4 {
+                        return Err(invalid_line(i));
+                    }
+                    let line = match parts[3].parse::<usize>() {
+                        Ok(n) if n > 0 => n - 1,
+                        _ => return Err(invalid_line(i)),
+                    };
+                    match (parse_address(parts[1]), parse_address(parts[2])) {
+                        (Some(start), Some(end)) => map.lines.push(LineRange {
+                            start: start,
+                            end: end,
+                            line: line,
+                            data: parts[0] == "data",
+                        }),
+                        _ => return Err(invalid_line(i)),
+                    }
+                },
+                "source" => {
+                    map.source = Some(content.trim()["source".len()..].trim().to_string());
+                },
+                _ => {},
+            }
+        }
+        map.labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        map.lines.sort_by_key(|l| l.start);
+        Ok(map)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "; dcpu16 symbol map")?;
+        if let Some(ref source) = self.source {
+            writeln!(file, "source {}", source)?;
+        }
+        for &(ref name, address) in self.labels.iter() {
+            writeln!(file, "label 0x{:04x} {}", address, name)?;
+        }
+        for l in self.lines.iter() {
+            let kind = if l.data { "data" } else { "line" };
+            writeln!(file, "{} 0x{:04x} 0x{:04x} {}", kind, l.start, l.end, l.line + 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_address(s: &str) -> Option<u16> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u16::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn invalid_line(i: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid symbol map record on line {}", i + 1))
+}
@@ -0,0 +1,275 @@
+// Execution traces.
+//
+// A trace holds one record per `DCPU::tick`, with the instruction that was executed and all the
+// changes it made. Traces are stored as a header followed by records, all made up of 16-bit words
+// with the most significant byte first (same as binary program files). The header is the magic,
+// the format version and the instruction set (0x0101 for 1.1, 0x0107 for 1.7). Each record is laid
+// out as:
+//
+//     pc
+//     instruction length, followed by the instruction words
+//     changed register mask (bit i is set if register i changed), followed by the new values
+//     write count, followed by (address, value) pairs
+//     interrupt count, followed by the messages of interrupts taken
+//     total cycle count (four words)
+//
+// Registers are numbered as in `REGISTER_NAMES`. Memory written directly by devices is not part of
+// the trace.
+
+use std::io::{self, Read, Write};
+
+use instructions::Isa;
+use snapshot::write_words;
+
+const TRACE_MAGIC: &'static [u8] = b"DCPU16TR";
+const TRACE_VERSION: u16 = 2;
+
+pub const NUM_REGISTERS: usize = 12;
+
+pub const REGISTER_NAMES: [&'static str; NUM_REGISTERS] = [
+    "A", "B", "C", "X", "Y", "Z", "I", "J", "PC", "SP", "EX", "IA",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Address of the executed instruction.
+    pub pc: u16,
+    /// The instruction words, including next-word operands.
+    pub instruction: Vec<u16>,
+    /// Registers that changed, as (register number, new value).
+    pub registers: Vec<(usize, u16)>,
+    /// Memory writes made by the instruction (and by interrupt handling), as (address, value).
+    pub writes: Vec<(u16, u16)>,
+    /// Messages of interrupts that were taken after the instruction.
+    pub interrupts: Vec<u16>,
+    /// Total number of cycles since tracing started, after this instruction.
+    pub cycle: u64,
+}
+
+impl TraceRecord {
+    /// Builds a record from the registers before and after the instruction, both given in the
+    /// order of `REGISTER_NAMES`.
+    pub fn new(before: &[u16; NUM_REGISTERS], after: &[u16; NUM_REGISTERS], instruction: &[u16],
+               writes: &[(u16, u16)], interrupts: &[u16], cycle: u64) -> TraceRecord {
+        TraceRecord {
+            pc: before[8],
+            instruction: instruction.to_vec(),
+            registers: (0..NUM_REGISTERS).filter(|&i| before[i] != after[i])
+                                         .map(|i| (i, after[i])).collect(),
+            writes: writes.to_vec(),
+            interrupts: interrupts.to_vec(),
+            cycle: cycle,
+        }
+    }
+
+    fn words(&self) -> Vec<u16> {
+        let mut words = Vec::with_capacity(16);
+        words.push(self.pc);
+        words.push(self.instruction.len() as u16);
+        words.extend_from_slice(&self.instruction);
+        let mask = self.registers.iter().fold(0u16, |m, &(i, _)| m | (1 << i));
+        words.push(mask);
+        for &(_, v) in self.registers.iter() {
+            words.push(v);
+        }
+        words.push(self.writes.len() as u16);
+        for &(address, value) in self.writes.iter() {
+            words.push(address);
+            words.push(value);
+        }
+        words.push(self.interrupts.len() as u16);
+        words.extend_from_slice(&self.interrupts);
+        for i in 0..4 {
+            words.push((self.cycle >> (48 - 16 * i)) as u16);
+        }
+        words
+    }
+}
+
+/// Writes trace records to a stream. Used by `DCPU::start_trace`.
+pub struct TraceWriter {
+    writer: Box<Write + Send>,
+    cycle: u64,
+    error: Option<io::Error>,
+}
+
+impl TraceWriter {
+    /// Writes the header, for a program that uses `isa`.
+    pub fn new(mut writer: Box<Write + Send>, isa: Isa) -> io::Result<TraceWriter> {
+        writer.write_all(TRACE_MAGIC)?;
+        let isa = match isa {
+            Isa::V1_1 => 0x0101,
+            Isa::V1_7 => 0x0107,
+        };
+        write_words(&mut writer, &[TRACE_VERSION, isa])?;
+        Ok(TraceWriter {
+            writer: writer,
+            cycle: 0,
+            error: None,
+        })
+    }
+
+    /// Total cycles recorded so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Advances the cycle count by `cycles` and writes a record. Write errors are kept until
+    /// `finish`, and nothing more is written after one.
+    pub fn record(&mut self, before: &[u16; NUM_REGISTERS], after: &[u16; NUM_REGISTERS],
+                  instruction: &[u16], writes: &[(u16, u16)], interrupts: &[u16], cycles: u64) {
+        self.cycle += cycles;
+        if self.error.is_some() {
+            return;
+        }
+        let record = TraceRecord::new(before, after, instruction, writes, interrupts, self.cycle);
+        if let Err(e) = write_words(&mut self.writer, &record.words()) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Flushes the stream, and reports any error that happened while writing records.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
+
+/// Reads the records of a trace, one at a time.
+pub struct TraceReader<R: Read> {
+    reader: R,
+    isa: Isa,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<TraceReader<R>> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[..] != TRACE_MAGIC {
+            return Err(invalid("Not a DCPU-16 trace"));
+        }
+        let version = read_word(&mut reader)?;
+        if version != TRACE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Unsupported trace version {}", version)));
+        }
+        let isa = match read_word(&mut reader)? {
+            0x0101 => Isa::V1_1,
+            0x0107 => Isa::V1_7,
+            _ => return Err(invalid("Invalid instruction set in trace")),
+        };
+        Ok(TraceReader {
+            reader: reader,
+            isa: isa,
+        })
+    }
+
+    /// Instruction set of the traced program.
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Returns `None` at the end of the trace.
+    pub fn next_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut first = [0u8; 2];
+        let n = self.reader.read(&mut first)?;
+        if n == 0 {
+            return Ok(None);
+        } else if n == 1 {
+            self.reader.read_exact(&mut first[1..])?;
+        }
+        let pc = ((first[0] as u16) << 8) | (first[1] as u16);
+
+        let len = self.next()? as usize;
+        if len == 0 || len > 3 {
+            return Err(invalid("Invalid instruction length in trace"));
+        }
+        let instruction = self.words(len)?;
+        let mask = self.next()?;
+        let mut registers = Vec::new();
+        for i in 0..NUM_REGISTERS {
+            if mask & (1 << i) != 0 {
+                registers.push((i, self.next()?));
+            }
+        }
+        let n_writes = self.next()? as usize;
+        let mut writes = Vec::with_capacity(n_writes);
+        for _ in 0..n_writes {
+            let address = self.next()?;
+            let value = self.next()?;
+            writes.push((address, value));
+        }
+        let n_interrupts = self.next()? as usize;
+        let interrupts = self.words(n_interrupts)?;
+        let cycle = self.words(4)?.iter().fold(0u64, |c, &w| (c << 16) | (w as u64));
+
+        Ok(Some(TraceRecord {
+            pc: pc,
+            instruction: instruction,
+            registers: registers,
+            writes: writes,
+            interrupts: interrupts,
+            cycle: cycle,
+        }))
+    }
+
+    fn next(&mut self) -> io::Result<u16> {
+        read_word(&mut self.reader)
+    }
+
+    fn words(&mut self, n: usize) -> io::Result<Vec<u16>> {
+        let mut v = Vec::with_capacity(n);
+        for _ in 0..n {
+            v.push(self.next()?);
+        }
+        Ok(v)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_word<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(((buf[0] as u16) << 8) | (buf[1] as u16))
+}
+
+/// Where two traces first differ.
+#[derive(Debug)]
+pub struct Divergence {
+    /// Number of records that matched before the divergence.
+    pub index: usize,
+    /// Last record that matched, if any.
+    pub previous: Option<TraceRecord>,
+    /// The differing records. One of them is `None` if its trace ended early.
+    pub a: Option<TraceRecord>,
+    pub b: Option<TraceRecord>,
+}
+
+/// Compares two traces record by record. Returns `None` if they are identical.
+pub fn first_divergence<A: Read, B: Read>(a: &mut TraceReader<A>, b: &mut TraceReader<B>)
+        -> io::Result<Option<Divergence>> {
+    let mut index = 0;
+    let mut previous = None;
+    loop {
+        let ra = a.next_record()?;
+        let rb = b.next_record()?;
+        if ra != rb {
+            return Ok(Some(Divergence {
+                index: index,
+                previous: previous,
+                a: ra,
+                b: rb,
+            }));
+        }
+        if ra.is_none() {
+            return Ok(None);
+        }
+        previous = ra;
+        index += 1;
+    }
+}
@@ -0,0 +1,71 @@
+// Helpers shared by the tests
+
+use std::any::Any;
+use std::io::Result;
+use std::ops::{Deref, DerefMut};
+use dcpu16::dcpu::{DCPU, Device, DeviceInfo};
+use dcpu16::snapshot::{StateWriter, StateReader};
+
+/// What a test device does. Everything defaults to doing nothing (like the defaults of `Device`),
+/// so that tests only implement the behavior they check.
+pub trait Behavior: Send + 'static {
+    fn process_interrupt(&mut self, _cpu: &mut DCPU) -> () {}
+    fn run(&mut self, _cpu: &mut DCPU, _cycles: usize) -> () {}
+    fn memory_read(&mut self, _cpu: &mut DCPU, _address: u16) -> u16 {
+        0
+    }
+    fn memory_write(&mut self, _cpu: &mut DCPU, _address: u16, _value: u16) -> () {}
+}
+
+/// Device with hardware ID 0x12345678 that does what `T` does, and has no state to save. Derefs
+/// to `T`, so `find_device::<TestDevice<T>>` and `with_device` give access to it.
+pub struct TestDevice<T: Behavior>(pub T);
+
+impl<T: Behavior> Deref for TestDevice<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Behavior> DerefMut for TestDevice<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Behavior> Device for TestDevice<T> {
+    fn info(&self) -> DeviceInfo {
+        DeviceInfo {
+            name: "Test Device",
+            hardware_id: 0x12345678,
+            version: 1,
+            manufacturer_id: 0,
+        }
+    }
+    fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
+        self.0.process_interrupt(cpu);
+    }
+    fn run(&mut self, cpu: &mut DCPU, cycles: usize) -> () {
+        self.0.run(cpu, cycles);
+    }
+    fn save_state(&self, _: &mut StateWriter) -> () {}
+    fn load_state(&mut self, _: &mut StateReader) -> Result<()> { Ok(()) }
+
+    fn memory_read(&mut self, cpu: &mut DCPU, address: u16) -> u16 {
+        self.0.memory_read(cpu, address)
+    }
+
+    fn memory_write(&mut self, cpu: &mut DCPU, address: u16, value: u16) -> () {
+        self.0.memory_write(cpu, address, value);
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
@@ -106,6 +106,13 @@ fn test_assembler_hardware_ops() {
     test_case(&["HWI 1"], &[0x8a40]);
 }
 
+#[test]
+fn test_assembler_extension_ops() {
+    test_case(&["OUT A"], &[0x0260]);
+    test_case(&["OUV A"], &[0x0280]);
+    test_case(&["HLT 3"], &[0x92a0]);
+}
+
 #[test]
 fn test_assembler_basic_registers() {
     test_case(&["SET PC, 0"], &[0x8781]);
@@ -254,3 +261,14 @@ fn test_assembler_future_labels() {
 fn test_assembler_unknown_label() {
     test_case(&["SET A, label"], &[0]);
 }
+
+#[test]
+fn test_assembler_labels_and_lines() {
+    let lines: Vec<String> = vec!["SET A, 1", "; comment", ":start", ":loop ADD A, 1", "SET PC, loop"]
+        .iter().map(|l| l.to_string()).collect();
+    let mut cpu = PCPU::new();
+    assert!(parse(&lines, &mut cpu).is_ok());
+    assert_eq!(cpu.labels(), vec![("loop".to_string(), 1), ("start".to_string(), 1)]);
+    assert_eq!(cpu.line_ranges(), &[(0, 1, 0), (1, 2, 3), (2, 3, 4)]);
+    assert!(!cpu.is_data_line(3));
+}
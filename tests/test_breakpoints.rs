@@ -0,0 +1,92 @@
+use dcpu16::dcpu::{DCPU, RunStatus, StopReason, WatchKind, MemoryAccess};
+
+#[test]
+fn breakpoint_stops_run() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8c01; // SET A, 2
+    cpu.mem[2] = 0x9001; // SET A, 3
+    cpu.mem[3] = 0x8b81; // SET PC, 1
+    cpu.add_breakpoint(2);
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Breakpoint(2)));
+    assert_eq!(cpu.pc, 2);
+    assert_eq!(cpu.reg[0], 2);
+
+    // Continuing executes the instruction at the breakpoint and loops back to it
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Breakpoint(2)));
+    assert_eq!(cpu.reg[0], 2);
+
+    assert!(cpu.remove_breakpoint(2));
+    assert!(!cpu.remove_breakpoint(2));
+    assert_eq!(cpu.run(100), RunStatus::CycleBudget);
+}
+
+#[test]
+fn breakpoint_stops_tick() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8c01; // SET A, 2
+    cpu.add_breakpoint(1);
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Breakpoint(1)));
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.reg[0], 2);
+}
+
+#[test]
+fn breakpoint_at_start() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8781; // SET PC, 0
+    cpu.add_breakpoint(0);
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Breakpoint(0)));
+    assert_eq!((cpu.reg[0], cpu.cycle()), (0, 0));
+
+    // Resuming from the breakpoint executes it, and stops when it comes around again
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Breakpoint(0)));
+    assert_eq!((cpu.reg[0], cpu.pc), (1, 0));
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.pc, 1);
+
+    // Moving PC onto a breakpoint is not resuming from it
+    cpu.add_breakpoint(1);
+    cpu.pc = 1;
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Breakpoint(1)));
+    assert_eq!(cpu.pc, 1);
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Breakpoint(0)));
+}
+
+#[test]
+fn watchpoint_write() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8bc1; cpu.mem[2] = 0x1000; // SET [0x1000], 1
+    cpu.mem[3] = 0x8801; // SET A, 1
+    let id = cpu.add_watchpoint(0x1000, 0x10ff, WatchKind::Write);
+    assert_eq!(cpu.run(1000),
+               RunStatus::Stopped(StopReason::Watchpoint { id: id, address: 0x1000, access: MemoryAccess::Write }));
+    assert_eq!(cpu.pc, 3);
+    assert_eq!(cpu.mem[0x1000], 1);
+}
+
+#[test]
+fn watchpoint_read_ignores_writes() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x7fc1; cpu.mem[1] = 0x0005; cpu.mem[2] = 0x2000; // SET [0x2000], 5
+    cpu.mem[3] = 0x7801; cpu.mem[4] = 0x2000; // SET A, [0x2000]
+    let id = cpu.add_watchpoint(0x2000, 0x2000, WatchKind::Read);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.tick(),
+               RunStatus::Stopped(StopReason::Watchpoint { id: id, address: 0x2000, access: MemoryAccess::Read }));
+    assert_eq!(cpu.reg[0], 5);
+}
+
+#[test]
+fn watchpoint_stack() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8b01; // SET PUSH, 1
+    let id = cpu.add_watchpoint(0xff00, 0xffff, WatchKind::ReadWrite);
+    assert_eq!(cpu.tick(),
+               RunStatus::Stopped(StopReason::Watchpoint { id: id, address: 0xffff, access: MemoryAccess::Write }));
+    assert!(cpu.remove_watchpoint(id));
+    assert!(!cpu.remove_watchpoint(id));
+}
@@ -0,0 +1,116 @@
+use std::io::{self, Cursor};
+use std::thread;
+use std::time::Duration;
+use dcpu16::assembler::{PCPU, parse};
+use dcpu16::dcpu::{self, DCPU, Device};
+use dcpu16::devices::console::DeviceConsole;
+
+fn console_cpu(ll: &[&str]) -> DCPU {
+    let lines: Vec<String> = ll.iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    assert!(parse(&lines, &mut pcpu).is_ok(), "{:?}", ll);
+    let mut cpu = DCPU::new();
+    cpu.mem.copy_from_slice(&pcpu.mem[..]);
+    cpu.add_device(Box::new(DeviceConsole::new())).unwrap();
+    cpu
+}
+
+fn with_console<T, F: FnOnce(&mut DeviceConsole) -> T>(cpu: &DCPU, f: F) -> T {
+    let mut device = cpu.devices[0].lock().unwrap();
+    f(device.as_any_mut().downcast_mut::<DeviceConsole>().unwrap())
+}
+
+// Sends an interrupt to the console, as HWI would
+fn hwi(console: &mut DeviceConsole, cpu: &mut DCPU, a: u16, b: u16, c: u16) -> (u16, u16) {
+    cpu.reg[dcpu::REG_A] = a;
+    cpu.reg[dcpu::REG_B] = b;
+    cpu.reg[dcpu::REG_C] = c;
+    console.process_interrupt(cpu);
+    (cpu.reg[dcpu::REG_B], cpu.reg[dcpu::REG_C])
+}
+
+#[test]
+fn console_write() {
+    let mut cpu = console_cpu(&["SET A, 4",
+                                "SET B, hello",
+                                "HWI 0",
+                                "SET A, 3",
+                                "SET B, 0x21",
+                                "HWI 0",
+                                "HLT 0",
+                                ":hello DAT \"Hello\", 0"]);
+    cpu.run(100);
+    assert_eq!(with_console(&cpu, |c| c.output().to_vec()), b"Hello!");
+}
+
+#[test]
+fn console_interrupt_on_input() {
+    let mut cpu = console_cpu(&["IAS handler",
+                                "SET A, 5",
+                                "SET B, 0x42",
+                                "HWI 0",
+                                ":loop SET PC, loop",
+                                ":handler SET A, 1",
+                                "HWI 0",
+                                "SET [0x1000], C",
+                                "ADD [0x1001], 1",
+                                "RFI 0"]);
+    cpu.run(100);
+    assert_eq!(cpu.mem[0x1001], 0);
+    with_console(&cpu, |c| c.push_input(b"x"));
+    cpu.run(100);
+    assert_eq!((cpu.mem[0x1000], cpu.mem[0x1001]), (0x78, 1));
+}
+
+#[test]
+fn console_line_mode() {
+    let mut cpu = DCPU::new();
+    let mut console = DeviceConsole::new();
+    hwi(&mut console, &mut cpu, 0, 1, 0);
+    console.push_input(b"hel");
+    console.run(&mut cpu, 1);
+    assert_eq!(hwi(&mut console, &mut cpu, 6, 0, 0), (0, 0));
+    assert_eq!(hwi(&mut console, &mut cpu, 1, 0, 0).1, 0);
+    assert_eq!(hwi(&mut console, &mut cpu, 2, 0x2000, 10).1, 0xffff);
+
+    console.push_input(b"lo\r\nworld, and more\n");
+    console.run(&mut cpu, 1);
+    assert_eq!(hwi(&mut console, &mut cpu, 6, 0, 0), (0, 2));
+    assert_eq!(hwi(&mut console, &mut cpu, 2, 0x2000, 10).1, 5);
+    assert_eq!(&cpu.mem[0x2000..0x2006], &[0x68, 0x65, 0x6c, 0x6c, 0x6f, 0]);
+    // The rest of a long line is dropped
+    assert_eq!(hwi(&mut console, &mut cpu, 2, 0x2000, 5).1, 5);
+    assert_eq!(&cpu.mem[0x2000..0x2006], &[0x77, 0x6f, 0x72, 0x6c, 0x64, 0]);
+    assert_eq!(hwi(&mut console, &mut cpu, 6, 0, 0), (0, 0));
+}
+
+#[test]
+fn console_end_of_input() {
+    let mut cpu = DCPU::new();
+    let mut console = DeviceConsole::new();
+    hwi(&mut console, &mut cpu, 0, 1, 0);
+    console.push_input(b"ab");
+    console.close_input();
+    console.run(&mut cpu, 1);
+    assert_eq!(hwi(&mut console, &mut cpu, 6, 0, 0), (0, 1));
+    assert_eq!(hwi(&mut console, &mut cpu, 1, 0, 0).1, 0x61);
+    assert_eq!(hwi(&mut console, &mut cpu, 1, 0, 0).1, 0x62);
+    assert_eq!(hwi(&mut console, &mut cpu, 1, 0, 0).1, 0x0a);
+    assert_eq!(hwi(&mut console, &mut cpu, 6, 0, 0), (1, 0));
+}
+
+#[test]
+fn console_host_input() {
+    let mut cpu = DCPU::new();
+    let mut console = DeviceConsole::with_io(Cursor::new(b"abc".to_vec()), Box::new(io::sink()));
+    for _ in 0..1000 {
+        console.run(&mut cpu, 1);
+        if hwi(&mut console, &mut cpu, 6, 0, 0).0 == 0 && cpu.reg[dcpu::REG_C] == 4 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(hwi(&mut console, &mut cpu, 2, 0x3000, 10).1, 3);
+    assert_eq!(hwi(&mut console, &mut cpu, 6, 0, 0), (1, 0));
+    assert_eq!(console.output(), b"");
+}
@@ -0,0 +1,94 @@
+use std::env;
+use std::fs;
+use dcpu16::assembler::{PCPU, parse};
+use dcpu16::dcpu::DCPU;
+use dcpu16::symbols::SymbolMap;
+
+const PROGRAM: [&'static str; 9] = [
+    ":start SET A, 2",
+    ":loop SUB A, 1",
+    "IFE A, 5",
+    "    SET B, 1",
+    "IFN A, 0",
+    "    SET PC, loop",
+    "DAT 0",
+    ":unused SET C, 1",
+    ":table DAT 1, 2, 3",
+];
+
+fn assemble() -> (DCPU, SymbolMap) {
+    let lines: Vec<String> = PROGRAM.iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    assert!(parse(&lines, &mut pcpu).is_ok());
+    let mut cpu = DCPU::new();
+    cpu.set_halt_on_zero(true);
+    cpu.mem.copy_from_slice(&pcpu.mem[..]);
+    let mut symbols = SymbolMap::from_assembler(&pcpu);
+    symbols.set_source("test.asm");
+    (cpu, symbols)
+}
+
+#[test]
+fn coverage_branches() {
+    let (mut cpu, _) = assemble();
+    cpu.start_coverage();
+    while !cpu.tick().is_stopped() {}
+    let coverage = cpu.stop_coverage().unwrap();
+    assert_eq!(coverage.hits(0), 1);
+    assert_eq!(coverage.hits(1), 2);
+    assert_eq!(coverage.hits(3), 0);
+    let ife = coverage.branch(2).unwrap();
+    assert_eq!((ife.taken, ife.skipped), (0, 2));
+    let ifn = coverage.branch(4).unwrap();
+    assert_eq!((ifn.taken, ifn.skipped), (1, 1));
+    assert!(coverage.branch(0).is_none());
+}
+
+#[test]
+fn coverage_lcov() {
+    let (mut cpu, symbols) = assemble();
+
+    // Goes through the symbol map file, same as the command line tools
+    let path = env::temp_dir().join("dcpu16_test_coverage.sym");
+    symbols.save(&path).unwrap();
+    let symbols = SymbolMap::load(&path).unwrap();
+    fs::remove_file(&path).ok();
+    assert_eq!(symbols.source(), Some("test.asm"));
+    assert_eq!(symbols.line_at(3).unwrap().line, 3);
+    assert!(symbols.line_at(0x0a).unwrap().data);
+
+    cpu.start_coverage();
+    while !cpu.tick().is_stopped() {}
+    let mut out: Vec<u8> = Vec::new();
+    cpu.coverage().unwrap().write_lcov(&mut out, &symbols, None, &cpu.mem[..]).unwrap();
+    let lcov = String::from_utf8(out).unwrap();
+    let expected = "\
+TN:
+SF:test.asm
+FN:1,start
+FN:2,loop
+FN:8,unused
+FNDA:1,start
+FNDA:2,loop
+FNDA:0,unused
+FNF:3
+FNH:2
+BRDA:3,0,0,0
+BRDA:3,0,1,2
+BRDA:5,0,0,1
+BRDA:5,0,1,1
+BRF:4
+BRH:3
+DA:1,1
+DA:2,2
+DA:3,2
+DA:4,0
+DA:5,2
+DA:6,1
+DA:8,0
+LF:7
+LH:5
+end_of_record
+";
+    assert_eq!(lcov, expected);
+}
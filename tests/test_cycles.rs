@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use dcpu16::dcpu::{self, DCPU, RunStatus};
+use common::{Behavior, TestDevice};
+
+// Records the cycle count seen by `run`
+struct DeviceTimeline {
+    seen: Arc<AtomicU64>,
+}
+
+impl Behavior for DeviceTimeline {
+    fn run(&mut self, cpu: &mut DCPU, _: usize) -> () {
+        self.seen.store(cpu.cycle(), Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn cycles_never_wrap() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8781; // SET PC, 0
+    for _ in 0..10 {
+        assert_eq!(cpu.run(dcpu::CYCLE_HZ), RunStatus::CycleBudget);
+    }
+    assert_eq!(cpu.cycle(), 10 * dcpu::CYCLE_HZ as u64);
+    assert_eq!(cpu.elapsed(), Duration::from_secs(10));
+}
+
+#[test]
+fn cycles_duration_conversion() {
+    assert_eq!(dcpu::cycles_to_duration(dcpu::CYCLE_HZ as u64), Duration::from_secs(1));
+    assert_eq!(dcpu::cycles_to_duration(1), Duration::new(0, 1_000_000_000 / dcpu::CYCLE_HZ as u32));
+    assert_eq!(dcpu::duration_to_cycles(Duration::from_millis(1500)), 3 * dcpu::CYCLE_HZ as u64 / 2);
+    assert_eq!(dcpu::duration_to_cycles(dcpu::cycles_to_duration(123_456_789)), 123_456_789);
+}
+
+#[test]
+fn cycles_halt_outside_run() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8781; // SET PC, 0
+    cpu.halt(500);
+    assert_eq!(cpu.cycle(), 500);
+    // The halt uses up the budget of the next runs
+    assert_eq!(cpu.run(300), RunStatus::CycleBudget);
+    assert_eq!(cpu.cycle(), 500);
+    assert_eq!(cpu.run(300), RunStatus::CycleBudget);
+    assert_eq!(cpu.cycle(), 600);
+}
+
+#[test]
+fn cycles_seen_by_devices() {
+    let seen = Arc::new(AtomicU64::new(0));
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(TestDevice(DeviceTimeline { seen: seen.clone() }))).unwrap();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8802; // ADD A, 1
+    cpu.tick();
+    assert_eq!(seen.load(Ordering::SeqCst), 1);
+    cpu.tick();
+    assert_eq!(seen.load(Ordering::SeqCst), 3);
+    cpu.run(dcpu::CYCLE_HZ * 2);
+    assert_eq!(seen.load(Ordering::SeqCst), cpu.cycle());
+    assert!(seen.load(Ordering::SeqCst) > 0xffff);
+}
@@ -0,0 +1,130 @@
+use dcpu16::dcpu::{self, DCPU, Device, RunStatus};
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk};
+use dcpu16::instructions::Isa;
+
+#[test]
+fn decode_cache_self_modifying_code() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x7fc1; cpu.mem[1] = 0x9001; cpu.mem[2] = 0x0004; // SET [4], 0x9001
+    cpu.mem[3] = 0x8801; // SET A, 1
+    cpu.mem[4] = 0x8401; // SET A, 0 (becomes SET A, 3)
+    cpu.pc = 4;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 0);
+    cpu.pc = 0;
+    for _ in 0..3 {
+        assert_eq!(cpu.tick(), RunStatus::Running);
+    }
+    assert_eq!(cpu.reg[0], 3);
+}
+
+#[test]
+fn decode_cache_direct_writes() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 1);
+    cpu.mem[0] = 0x9001; // SET A, 3
+    cpu.pc = 0;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 3);
+}
+
+#[test]
+fn decode_cache_device_dma() {
+    let mut cpu = DCPU::new();
+    let mut disk = FloppyDisk::new();
+    let mut sector = [0; 512];
+    sector[0] = 0x9001; // SET A, 3
+    disk.sectors.push(sector);
+    let mut floppy = DeviceFloppyM35FD::new();
+    floppy.insert(disk);
+    cpu.add_device(Box::new(floppy)).unwrap();
+    cpu.mem[0x10] = 0x8801; // SET A, 1
+    cpu.pc = 0x10;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 1);
+
+    // Read sector 0 over the code
+    cpu.with_device(|floppy: &mut DeviceFloppyM35FD, cpu| {
+        cpu.reg[dcpu::REG_A] = 2;
+        cpu.reg[dcpu::REG_X] = 0;
+        cpu.reg[dcpu::REG_Y] = 0x10;
+        floppy.process_interrupt(cpu);
+        floppy.run(cpu, 100000);
+    });
+    cpu.pc = 0x10;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 3);
+}
+
+#[test]
+fn decode_cache_snapshot_restore() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x9001; // SET A, 3
+    let mut snapshot = Vec::new();
+    cpu.write_snapshot(&mut snapshot).unwrap();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 1);
+    cpu.read_snapshot(&mut &snapshot[..]).unwrap();
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 3);
+}
+
+#[test]
+fn decode_cache_step_back() {
+    let mut cpu = DCPU::new();
+    cpu.set_history_limit(10);
+    cpu.mem[0] = 0x7fc1; cpu.mem[1] = 0x9001; cpu.mem[2] = 0x0010; // SET [0x10], 0x9001
+    cpu.mem[0x10] = 0x8801; // SET A, 1 (becomes SET A, 3)
+    cpu.tick();
+    cpu.pc = 0x10;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 3);
+
+    // Undoing the write brings SET A, 1 back
+    cpu.step_back(2);
+    assert_eq!(cpu.mem[0x10], 0x8801);
+    cpu.pc = 0x10;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 1);
+}
+
+#[test]
+fn decode_cache_isa() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1 (SET A, 2 in 1.1)
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 1);
+    cpu.set_isa(Isa::V1_1);
+    cpu.pc = 0;
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 2);
+}
+
+#[test]
+fn decode_cache_same_result() {
+    // A: counts down from 100, B: sum of A, C: skipped unless A is 50
+    let program = [
+        0x7c01, 0x0064, // SET A, 100
+        0x0022,         // ADD B, A
+        0x7c12, 0x0032, // IFE A, 50
+        0x7c41, 0x1234, //   SET C, 0x1234
+        0x8803,         // SUB A, 1
+        0x8413,         // IFN A, 0
+        0x8f81,         //   SET PC, 2 (wraps to the start at the end)
+        0x8781,         // SET PC, 0
+    ];
+    let mut results = Vec::new();
+    for &on in [true, false].iter() {
+        let mut cpu = DCPU::new();
+        cpu.set_decode_cache(on);
+        assert_eq!(cpu.decode_cache(), on);
+        cpu.mem[..program.len()].copy_from_slice(&program);
+        cpu.run(10_000);
+        results.push((cpu.reg, cpu.pc, cpu.cycle()));
+    }
+    assert_eq!(results[0], results[1]);
+    assert_eq!(results[0].0[2], 0x1234);
+}
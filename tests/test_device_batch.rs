@@ -0,0 +1,63 @@
+use dcpu16::dcpu::DCPU;
+use common::{Behavior, TestDevice};
+
+// Counts how often and for how many cycles it has been run
+struct DeviceCounter {
+    calls: usize,
+    cycles: usize,
+    // Cycles it had been run for when HWI was sent to it
+    cycles_at_interrupt: Option<usize>,
+}
+
+impl Behavior for DeviceCounter {
+    fn process_interrupt(&mut self, _: &mut DCPU) -> () {
+        self.cycles_at_interrupt = Some(self.cycles);
+    }
+    fn run(&mut self, _: &mut DCPU, cycles: usize) -> () {
+        self.calls += 1;
+        self.cycles += cycles;
+    }
+}
+
+fn counter(cpu: &DCPU) -> (usize, usize, Option<usize>) {
+    let device = cpu.devices[0].lock().unwrap();
+    let c = device.as_any().downcast_ref::<TestDevice<DeviceCounter>>().unwrap();
+    (c.calls, c.cycles, c.cycles_at_interrupt)
+}
+
+fn counting_cpu() -> DCPU {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(TestDevice(DeviceCounter { calls: 0, cycles: 0, cycles_at_interrupt: None }))).unwrap();
+    cpu
+}
+
+#[test]
+fn device_batch() {
+    let mut cpu = counting_cpu();
+    cpu.mem[0] = 0x8781; // SET PC, 0
+    assert_eq!(cpu.device_batch(), 1);
+    cpu.run(100);
+    assert_eq!(counter(&cpu), (100, 100, None));
+
+    let mut cpu = counting_cpu();
+    cpu.mem[0] = 0x8781; // SET PC, 0
+    cpu.set_device_batch(10);
+    cpu.run(95);
+    // Caught up when run returns
+    assert_eq!(counter(&cpu), (10, 95, None));
+    cpu.tick();
+    assert_eq!(counter(&cpu), (10, 95, None));
+}
+
+#[test]
+fn device_batch_catches_up_before_hwi() {
+    let mut cpu = counting_cpu();
+    cpu.mem[0] = 0x8401; // SET A, 0
+    cpu.mem[1] = 0x8401; // SET A, 0
+    cpu.mem[2] = 0x8640; // HWI 0
+    cpu.set_device_batch(100);
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    assert_eq!(counter(&cpu), (1, 2, Some(2)));
+}
@@ -0,0 +1,77 @@
+use dcpu16::assembler::{PCPU, parse};
+use dcpu16::dcpu::{DCPU, DeviceInfo};
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk};
+use dcpu16::devices::monitor_lem1802::DeviceMonitorLEM1802;
+use common::{Behavior, TestDevice};
+
+fn cpu_with_devices() -> DCPU {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.add_device(Box::new(DeviceMonitorLEM1802::new())).unwrap();
+    cpu.add_device(Box::new(DeviceFloppyM35FD::new())).unwrap();
+    cpu
+}
+
+#[test]
+fn device_info_hwq() {
+    let lines: Vec<String> = ["HWQ 1", "HLT 0"].iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    assert!(parse(&lines, &mut pcpu).is_ok());
+    let mut cpu = cpu_with_devices();
+    cpu.mem.copy_from_slice(&pcpu.mem[..]);
+    cpu.run(100);
+    assert_eq!(&cpu.reg[..5], &[0xf615, 0x7349, 0x1802, 0x8b36, 0x1c6c]);
+
+    assert_eq!(cpu.device_info()[1], Some(DeviceInfo {
+        name: "NYA Elektriska LEM1802 Monitor",
+        hardware_id: 0x7349f615,
+        version: 0x1802,
+        manufacturer_id: 0x1c6c8b36,
+    }));
+}
+
+#[test]
+fn device_info_lookup() {
+    let mut cpu = cpu_with_devices();
+    assert_eq!(cpu.find_device::<DeviceFloppyM35FD>(), Some(2));
+    assert_eq!(cpu.find_device_by_id(0x7349f615), Some(1));
+    assert_eq!(cpu.find_device_by_id(0x30cf7406), None);
+
+    let state = cpu.with_device(|floppy: &mut DeviceFloppyM35FD, _| {
+        floppy.insert(FloppyDisk::new());
+        floppy.state()
+    });
+    assert_eq!(state, Some(1));
+    assert!(cpu.remove_device(0).is_ok());
+    assert_eq!(cpu.find_device::<DeviceClockGeneric>(), None);
+    assert_eq!(cpu.with_device(|_: &mut DeviceClockGeneric, _| ()), None);
+}
+
+// Looks up devices when interrupted
+struct DeviceLookup {
+    found: Option<(Vec<Option<DeviceInfo>>, Option<usize>, Option<usize>)>,
+}
+
+impl Behavior for DeviceLookup {
+    fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
+        self.found = Some((cpu.device_info(), cpu.find_device::<TestDevice<DeviceLookup>>(),
+                           cpu.find_device_by_id(0x12d0b402)));
+    }
+}
+
+#[test]
+fn device_info_from_device() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.add_device(Box::new(TestDevice(DeviceLookup { found: None }))).unwrap();
+    cpu.mem[0] = 0x8a40; // HWI 1
+    cpu.tick();
+    let found = cpu.with_device(|device: &mut TestDevice<DeviceLookup>, _| device.found.take()).unwrap();
+    let (info, index, clock) = found.unwrap();
+    assert_eq!(info.len(), 2);
+    assert_eq!(info[0].map(|i| i.hardware_id), Some(0x12d0b402));
+    assert_eq!(info[1], None);
+    assert_eq!(index, None);
+    assert_eq!(clock, Some(0));
+}
@@ -0,0 +1,101 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use dcpu16::assembler::{PCPU, parse};
+use dcpu16::dcpu::{DCPU, RunStatus, StopReason};
+use dcpu16::disassembler;
+use dcpu16::extensions::{Extensions, ExtensionError};
+use dcpu16::instructions::{Isa, HWI};
+
+// Lets the test look at the output after handing the writer to the DCPU
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Standard extensions plus `SQR a`, which sets A to a * a
+fn with_square() -> Arc<Extensions> {
+    let mut extensions = Extensions::standard();
+    extensions.register("SQR", 0x16, 2, |cpu, a| {
+        cpu.reg[0] = a.wrapping_mul(a);
+    }).unwrap();
+    Arc::new(extensions)
+}
+
+fn assemble(ll: &[&str], extensions: Arc<Extensions>, isa: Isa) -> DCPU {
+    let lines: Vec<String> = ll.iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    pcpu.set_isa(isa);
+    pcpu.set_extensions(extensions.clone());
+    assert!(parse(&lines, &mut pcpu).is_ok(), "{:?}", ll);
+    let mut cpu = DCPU::new();
+    cpu.set_isa(isa);
+    cpu.set_extensions(extensions);
+    cpu.mem.copy_from_slice(&pcpu.mem[..]);
+    cpu
+}
+
+#[test]
+fn extension_custom_opcode() {
+    for &isa in [Isa::V1_7, Isa::V1_1].iter() {
+        let mut cpu = assemble(&["sqr 7", "HLT 0"], with_square(), isa);
+        assert_eq!(disassembler::disassemble_instruction_at(&cpu, 0, false).1, "SQR 7");
+        assert_eq!(cpu.run(100), RunStatus::Stopped(StopReason::Halt(0)));
+        assert_eq!(cpu.reg[0], 49);
+        // 2 for SQR and 1 for HLT
+        assert_eq!(cpu.cycle(), 3);
+    }
+}
+
+#[test]
+fn extension_unknown_without_registry() {
+    let lines = vec!["SQR 7".to_string()];
+    let mut pcpu = PCPU::new();
+    assert!(parse(&lines, &mut pcpu).is_err());
+
+    // Without the extension, the opcode is reserved
+    let mut cpu = assemble(&["SQR 7"], with_square(), Isa::V1_7);
+    cpu.set_extensions(Arc::new(Extensions::standard()));
+    assert_eq!(disassembler::disassemble_instruction_at(&cpu, 0, false).1, "DAT 0xa2c0");
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 0);
+}
+
+#[test]
+fn extension_registration_errors() {
+    let mut extensions = Extensions::standard();
+    let count = Arc::new(AtomicUsize::new(0));
+    let c = count.clone();
+    assert_eq!(extensions.register("SET", 0x16, 0, |_, _| {}), Err(ExtensionError::MnemonicTaken));
+    assert_eq!(extensions.register("out", 0x16, 0, |_, _| {}), Err(ExtensionError::MnemonicTaken));
+    assert_eq!(extensions.register("BRK", HWI, 0, |_, _| {}), Err(ExtensionError::InvalidOpcode));
+    assert_eq!(extensions.register("BRK", 0x20, 0, |_, _| {}), Err(ExtensionError::InvalidOpcode));
+    assert_eq!(extensions.register("BRK", 0x13, 0, |_, _| {}), Err(ExtensionError::OpcodeTaken));
+    assert_eq!(extensions.register("BREAK", 0x16, 0, |_, _| {}), Err(ExtensionError::InvalidMnemonic));
+    assert_eq!(extensions.register("BRK", 0x16, 0, move |_, _| {
+        c.fetch_add(1, Ordering::SeqCst);
+    }), Ok(()));
+    assert!(extensions.unregister("OUT"));
+    assert!(extensions.by_mnemonic("OUT").is_none());
+    assert_eq!(extensions.by_opcode(0x16).unwrap().mnemonic(), "BRK");
+    extensions.by_opcode(0x16).unwrap().execute(&mut DCPU::new(), 0);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn extension_output_capture() {
+    let mut cpu = assemble(&["OUT hello", "OUV 1234", "HLT 0", ":hello DAT \"Hello\\n\", 0"],
+                           Arc::new(Extensions::standard()), Isa::V1_7);
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    cpu.set_output(Box::new(buffer.clone()));
+    cpu.run(100);
+    assert_eq!(&buffer.0.lock().unwrap()[..], b"Hello\n1234\n");
+}
@@ -0,0 +1,154 @@
+use dcpu16::dcpu::{DCPU, RunStatus, StopReason, CpuFault, FaultKind, FaultPolicy, MAX_QUEUED_INTERRUPTS};
+
+// Raises interrupt 5 over and over with queueing turned on
+fn interrupt_flood(cpu: &mut DCPU) {
+    cpu.mem[0] = 0x8980; // IAQ 1
+    cpu.mem[1] = 0x9900; // INT 5
+    cpu.mem[2] = 0x8b81; // SET PC, 1
+}
+
+#[test]
+fn fault_queue_overflow_halts() {
+    let mut cpu = DCPU::new();
+    interrupt_flood(&mut cpu);
+    let fault = CpuFault::InterruptQueueOverflow(5);
+    assert_eq!(cpu.run(100_000), RunStatus::Stopped(StopReason::Fault(fault)));
+    assert_eq!(cpu.fault(), Some(fault));
+    assert_eq!(cpu.pc, 2);
+
+    // Stays halted
+    let cycle = cpu.cycle();
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Fault(fault)));
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Fault(fault)));
+    assert_eq!(cpu.pc, 2);
+    assert_eq!(cpu.cycle(), cycle);
+
+    cpu.clear_fault();
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.pc, 1);
+}
+
+#[test]
+fn fault_queue_overflow_from_device() {
+    let mut cpu = DCPU::new();
+    for i in 0..MAX_QUEUED_INTERRUPTS {
+        cpu.interrupt(i as u16);
+    }
+    assert_eq!(cpu.fault(), None);
+    cpu.interrupt(0xabcd);
+    assert_eq!(cpu.fault(), Some(CpuFault::InterruptQueueOverflow(0xabcd)));
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Fault(CpuFault::InterruptQueueOverflow(0xabcd))));
+    assert_eq!(cpu.pc, 0);
+}
+
+#[test]
+fn fault_queue_overflow_log() {
+    let mut cpu = DCPU::new();
+    interrupt_flood(&mut cpu);
+    cpu.set_fault_policy(FaultKind::InterruptQueueOverflow, FaultPolicy::Log);
+    assert_eq!(cpu.run(10_000), RunStatus::CycleBudget);
+    assert_eq!(cpu.fault(), None);
+    assert!(!cpu.is_on_fire());
+}
+
+#[test]
+fn fault_reserved_opcode() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x0018; // Reserved basic opcode
+    cpu.mem[1] = 0x0040; // Reserved special opcode
+    cpu.mem[2] = 0x8801; // SET A, 1
+    assert_eq!(cpu.fault_policy(FaultKind::ReservedOpcode), FaultPolicy::Ignore);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.reg[0], 1);
+    // Reserved opcodes take one cycle each, so zeroed memory does not stall `run`
+    assert_eq!(cpu.cycle(), 3);
+    assert_eq!(cpu.run(100), RunStatus::CycleBudget);
+
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x0018;
+    cpu.mem[1] = 0x0040;
+    cpu.set_fault_policy(FaultKind::ReservedOpcode, FaultPolicy::Halt);
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Fault(CpuFault::ReservedOpcode { pc: 0, word: 0x0018 })));
+    assert_eq!(cpu.pc, 1);
+    cpu.clear_fault();
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Fault(CpuFault::ReservedOpcode { pc: 1, word: 0x0040 })));
+}
+
+#[test]
+fn fault_literal_write() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x93e1; cpu.mem[1] = 0x1234; // SET 0x1234, 3
+    cpu.mem[2] = 0x8520; // IAG 0
+    cpu.mem[3] = 0x8801; // SET A, 1
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    // The literal is skipped, and not overwritten
+    assert_eq!(cpu.pc, 2);
+    assert_eq!(cpu.mem[1], 0x1234);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.reg[0], 1);
+
+    cpu.set_fault_policy(FaultKind::LiteralWrite, FaultPolicy::Halt);
+    cpu.pc = 0;
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Fault(CpuFault::LiteralWrite { pc: 0 })));
+    assert_eq!(cpu.pc, 2);
+    cpu.clear_fault();
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Fault(CpuFault::LiteralWrite { pc: 2 })));
+}
+
+fn burning(seed: u32) -> DCPU {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x0018; // Reserved opcode
+    cpu.mem[1] = 0x8b81; // SET PC, 1
+    cpu.set_fault_policy(FaultKind::ReservedOpcode, FaultPolicy::Fire);
+    cpu.set_fire_seed(seed);
+    cpu
+}
+
+#[test]
+fn fault_fire() {
+    let mut cpu = burning(1234);
+    cpu.set_history_limit(1000);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert!(cpu.is_on_fire());
+    for _ in 0..100 {
+        cpu.tick();
+    }
+    let damaged = cpu.mem.iter().skip(2).filter(|&&w| w != 0).count();
+    assert!(damaged > 0);
+
+    // Damage is deterministic for a given seed
+    let mut other = burning(1234);
+    for _ in 0..101 {
+        other.tick();
+    }
+    assert!(cpu.mem[..] == other.mem[..]);
+
+    // And can be undone
+    assert_eq!(cpu.step_back(101), 101);
+    assert!(!cpu.is_on_fire());
+    assert!(cpu.mem.iter().skip(2).all(|&w| w == 0));
+
+    cpu.tick();
+    cpu.clear_fault();
+    let mem = cpu.mem.to_vec();
+    cpu.run(1000);
+    assert!(cpu.mem[..] == mem[..]);
+}
+
+#[test]
+fn fault_snapshot() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x0018;
+    cpu.set_fault_policy(FaultKind::ReservedOpcode, FaultPolicy::Halt);
+    cpu.tick();
+    let mut buf = Vec::new();
+    cpu.write_snapshot(&mut buf).unwrap();
+
+    let mut restored = DCPU::new();
+    restored.read_snapshot(&mut &buf[..]).unwrap();
+    assert_eq!(restored.fault(), Some(CpuFault::ReservedOpcode { pc: 0, word: 0x0018 }));
+    assert_eq!(restored.tick(), RunStatus::Stopped(StopReason::Fault(CpuFault::ReservedOpcode { pc: 0, word: 0x0018 })));
+}
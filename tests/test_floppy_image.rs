@@ -0,0 +1,136 @@
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use dcpu16::dcpu::{self, DCPU, Device};
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk, ImageFormat};
+
+fn test_disk() -> FloppyDisk {
+    let mut disk = FloppyDisk::new();
+    disk.sectors.push([0; 512]);
+    disk.sectors.push([0x1234; 512]);
+    disk.sectors.push([0; 512]);
+    disk
+}
+
+#[test]
+fn floppy_image_raw() {
+    let mut bytes = Vec::new();
+    test_disk().write_image(&mut bytes, ImageFormat::Raw).unwrap();
+    assert_eq!(bytes.len(), 1440 * 512 * 2);
+    assert_eq!(&bytes[1022..1026], &[0x00, 0x00, 0x12, 0x34]);
+    let disk = FloppyDisk::read_image(&mut Cursor::new(bytes)).unwrap();
+    assert_eq!(disk.sectors.len(), 1440);
+    assert_eq!((disk.sectors[0][511], disk.sectors[1][0], disk.sectors[2][0]), (0, 0x1234, 0));
+
+    // Short images are padded, and long ones are refused
+    let disk = FloppyDisk::read_image(&mut Cursor::new(vec![0xab, 0xcd, 0xef])).unwrap();
+    assert_eq!((disk.sectors.len(), disk.sectors[0][0], disk.sectors[0][1]), (1, 0xabcd, 0));
+    assert!(FloppyDisk::read_image(&mut Cursor::new(vec![0; 1440 * 512 * 2 + 2])).is_err());
+}
+
+#[test]
+fn floppy_image_sparse() {
+    let mut bytes = Vec::new();
+    test_disk().write_image(&mut bytes, ImageFormat::Sparse).unwrap();
+    assert_eq!(bytes.len(), 8 + 2 * 513);
+    assert_eq!(&bytes[..10], b"M35FDSPR\x00\x01");
+    let disk = FloppyDisk::read_image(&mut Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(disk.sectors.len(), 2);
+    assert_eq!(disk.sectors[1][511], 0x1234);
+
+    bytes[9] = 0xff; // Sector 255 is fine, but the sector is cut short
+    bytes.pop();
+    assert!(FloppyDisk::read_image(&mut Cursor::new(bytes)).is_err());
+}
+
+#[test]
+fn floppy_image_write_back() {
+    let path = env::temp_dir().join("dcpu16_test_floppy.img");
+    test_disk().save(&path, ImageFormat::Raw).unwrap();
+    let disk = FloppyDisk::load(&path).unwrap();
+    assert!(!disk.write_protected);
+
+    // Write sector 5 from 0x1000, as HWI would
+    let mut cpu = DCPU::new();
+    cpu.mem[0x1000] = 0xbeef;
+    let mut floppy = DeviceFloppyM35FD::new();
+    floppy.insert(disk);
+    cpu.reg[dcpu::REG_A] = 3;
+    cpu.reg[dcpu::REG_X] = 5;
+    cpu.reg[dcpu::REG_Y] = 0x1000;
+    floppy.process_interrupt(&mut cpu);
+    floppy.run(&mut cpu, 100000);
+    assert!(floppy.disk.as_ref().unwrap().is_dirty());
+    floppy.flush().unwrap();
+    assert!(!floppy.disk.as_ref().unwrap().is_dirty());
+
+    let bytes = fs::read(&path).unwrap();
+    assert_eq!(bytes.len(), 1440 * 512 * 2);
+    assert_eq!(&bytes[5 * 1024..5 * 1024 + 4], &[0xbe, 0xef, 0x00, 0x00]);
+    assert_eq!(&bytes[1024..1026], &[0x12, 0x34]);
+
+    // Read-only files give write-protected disks
+    let mut permissions = fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&path, permissions.clone()).unwrap();
+    assert!(FloppyDisk::load(&path).unwrap().write_protected);
+    permissions.set_readonly(false);
+    fs::set_permissions(&path, permissions).unwrap();
+    fs::remove_file(&path).ok();
+}
+
+// Writes sector `sector` from 0x1000, as HWI would
+fn write_sector(floppy: &mut DeviceFloppyM35FD, cpu: &mut DCPU, sector: u16) {
+    cpu.reg[dcpu::REG_A] = 3;
+    cpu.reg[dcpu::REG_X] = sector;
+    cpu.reg[dcpu::REG_Y] = 0x1000;
+    floppy.process_interrupt(cpu);
+    floppy.run(cpu, 100000);
+}
+
+#[test]
+fn floppy_image_snapshot_restore() {
+    let path = env::temp_dir().join("dcpu16_test_floppy_snapshot.img");
+    test_disk().save(&path, ImageFormat::Raw).unwrap();
+    let mut cpu = DCPU::new();
+    let mut floppy = DeviceFloppyM35FD::new();
+    floppy.insert(FloppyDisk::load(&path).unwrap());
+    cpu.add_device(Box::new(floppy)).unwrap();
+
+    let mut snapshot = Vec::new();
+    cpu.write_snapshot(&mut snapshot).unwrap();
+    cpu.with_device(|floppy: &mut DeviceFloppyM35FD, cpu| {
+        cpu.mem[0x1000] = 0x1111;
+        write_sector(floppy, cpu, 2);
+        floppy.flush().unwrap();
+    });
+
+    // Sector 2 goes back to zero, and writes after the restore still reach the image file
+    cpu.read_snapshot(&mut Cursor::new(snapshot)).unwrap();
+    cpu.with_device(|floppy: &mut DeviceFloppyM35FD, cpu| {
+        cpu.mem[0x1000] = 0x2222;
+        write_sector(floppy, cpu, 7);
+        floppy.flush().unwrap();
+    });
+    let disk = FloppyDisk::load(&path).unwrap();
+    fs::remove_file(&path).ok();
+    assert_eq!((disk.sectors[1][0], disk.sectors[2][0], disk.sectors[7][0]), (0x1234, 0, 0x2222));
+}
+
+#[test]
+fn floppy_image_write_protected() {
+    let mut disk = test_disk();
+    disk.write_protected = true;
+    let mut cpu = DCPU::new();
+    let mut floppy = DeviceFloppyM35FD::new();
+    floppy.insert(disk);
+    cpu.reg[dcpu::REG_A] = 0;
+    floppy.process_interrupt(&mut cpu);
+    assert_eq!(cpu.reg[dcpu::REG_B], 2); // STATE_READY_WP
+    cpu.reg[dcpu::REG_A] = 3;
+    floppy.process_interrupt(&mut cpu);
+    cpu.reg[dcpu::REG_A] = 0;
+    floppy.process_interrupt(&mut cpu);
+    assert_eq!(cpu.reg[dcpu::REG_C], 3); // ERROR_PROTECTED
+    assert!(!floppy.disk.as_ref().unwrap().is_dirty());
+}
@@ -0,0 +1,56 @@
+use dcpu16::dcpu::{self, DCPU, Device};
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk, FloppyTiming};
+
+// Starts writing `sector` from 0x1000, as HWI would
+fn write(floppy: &mut DeviceFloppyM35FD, cpu: &mut DCPU, sector: u16) {
+    cpu.reg[dcpu::REG_A] = 3;
+    cpu.reg[dcpu::REG_X] = sector;
+    cpu.reg[dcpu::REG_Y] = 0x1000;
+    floppy.process_interrupt(cpu);
+    assert_eq!(floppy.state(), 3); // STATE_BUSY
+}
+
+// Runs the drive one cycle at a time, and returns how long the operation took
+fn wait(floppy: &mut DeviceFloppyM35FD, cpu: &mut DCPU) -> usize {
+    let mut cycles = 0;
+    while floppy.state() == 3 {
+        floppy.run(cpu, 1);
+        cycles += 1;
+    }
+    cycles
+}
+
+#[test]
+fn floppy_timing_m35fd() {
+    let timing = FloppyTiming::m35fd();
+    assert_eq!(timing.cycles(0, 0), 1668); // 512 words at 30.7 kw/s
+    assert_eq!(timing.cycles(0, 17), 1668);
+    // Track 1 is on the other side of cylinder 0
+    assert_eq!(timing.cycles(0, 18), 1668);
+    assert_eq!(timing.cycles(0, 36), 1668 + 240);
+    assert_eq!(timing.cycles(1439, 0), 1668 + 39 * 240);
+    assert_eq!(FloppyTiming::instant().cycles(1439, 0), 0);
+}
+
+#[test]
+fn floppy_timing_reads_and_writes() {
+    let mut cpu = DCPU::new();
+    let mut floppy = DeviceFloppyM35FD::new();
+    floppy.insert(FloppyDisk::new());
+
+    // Writes pay for seeking, same as reads
+    write(&mut floppy, &mut cpu, 72);
+    assert_eq!(wait(&mut floppy, &mut cpu), 1668 + 2 * 240);
+    write(&mut floppy, &mut cpu, 90);
+    assert_eq!(wait(&mut floppy, &mut cpu), 1668);
+
+    cpu.reg[dcpu::REG_A] = 2;
+    cpu.reg[dcpu::REG_X] = 0;
+    floppy.process_interrupt(&mut cpu);
+    assert_eq!(wait(&mut floppy, &mut cpu), 1668 + 2 * 240);
+
+    floppy.set_timing(FloppyTiming::instant());
+    write(&mut floppy, &mut cpu, 1439);
+    assert_eq!(wait(&mut floppy, &mut cpu), 1);
+    assert_eq!(floppy.disk.as_ref().unwrap().sectors.len(), 1440);
+}
@@ -0,0 +1,155 @@
+use std::io::{self, Cursor, Read, Write};
+use dcpu16::dcpu::DCPU;
+use dcpu16::gdb::{GdbConnection, GdbStub};
+
+// Scripted client: all requests are queued up front, and every response is acked.
+struct ScriptedClient {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl ScriptedClient {
+    fn new(packets: &[&str]) -> ScriptedClient {
+        let mut input = Vec::new();
+        for p in packets {
+            let cs = p.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+            input.extend_from_slice(format!("${}#{:02x}+", p, cs).as_bytes());
+        }
+        ScriptedClient { input: Cursor::new(input), output: Vec::new() }
+    }
+
+    fn responses(&self) -> Vec<String> {
+        let out = String::from_utf8_lossy(&self.output).into_owned();
+        out.split('$').skip(1).map(|p| p.splitn(2, '#').next().unwrap().to_string()).collect()
+    }
+}
+
+impl Read for ScriptedClient {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for ScriptedClient {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl GdbConnection for ScriptedClient {
+    fn poll_interrupt(&mut self) -> bool {
+        false
+    }
+}
+
+fn session(cpu: &mut DCPU, packets: &[&str]) -> Vec<String> {
+    let mut stub = GdbStub::new(ScriptedClient::new(packets));
+    stub.serve(cpu).unwrap();
+    stub.into_inner().responses()
+}
+
+#[test]
+fn gdb_registers() {
+    let mut cpu = DCPU::new();
+    cpu.reg[0] = 0x1234;
+    cpu.pc = 0x0010;
+    let r = session(&mut cpu, &["?", "g", "P9=fff0", "p9", "Gffff000100020003000400050006000700080009000a000b"]);
+    assert_eq!(r[0], "S05");
+    assert_eq!(r[1], "123400000000000000000000000000000010000000000000");
+    assert_eq!(r[2], "OK");
+    assert_eq!(r[3], "fff0");
+    assert_eq!(r[4], "OK");
+    assert_eq!(cpu.reg[0], 0xffff);
+    assert_eq!(cpu.pc, 0x0008);
+    assert_eq!(cpu.ia, 0x000b);
+}
+
+#[test]
+fn gdb_memory() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0x100] = 0xabcd;
+    cpu.mem[0x101] = 0x0102;
+    let r = session(&mut cpu, &["m100,4", "M200,4:beefcafe", "m200,4"]);
+    assert_eq!(r, vec!["abcd0102", "OK", "beefcafe"]);
+    assert_eq!(cpu.mem[0x200], 0xbeef);
+    assert_eq!(cpu.mem[0x201], 0xcafe);
+}
+
+#[test]
+fn gdb_memory_wraps() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0xffff] = 0xabcd;
+    cpu.mem[0] = 0x0102;
+    let r = session(&mut cpu, &["mffff,4", "Mffffffffffffffff,4:beefcafe"]);
+    assert_eq!(r, vec!["abcd0102", "OK"]);
+    assert_eq!((cpu.mem[0xffff], cpu.mem[0]), (0xbeef, 0xcafe));
+}
+
+#[test]
+fn gdb_non_ascii_packets() {
+    let mut cpu = DCPU::new();
+    let registers = "G".to_string() + &"\u{e9}".repeat(16) + &"0".repeat(16);
+    let r = session(&mut cpu, &["\u{e9}", &registers, "m\u{e9},2"]);
+    assert_eq!(r, vec!["E01", "E01", "E01"]);
+}
+
+#[test]
+fn gdb_breakpoint_and_step() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8c01; // SET A, 2
+    cpu.mem[2] = 0x9001; // SET A, 3
+    cpu.mem[3] = 0x8b81; // SET PC, 1
+    let r = session(&mut cpu, &["Z0,2,1", "c", "p8", "s", "p0", "z0,2,1", "s", "p8"]);
+    assert_eq!(r, vec!["OK", "S05", "0002", "S05", "0003", "OK", "S05", "0001"]);
+}
+
+#[test]
+fn gdb_watchpoint() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8bc1; cpu.mem[1] = 0x1000; // SET [0x1000], 1
+    cpu.mem[2] = 0x8b81; // SET PC, 1
+    let r = session(&mut cpu, &["Z2,1000,2", "c"]);
+    assert_eq!(r, vec!["OK", "T05watch:1000;"]);
+}
+
+#[test]
+fn gdb_target_description() {
+    let mut cpu = DCPU::new();
+    let r = session(&mut cpu, &["qSupported:xmlRegisters=i386", "qXfer:features:read:target.xml:0,40"]);
+    assert!(r[0].contains("qXfer:features:read+"));
+    assert!(r[1].starts_with("m<?xml"));
+}
+
+#[test]
+fn gdb_program_exit() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x92a0; // HLT 3
+    let r = session(&mut cpu, &["c"]);
+    assert_eq!(r, vec!["W03"]);
+
+    let mut cpu = DCPU::new();
+    cpu.set_halt_on_zero(true);
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x0000; // Terminates
+    let r = session(&mut cpu, &["c"]);
+    assert_eq!(r, vec!["W00"]);
+}
+
+#[test]
+fn gdb_reverse_step() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8c01; // SET A, 2
+    cpu.mem[2] = 0x9001; // SET A, 3
+    cpu.set_history_limit(10);
+    let r = session(&mut cpu, &["bs", "s", "s", "s", "bs", "p0", "Z0,0,1", "bc", "bc", "k"]);
+    assert_eq!(r, vec!["T05replaylog:begin;", "S05", "S05", "S05", "S05", "0002", "OK", "S05",
+                       "T05replaylog:begin;"]);
+    assert_eq!(cpu.pc, 0);
+}
@@ -0,0 +1,95 @@
+use dcpu16::dcpu::{DCPU, StopReason, WatchKind, MemoryAccess};
+
+// Pushes 1, 2, 3 and then clobbers the first stack word with 0x1234
+fn stack_program(cpu: &mut DCPU) {
+    cpu.mem[0] = 0x8b01; // SET PUSH, 1
+    cpu.mem[1] = 0x8f01; // SET PUSH, 2
+    cpu.mem[2] = 0x9301; // SET PUSH, 3
+    cpu.mem[3] = 0x7fc1; cpu.mem[4] = 0x1234; cpu.mem[5] = 0xffff; // SET [0xffff], 0x1234
+    cpu.mem[6] = 0x8801; // SET A, 1
+}
+
+#[test]
+fn history_step_back() {
+    let mut cpu = DCPU::new();
+    stack_program(&mut cpu);
+    cpu.set_history_limit(100);
+    for _ in 0..5 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg[0], 1);
+    assert_eq!(cpu.mem[0xffff], 0x1234);
+    assert_eq!(cpu.history_len(), 5);
+
+    assert_eq!(cpu.step_back(2), 2);
+    assert_eq!(cpu.pc, 3);
+    assert_eq!(cpu.reg[0], 0);
+    assert_eq!(cpu.mem[0xffff], 1);
+
+    assert_eq!(cpu.step_back(1), 1);
+    assert_eq!(cpu.pc, 2);
+    assert_eq!(cpu.sp, 0xfffe);
+    assert_eq!(cpu.mem[0xfffd], 0);
+
+    // Runs forward again the same way
+    cpu.tick();
+    assert_eq!(cpu.sp, 0xfffd);
+    assert_eq!(cpu.mem[0xfffd], 3);
+
+    assert_eq!(cpu.step_back(10), 3);
+    assert_eq!(cpu.pc, 0);
+    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.cycle(), 0);
+    assert_eq!(cpu.mem[0xffff], 0);
+}
+
+#[test]
+fn history_run_back_to_write() {
+    let mut cpu = DCPU::new();
+    stack_program(&mut cpu);
+    cpu.set_history_limit(100);
+    for _ in 0..5 {
+        cpu.tick();
+    }
+    assert!(!cpu.run_back_to_write(0x1000));
+    assert_eq!(cpu.pc, 7);
+    assert!(cpu.run_back_to_write(0xffff));
+    assert_eq!(cpu.pc, 3);
+    assert_eq!(cpu.mem[0xffff], 1);
+    assert!(cpu.run_back_to_write(0xffff));
+    assert_eq!(cpu.pc, 0);
+    assert_eq!(cpu.mem[0xffff], 0);
+}
+
+#[test]
+fn history_run_back() {
+    let mut cpu = DCPU::new();
+    stack_program(&mut cpu);
+    cpu.set_history_limit(100);
+    for _ in 0..5 {
+        cpu.tick();
+    }
+    cpu.add_breakpoint(1);
+    let id = cpu.add_watchpoint(0xfffe, 0xfffe, WatchKind::Write);
+    assert_eq!(cpu.run_back(),
+               Some(StopReason::Watchpoint { id: id, address: 0xfffe, access: MemoryAccess::Write }));
+    assert_eq!(cpu.pc, 1);
+    assert!(cpu.remove_watchpoint(id));
+    assert_eq!(cpu.run_back(), None);
+    assert_eq!(cpu.pc, 0);
+}
+
+#[test]
+fn history_limit() {
+    let mut cpu = DCPU::new();
+    stack_program(&mut cpu);
+    cpu.tick();
+    assert_eq!(cpu.history_len(), 0);
+    cpu.set_history_limit(2);
+    for _ in 0..4 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.history_len(), 2);
+    assert_eq!(cpu.step_back(3), 2);
+    assert_eq!(cpu.pc, 3);
+}
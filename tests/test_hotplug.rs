@@ -0,0 +1,80 @@
+use dcpu16::assembler::{PCPU, parse};
+use dcpu16::dcpu::{DCPU, DeviceError, MemoryRegion};
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::keyboard_generic::DeviceKeyboardGeneric;
+use common::{Behavior, TestDevice};
+
+// Attaches a clock when it gets an interrupt
+struct DevicePlugger;
+
+impl Behavior for DevicePlugger {
+    fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
+        cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    }
+}
+
+// Counts hot-plug interrupts in [0x1000], and keeps the HWN count in [0x1001]
+const COUNTING_PROGRAM: &'static [&'static str] = &[
+    "IAS handler",
+    ":loop SET PC, loop",
+    ":handler ADD [0x1000], 1",
+    "HWN [0x1001]",
+    "RFI 0",
+];
+
+fn load(ll: &[&str]) -> DCPU {
+    let lines: Vec<String> = ll.iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    assert!(parse(&lines, &mut pcpu).is_ok(), "{:?}", ll);
+    let mut cpu = DCPU::new();
+    cpu.mem.copy_from_slice(&pcpu.mem[..]);
+    cpu
+}
+
+#[test]
+fn hotplug_from_device_callback() {
+    let mut cpu = load(&["HWI 0", "HWN A", "HLT 0"]);
+    cpu.add_device(Box::new(TestDevice(DevicePlugger))).unwrap();
+    cpu.run(100);
+    assert_eq!(cpu.reg[0], 2);
+    assert_eq!(cpu.devices.len(), 2);
+    assert!(cpu.devices[1].lock().unwrap().as_any().is::<DeviceClockGeneric>());
+}
+
+#[test]
+fn hotplug_interrupt() {
+    let mut cpu = load(COUNTING_PROGRAM);
+    cpu.set_hotplug_interrupt(Some(0x55));
+    cpu.run(100);
+    assert_eq!(cpu.add_device(Box::new(DeviceKeyboardGeneric::new())), Ok(0));
+    assert_eq!(cpu.add_device(Box::new(DeviceClockGeneric::new())), Ok(1));
+    cpu.run(100);
+    assert_eq!((cpu.mem[0x1000], cpu.mem[0x1001]), (2, 2));
+
+    // Swap the keyboard for a new one
+    let keyboard = cpu.remove_device(0).unwrap();
+    assert!(keyboard.lock().unwrap().as_any().is::<DeviceKeyboardGeneric>());
+    cpu.run(100);
+    assert_eq!((cpu.mem[0x1000], cpu.mem[0x1001]), (3, 1));
+    assert!(cpu.devices[0].lock().unwrap().as_any().is::<DeviceClockGeneric>());
+    assert_eq!(cpu.add_device(Box::new(DeviceKeyboardGeneric::new())), Ok(1));
+    cpu.run(100);
+    assert_eq!((cpu.mem[0x1000], cpu.mem[0x1001]), (4, 2));
+}
+
+#[test]
+fn hotplug_errors() {
+    let mut cpu = DCPU::new();
+    assert_eq!(cpu.remove_device(0).err(), Some(DeviceError::NoSuchDevice(0)));
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.add_device(Box::new(DeviceKeyboardGeneric::new())).unwrap();
+    cpu.map_device(1, 0x9000, 0x90ff).unwrap();
+    assert_eq!(cpu.remove_device(1).err(), Some(DeviceError::Mapped(1)));
+
+    // Mappings follow devices to their new index
+    assert!(cpu.remove_device(0).is_ok());
+    assert_eq!(cpu.mapping_at(0x9000), Some(MemoryRegion::Device(0)));
+    assert!(cpu.unmap(0x9000));
+    assert!(cpu.remove_device(0).is_ok());
+    assert_eq!(cpu.devices.len(), 0);
+}
@@ -0,0 +1,127 @@
+use dcpu16::assembler::{PCPU, parse};
+use dcpu16::dcpu::DCPU;
+use dcpu16::disassembler;
+use dcpu16::instructions::Isa;
+
+// The example program from the 1.1 spec
+const NOTCH_EXAMPLE: &'static [&'static str] = &[
+    "        SET A, 0x30",
+    "        SET [0x1000], 0x20",
+    "        SUB A, [0x1000]",
+    "        IFN A, 0x10",
+    "           SET PC, crash",
+    "        SET I, 10",
+    "        SET A, 0x2000",
+    ":loop   SET [0x2000+I], [A]",
+    "        SUB I, 1",
+    "        IFN I, 0",
+    "           SET PC, loop",
+    "        SET X, 0x4",
+    "        JSR testsub",
+    "        SET PC, crash",
+    ":testsub SHL X, 4",
+    "        SET PC, POP",
+    ":crash  SET PC, crash",
+];
+
+fn assemble(ll: &[&str], isa: Isa) -> PCPU {
+    let lines: Vec<String> = ll.iter().map(|l| l.to_string()).collect();
+    let mut pcpu = PCPU::new();
+    pcpu.set_isa(isa);
+    assert!(parse(&lines, &mut pcpu).is_ok(), "{:?}", ll);
+    pcpu
+}
+
+fn load(pcpu: &PCPU, isa: Isa) -> DCPU {
+    let mut cpu = DCPU::new();
+    cpu.set_isa(isa);
+    cpu.mem.copy_from_slice(&pcpu.mem[..]);
+    cpu
+}
+
+#[test]
+fn isa_v1_1_encoding() {
+    let pcpu = assemble(NOTCH_EXAMPLE, Isa::V1_1);
+    let expected = [0x7c01, 0x0030, 0x7de1, 0x1000, 0x0020, 0x7803, 0x1000, 0xc00d, 0x7dc1];
+    assert_eq!(&pcpu.mem[..expected.len()], &expected[..]);
+    assert_eq!(pcpu.mem[10], 0xa861); // SET I, 10
+    assert_eq!(pcpu.mem[13], 0x2161); // SET [0x2000+I], [A]
+
+    let pcpu = assemble(&["SET PC, POP", "SET PUSH, O", "JSR 5", "HLT 0"], Isa::V1_1);
+    assert_eq!(&pcpu.mem[..4], &[0x61c1, 0x75a1, 0x9410, 0x8150]);
+}
+
+#[test]
+fn isa_v1_1_unsupported() {
+    for src in ["HWI 0", "IFA A, 1", "SET PICK 1, A", "ADX A, 1"].iter() {
+        let mut pcpu = PCPU::new();
+        pcpu.set_isa(Isa::V1_1);
+        assert!(parse(&vec![src.to_string()], &mut pcpu).is_err(), "{}", src);
+    }
+}
+
+#[test]
+fn isa_v1_1_example_program() {
+    let pcpu = assemble(NOTCH_EXAMPLE, Isa::V1_1);
+    let crash = pcpu.labels().into_iter().find(|l| l.0 == "crash").unwrap().1;
+    let mut cpu = load(&pcpu, Isa::V1_1);
+    cpu.run(1000);
+    assert_eq!(cpu.pc, crash);
+    assert_eq!(cpu.reg[0], 0x2000); // A
+    assert_eq!(cpu.reg[3], 0x40); // X
+    assert_eq!(cpu.reg[6], 0); // I
+    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.mem[0x1000], 0x20);
+}
+
+#[test]
+fn isa_v1_1_overflow_and_cycles() {
+    let pcpu = assemble(&["SET A, 0xffff",
+                          "ADD A, 1",
+                          "SET B, 1",
+                          "SHR B, 1",
+                          "SET C, 7",
+                          "DIV C, 2",
+                          "SET X, O"], Isa::V1_1);
+    let mut cpu = load(&pcpu, Isa::V1_1);
+    cpu.tick();
+    assert_eq!(cpu.cycle(), 2);
+    cpu.tick();
+    assert_eq!((cpu.reg[0], cpu.ex), (0, 1));
+    assert_eq!(cpu.cycle(), 4);
+    cpu.tick();
+    cpu.tick();
+    assert_eq!((cpu.reg[1], cpu.ex), (0, 0x8000));
+    cpu.tick();
+    cpu.tick();
+    cpu.tick();
+    assert_eq!((cpu.reg[2], cpu.reg[3]), (3, 0x8000));
+    assert_eq!(cpu.cycle(), 12);
+}
+
+#[test]
+fn isa_v1_1_conditionals_do_not_chain() {
+    let src = ["SET A, 1", "IFE A, 0", "IFE A, 0", "SET B, 1", "SET C, 1"];
+    let mut cpu = load(&assemble(&src, Isa::V1_1), Isa::V1_1);
+    cpu.run(20);
+    assert_eq!((cpu.reg[1], cpu.reg[2]), (1, 1));
+
+    let mut cpu = load(&assemble(&src, Isa::V1_7), Isa::V1_7);
+    cpu.run(20);
+    assert_eq!((cpu.reg[1], cpu.reg[2]), (0, 1));
+}
+
+#[test]
+fn isa_v1_1_disassembly() {
+    let pcpu = assemble(&["SET A, 0x30", "SET PC, POP", "SET PUSH, O", "JSR 5", "IFG [0x1000], 31"],
+                        Isa::V1_1);
+    let cpu = load(&pcpu, Isa::V1_1);
+    let mut pc = 0;
+    let mut lines = vec![];
+    for _ in 0..5 {
+        let (len, s) = disassembler::disassemble_instruction_at(&cpu, pc, false);
+        lines.push(s);
+        pc += len;
+    }
+    assert_eq!(lines, ["SET A, 0x0030", "SET PC, POP", "SET PUSH, O", "JSR 5", "IFG [0x1000], 31"]);
+}
@@ -0,0 +1,92 @@
+use std::thread;
+use std::time::Duration;
+use dcpu16::dcpu::{DCPU, RunStatus, StopReason};
+use dcpu16::devices::keyboard_generic::DeviceKeyboardGeneric;
+use dcpu16::devices::monitor_lem1802::{self, DeviceMonitorLEM1802};
+use dcpu16::machine::{Machine, KeyEvent};
+use dcpu16::scheduler::Speed;
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn machine_is_send() {
+    assert_send::<DCPU>();
+    assert_send::<Machine>();
+}
+
+#[test]
+fn machine_step_and_memory() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x9801; // SET A, 5
+    cpu.mem[1] = 0x8aa0; // HLT 1
+    let machine = Machine::spawn(cpu);
+    assert_eq!(machine.status().unwrap().running, false);
+    assert_eq!(machine.step(), Some(RunStatus::Running));
+    let status = machine.status().unwrap();
+    assert_eq!((status.pc, status.cycle), (1, 1));
+    assert_eq!(machine.step(), Some(RunStatus::Stopped(StopReason::Halt(1))));
+
+    machine.write_memory(0xffff, &[0x1234, 0x5678]);
+    assert_eq!(machine.read_memory(0xfffe, 4), Some(vec![0, 0x1234, 0x5678, 0x8aa0]));
+    let cpu = machine.shutdown().unwrap();
+    assert_eq!(cpu.reg[0], 5);
+    assert_eq!(cpu.mem[0], 0x5678);
+}
+
+#[test]
+fn machine_run_until_stop() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8ea0; // HLT 2
+    let machine = Machine::spawn(cpu);
+    assert_eq!(machine.poll_stop(), None);
+    machine.set_speed(Speed::Unlimited);
+    machine.run();
+    assert_eq!(machine.wait_stop(), Some(StopReason::Halt(2)));
+    assert_eq!(machine.status().unwrap().running, false);
+}
+
+#[test]
+fn machine_pause() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8b83; // SUB PC, 1
+    let machine = Machine::spawn(cpu);
+    machine.run();
+    thread::sleep(Duration::from_millis(50));
+    machine.pause();
+    let status = machine.status().unwrap();
+    assert!(!status.running);
+    assert!(status.cycle > 0);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(machine.status().unwrap().cycle, status.cycle);
+    assert_eq!(machine.poll_stop(), None);
+}
+
+#[test]
+fn machine_keyboard() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceKeyboardGeneric::new())).unwrap();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8640; // HWI 0
+    cpu.mem[2] = 0x86a0; // HLT 0
+    let machine = Machine::spawn(cpu);
+    machine.key_event(KeyEvent::Press(0x61));
+    machine.key_event(KeyEvent::Release(0x61));
+    machine.run();
+    assert_eq!(machine.wait_stop(), Some(StopReason::Halt(0)));
+    let cpu = machine.shutdown().unwrap();
+    assert_eq!(cpu.reg[2], 0x61);
+}
+
+#[test]
+fn machine_monitor_frame() {
+    let machine = Machine::spawn(DCPU::new());
+    assert_eq!(machine.monitor_frame(false), Some(None));
+    machine.shutdown().unwrap();
+
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceMonitorLEM1802::new().with_pre_connect(0x8000))).unwrap();
+    let machine = Machine::spawn(cpu);
+    let frame = machine.monitor_frame(false).unwrap().unwrap();
+    let size = monitor_lem1802::MONITOR_WIDTH * monitor_lem1802::MONITOR_HEIGHT * 3;
+    assert_eq!(frame.len(), size as usize);
+}
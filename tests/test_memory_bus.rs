@@ -0,0 +1,90 @@
+use dcpu16::dcpu::{self, DCPU, Device, MemoryRegion, MemoryMapError};
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk};
+use common::{Behavior, TestDevice};
+
+// Memory-mapped register: reads count up, and writes are kept
+struct DeviceRegister {
+    reads: u16,
+    written: Vec<(u16, u16)>,
+}
+
+impl Behavior for DeviceRegister {
+    fn memory_read(&mut self, _: &mut DCPU, address: u16) -> u16 {
+        self.reads += 1;
+        address.wrapping_add(self.reads)
+    }
+
+    fn memory_write(&mut self, cpu: &mut DCPU, address: u16, value: u16) -> () {
+        self.written.push((address, value));
+        cpu.interrupt(value);
+    }
+}
+
+fn register(cpu: &DCPU) -> (u16, Vec<(u16, u16)>) {
+    let d = cpu.devices[0].lock().unwrap();
+    let r = d.as_any().downcast_ref::<TestDevice<DeviceRegister>>().unwrap();
+    (r.reads, r.written.clone())
+}
+
+#[test]
+fn memory_bus_device() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(TestDevice(DeviceRegister { reads: 0, written: Vec::new() }))).unwrap();
+    assert_eq!(cpu.map_device(0, 0x9000, 0x90ff), Ok(()));
+    assert_eq!(cpu.mapping_at(0x9080), Some(MemoryRegion::Device(0)));
+    assert_eq!(cpu.mapping_at(0x9100), None);
+
+    cpu.mem[0] = 0x7801; cpu.mem[1] = 0x9010; // SET A, [0x9010]
+    cpu.mem[2] = 0x7801; cpu.mem[3] = 0x9010; // SET A, [0x9010]
+    cpu.mem[4] = 0x7fc1; cpu.mem[5] = 0x0042; cpu.mem[6] = 0x90ff; // SET [0x90ff], 0x42
+    cpu.mem[7] = 0x7fc1; cpu.mem[8] = 0x0043; cpu.mem[9] = 0x9100; // SET [0x9100], 0x43
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 0x9011);
+    cpu.tick();
+    assert_eq!(cpu.reg[0], 0x9012);
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(register(&cpu), (2, vec![(0x90ff, 0x42)]));
+    assert_eq!(cpu.mem[0x90ff], 0);
+    assert_eq!(cpu.mem[0x9100], 0x43);
+
+    // RAM is still there underneath
+    cpu.mem[0x9010] = 7;
+    assert_eq!(cpu.read_memory(0x9010), 0x9013);
+    assert!(cpu.unmap(0x9000));
+    assert!(!cpu.unmap(0x9000));
+    assert_eq!(cpu.read_memory(0x9010), 7);
+}
+
+#[test]
+fn memory_bus_errors() {
+    let mut cpu = DCPU::new();
+    assert_eq!(cpu.map_device(0, 0x100, 0x1ff), Err(MemoryMapError::NoSuchDevice(0)));
+    cpu.add_device(Box::new(TestDevice(DeviceRegister { reads: 0, written: Vec::new() }))).unwrap();
+    assert_eq!(cpu.map_device(0, 0x100, 0x1ff), Ok(()));
+    assert_eq!(cpu.map_device(0, 0x1ff, 0x2ff), Err(MemoryMapError::Overlap { from: 0x100, to: 0x1ff }));
+    assert_eq!(cpu.map_device(0, 0x000, 0x100), Err(MemoryMapError::Overlap { from: 0x100, to: 0x1ff }));
+    assert_eq!(cpu.map_device(0, 0x300, 0x2ff), Err(MemoryMapError::InvalidRange));
+    assert_eq!(cpu.map_device(0, 0x000, 0x0ff), Ok(()));
+    assert_eq!(cpu.mappings(), vec![(0x000, 0x0ff, MemoryRegion::Device(0)),
+                                    (0x100, 0x1ff, MemoryRegion::Device(0))]);
+}
+
+#[test]
+fn memory_bus_floppy_write() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(TestDevice(DeviceRegister { reads: 0, written: Vec::new() }))).unwrap();
+    cpu.add_device(Box::new(DeviceFloppyM35FD::new())).unwrap();
+    assert_eq!(cpu.map_device(0, 0x9000, 0x91ff), Ok(()));
+    let sector = cpu.with_device(|floppy: &mut DeviceFloppyM35FD, cpu| {
+        floppy.insert(FloppyDisk::new());
+        cpu.reg[dcpu::REG_A] = 3; // Write sector 0 from 0x9000
+        cpu.reg[dcpu::REG_X] = 0;
+        cpu.reg[dcpu::REG_Y] = 0x9000;
+        floppy.process_interrupt(cpu);
+        floppy.run(cpu, 100000);
+        floppy.disk.as_ref().unwrap().sectors[0]
+    }).unwrap();
+    assert_eq!((sector[0], sector[511]), (0x9001, 0x9000 + 511 + 512));
+    assert_eq!(register(&cpu).0, 512);
+}
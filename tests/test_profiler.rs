@@ -0,0 +1,77 @@
+use dcpu16::dcpu::DCPU;
+use dcpu16::symbols::SymbolMap;
+
+// Calls `sub` twice, which calls `leaf` each time
+fn program(cpu: &mut DCPU) {
+    let words = [
+        0x9c20,                 // JSR 6
+        0x7c20, 0x0006,         // JSR 0x0006
+        0x7f81, 0x0003,         // SET PC, 3
+        0x0000,
+        0x7c20, 0x000a,         // JSR 0x000a
+        0x8801,                 // SET A, 1
+        0x6381,                 // SET PC, POP
+        0x8c04,                 // MUL A, 2
+        0x6381,                 // SET PC, POP
+    ];
+    cpu.mem[..words.len()].copy_from_slice(&words);
+}
+
+#[test]
+fn profiler_counts() {
+    let mut cpu = DCPU::new();
+    program(&mut cpu);
+    cpu.start_profiling();
+    for _ in 0..13 {
+        cpu.tick();
+    }
+    let profiler = cpu.stop_profiling().unwrap();
+    assert!(cpu.profiler().is_none());
+
+    let stats = profiler.address_stats();
+    let at = |a: u16| stats.iter().find(|s| s.address == a).cloned().unwrap();
+    assert_eq!(at(0).hits, 1);
+    assert_eq!(at(0).cycles, 3);
+    assert_eq!(at(1).cycles, 4);
+    assert_eq!(at(6).hits, 2);
+    assert_eq!(at(10).hits, 2);
+    assert_eq!(at(10).cycles, 4);
+    assert_eq!(at(3).hits, 1);
+    assert_eq!(profiler.total_cycles(), stats.iter().map(|s| s.cycles).sum::<u64>());
+
+    let functions = profiler.function_stats();
+    let sub = functions.iter().find(|f| f.address == 6).cloned().unwrap();
+    let leaf = functions.iter().find(|f| f.address == 10).cloned().unwrap();
+    assert_eq!(sub.calls, 2);
+    assert_eq!(leaf.calls, 2);
+    assert_eq!(leaf.self_cycles, 2 * (2 + 1));
+    assert_eq!(sub.total_cycles, sub.self_cycles + leaf.total_cycles);
+    assert_eq!(functions[0].address, 0);
+    assert_eq!(functions[0].total_cycles, profiler.total_cycles());
+}
+
+#[test]
+fn profiler_folded() {
+    let mut cpu = DCPU::new();
+    program(&mut cpu);
+    cpu.start_profiling();
+    for _ in 0..13 {
+        cpu.tick();
+    }
+    let mut symbols = SymbolMap::new();
+    symbols.add_label("main", 0);
+    symbols.add_label("sub", 6);
+    symbols.add_label("leaf", 10);
+
+    let mut out: Vec<u8> = Vec::new();
+    cpu.profiler().unwrap().write_folded(&mut out, Some(&symbols)).unwrap();
+    let folded = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = folded.lines().collect();
+    assert_eq!(lines, vec!["main 9", "main;sub 12", "main;sub;leaf 6"]);
+
+    let mut out: Vec<u8> = Vec::new();
+    cpu.profiler().unwrap().write_report(&mut out, Some(&symbols), 10).unwrap();
+    let report = String::from_utf8(out).unwrap();
+    assert!(report.starts_with("Total cycles: 27\n"));
+    assert!(report.contains("sub+2"));
+}
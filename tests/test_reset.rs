@@ -0,0 +1,109 @@
+use dcpu16::dcpu::{self, DCPU, Device, MemoryRegion};
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::exit::DeviceExit;
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk};
+use dcpu16::devices::keyboard_generic::DeviceKeyboardGeneric;
+use dcpu16::devices::monitor_lem1802::DeviceMonitorLEM1802;
+use dcpu16::machine::Machine;
+
+// Sends an interrupt to a device, as HWI would, and returns B and C
+fn hwi<T: Device>(device: &mut T, cpu: &mut DCPU, a: u16, b: u16) -> (u16, u16) {
+    cpu.reg[dcpu::REG_A] = a;
+    cpu.reg[dcpu::REG_B] = b;
+    device.process_interrupt(cpu);
+    (cpu.reg[dcpu::REG_B], cpu.reg[dcpu::REG_C])
+}
+
+#[test]
+fn soft_reset_cpu() {
+    let mut cpu = DCPU::new();
+    cpu.load_rom(0xf000, &[0x8b83]).unwrap(); // SUB PC, 1
+    cpu.set_reset_vector(0xf000);
+    cpu.mem[0x100] = 5;
+    cpu.reg[dcpu::REG_X] = 3;
+    cpu.sp = 0xfff0;
+    cpu.ia = 0x200;
+    cpu.run(100);
+    let cycle = cpu.cycle();
+    assert!(cycle > 0);
+
+    cpu.soft_reset(true);
+    assert_eq!((cpu.pc, cpu.sp, cpu.ia, cpu.reg[dcpu::REG_X]), (0xf000, 0, 0, 0));
+    assert_eq!(cpu.cycle(), cycle);
+    assert_eq!(cpu.mem[0x100], 5);
+
+    cpu.soft_reset(false);
+    assert_eq!(cpu.mem[0x100], 0);
+    assert_eq!(cpu.mem[0xf000], 0x8b83);
+    assert_eq!(cpu.mapping_at(0xf000), Some(MemoryRegion::Rom));
+}
+
+#[test]
+fn soft_reset_devices() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.add_device(Box::new(DeviceMonitorLEM1802::new())).unwrap();
+    cpu.add_device(Box::new(DeviceKeyboardGeneric::new())).unwrap();
+    cpu.add_device(Box::new(DeviceFloppyM35FD::new())).unwrap();
+    cpu.add_device(Box::new(DeviceExit::new())).unwrap();
+    cpu.mem[0] = 0x8b83; // SUB PC, 1
+
+    cpu.with_device(|clock: &mut DeviceClockGeneric, cpu| hwi(clock, cpu, 0, 1));
+    cpu.with_device(|monitor: &mut DeviceMonitorLEM1802, cpu| hwi(monitor, cpu, 0, 0x8000));
+    cpu.with_device(|keyboard: &mut DeviceKeyboardGeneric, cpu| keyboard.register_press(cpu, 0x61));
+    cpu.with_device(|floppy: &mut DeviceFloppyM35FD, cpu| {
+        floppy.insert(FloppyDisk::new());
+        cpu.reg[dcpu::REG_X] = 0;
+        cpu.reg[dcpu::REG_Y] = 0x1000;
+        hwi(floppy, cpu, 2, 0)
+    });
+    cpu.run(10000);
+    assert!(cpu.with_device(|clock: &mut DeviceClockGeneric, cpu| hwi(clock, cpu, 1, 0).1).unwrap() > 0);
+    cpu.with_device(|exit: &mut DeviceExit, cpu| hwi(exit, cpu, 0, 3));
+    assert_eq!(cpu.with_device(|exit: &mut DeviceExit, _| exit.status()), Some(Some(3)));
+
+    cpu.soft_reset(true);
+    assert_eq!(cpu.devices.len(), 5);
+    assert_eq!(cpu.with_device(|exit: &mut DeviceExit, _| exit.status()), Some(None));
+    assert_eq!(cpu.with_device(|clock: &mut DeviceClockGeneric, cpu| hwi(clock, cpu, 1, 0).1), Some(0));
+    assert_eq!(cpu.with_device(|monitor: &mut DeviceMonitorLEM1802, _| monitor.connected), Some(false));
+    assert_eq!(cpu.with_device(|keyboard: &mut DeviceKeyboardGeneric, cpu| hwi(keyboard, cpu, 1, 0).1),
+               Some(0));
+    // The disk is still in the drive (ready, no error)
+    assert_eq!(cpu.with_device(|floppy: &mut DeviceFloppyM35FD, cpu| hwi(floppy, cpu, 0, 0)), Some((1, 0)));
+
+    // The clock stays off
+    cpu.run(10000);
+    assert_eq!(cpu.with_device(|clock: &mut DeviceClockGeneric, cpu| hwi(clock, cpu, 1, 0).1), Some(0));
+}
+
+#[test]
+fn hard_reset() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.load_rom(0xf000, &[0x8b83]).unwrap();
+    cpu.mem[0x100] = 5;
+    cpu.run(100);
+    cpu.hard_reset();
+    assert_eq!(cpu.cycle(), 0);
+    assert_eq!(cpu.devices.len(), 0);
+    assert_eq!(cpu.mappings(), vec![]);
+    assert_eq!((cpu.mem[0x100], cpu.mem[0xf000]), (0, 0));
+}
+
+#[test]
+fn machine_reset() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8802; // ADD A, 1
+    cpu.mem[1] = 0x8f83; // SUB PC, 2
+    let machine = Machine::spawn(cpu);
+    for _ in 0..5 {
+        machine.step();
+    }
+    let cycle = machine.status().unwrap().cycle;
+    machine.reset(true);
+    let status = machine.status().unwrap();
+    assert_eq!((status.pc, status.cycle), (0, cycle));
+    let cpu = machine.shutdown().unwrap();
+    assert_eq!((cpu.reg[dcpu::REG_A], cpu.mem[0]), (0, 0x8802));
+}
@@ -0,0 +1,71 @@
+use dcpu16::bootrom;
+use dcpu16::dcpu::{DCPU, RunStatus, MemoryRegion, MemoryMapError, RomWritePolicy, StopReason};
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk};
+
+#[test]
+fn rom_ignores_writes() {
+    let mut cpu = DCPU::new();
+    assert_eq!(cpu.load_rom(0xf000, &[0x7fc1, 0x1234, 0xf000]), Ok(())); // SET [0xf000], 0x1234
+    assert_eq!(cpu.mapping_at(0xf002), Some(MemoryRegion::Rom));
+    assert_eq!(cpu.mapping_at(0xf003), None);
+    cpu.set_reset_vector(0xf000);
+    assert_eq!(cpu.pc, 0xf000);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.mem[0xf000], 0x7fc1);
+
+    cpu.set_rom_write_policy(RomWritePolicy::Stop);
+    cpu.pc = 0xf000;
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::RomWrite(0xf000)));
+    assert_eq!(cpu.mem[0xf000], 0x7fc1);
+}
+
+#[test]
+fn rom_errors() {
+    let mut cpu = DCPU::new();
+    assert_eq!(cpu.load_rom(0xfffe, &[1, 2, 3]), Err(MemoryMapError::InvalidRange));
+    assert_eq!(cpu.load_rom(0x0000, &[]), Err(MemoryMapError::InvalidRange));
+    assert_eq!(cpu.load_rom(0xfffd, &[1, 2, 3]), Ok(()));
+    assert_eq!(cpu.load_rom(0xff00, &[0; 0x100]), Err(MemoryMapError::Overlap { from: 0xfffd, to: 0xffff }));
+}
+
+#[test]
+fn rom_boot_from_floppy() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    let mut floppy = DeviceFloppyM35FD::new();
+    let mut disk = FloppyDisk::new();
+    let mut sector = [0u16; 512];
+    sector[0] = 0x7c01; sector[1] = 0xbeef; // SET A, 0xbeef
+    sector[2] = 0x0301;                     // SET PUSH, A
+    disk.sectors.push(sector);
+    floppy.insert(disk);
+    cpu.add_device(Box::new(floppy)).unwrap();
+
+    let rom = bootrom::bootloader(bootrom::BOOTLOADER_ADDRESS);
+    cpu.load_rom(bootrom::BOOTLOADER_ADDRESS, &rom).unwrap();
+    cpu.set_reset_vector(bootrom::BOOTLOADER_ADDRESS);
+    cpu.set_rom_write_policy(RomWritePolicy::Stop);
+
+    let mut ticks = 0;
+    while cpu.reg[0] != 0xbeef {
+        assert_eq!(cpu.tick(), RunStatus::Running);
+        ticks += 1;
+        assert!(ticks < 10000);
+    }
+    assert_eq!(cpu.reg[6], 1); // Drive number
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.sp, bootrom::BOOTLOADER_ADDRESS - 1);
+    assert_eq!(cpu.mem[cpu.sp as usize], 0xbeef);
+}
+
+#[test]
+fn rom_boot_without_disk() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceFloppyM35FD::new())).unwrap();
+    let rom = bootrom::bootloader(0x8000);
+    cpu.load_rom(0x8000, &rom).unwrap();
+    cpu.set_reset_vector(0x8000);
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Halt(1)));
+    assert_eq!(cpu.pc, 0x8000 + rom.len() as u16);
+}
@@ -0,0 +1,98 @@
+use dcpu16::dcpu::{DCPU, RunStatus, StopReason};
+use dcpu16::devices::exit::{self, DeviceExit};
+use common::{Behavior, TestDevice};
+
+// Stops the DCPU with the value of B when interrupted
+struct DeviceStopper;
+
+impl Behavior for DeviceStopper {
+    fn process_interrupt(&mut self, cpu: &mut DCPU) -> () {
+        let code = cpu.reg[1];
+        cpu.request_stop(code);
+    }
+}
+
+#[test]
+fn run_status_cycle_budget() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8b81; // SET PC, 1
+    cpu.mem[1] = 0x8781; // SET PC, 0
+    assert_eq!(cpu.run(100), RunStatus::CycleBudget);
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.halted(), None);
+}
+
+#[test]
+fn run_status_halt() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x02a0; // HLT A
+    cpu.mem[2] = 0x8c01; // SET A, 2
+    cpu.mem[3] = 0x02a0; // HLT A
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Halt(1)));
+    assert_eq!(cpu.pc, 2);
+    assert_eq!(cpu.halted(), Some(StopReason::Halt(1)));
+
+    // Stays halted
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::Halt(1)));
+    assert_eq!(cpu.pc, 2);
+
+    cpu.resume();
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::Halt(2)));
+    assert_eq!(cpu.pc, 4);
+}
+
+#[test]
+fn run_status_zero_word() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    // Zeroed memory is executed like any other reserved opcode
+    assert_eq!(cpu.run(1000), RunStatus::CycleBudget);
+    assert_eq!(cpu.halted(), None);
+
+    let mut cpu = DCPU::new();
+    cpu.set_halt_on_zero(true);
+    cpu.mem[0] = 0x8801; // SET A, 1
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::ZeroWord(1)));
+    assert_eq!(cpu.tick(), RunStatus::Stopped(StopReason::ZeroWord(1)));
+    assert_eq!(cpu.pc, 2);
+}
+
+#[test]
+fn run_status_device_stop() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(TestDevice(DeviceStopper))).unwrap();
+    cpu.mem[0] = 0x7c21; cpu.mem[1] = 0x0042; // SET B, 0x42
+    cpu.mem[2] = 0x8640; // HWI 0
+    cpu.mem[3] = 0x8b81; // SET PC, 1
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::DeviceStop(0x42)));
+    assert_eq!(cpu.pc, 3);
+
+    // The DCPU is not halted by a device stop
+    assert_eq!(cpu.tick(), RunStatus::Running);
+    assert_eq!(cpu.pc, 1);
+}
+
+#[test]
+fn run_status_exit_device() {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceExit::new())).unwrap();
+    cpu.mem[0] = 0x8401; // SET A, 0
+    cpu.mem[1] = 0x9c21; // SET B, 6
+    cpu.mem[2] = 0x8640; // HWI 0
+    cpu.mem[3] = 0x8781; // SET PC, 0
+    assert_eq!(cpu.run(1000), RunStatus::Stopped(StopReason::DeviceStop(6)));
+    let device = cpu.devices[0].lock().unwrap();
+    let exit = device.as_any().downcast_ref::<DeviceExit>().unwrap();
+    assert_eq!(exit.status(), Some(6));
+}
+
+#[test]
+fn exit_code() {
+    assert_eq!(exit::exit_code(0), 0);
+    assert_eq!(exit::exit_code(6), 6);
+    assert_eq!(exit::exit_code(255), 255);
+    assert_eq!(exit::exit_code(256), exit::EXIT_OVERFLOW);
+    assert_eq!(exit::exit_code(0xffff), exit::EXIT_OVERFLOW);
+    assert_eq!(exit::exit_code(exit::EXIT_LIMIT as u16), exit::EXIT_OVERFLOW);
+}
@@ -0,0 +1,110 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use dcpu16::dcpu::{DCPU, RunStatus, CYCLE_HZ};
+use dcpu16::scheduler::{Scheduler, Speed};
+
+fn spinning_cpu() -> DCPU {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x8b83; // SUB PC, 1
+    cpu
+}
+
+// Steps the scheduler for `time` and returns the number of cycles executed
+fn run_for(cpu: &mut DCPU, scheduler: &mut Scheduler, time: Duration) -> u64 {
+    let start = cpu.cycle();
+    let now = Instant::now();
+    while now.elapsed() < time {
+        assert_eq!(scheduler.step(cpu), RunStatus::CycleBudget);
+        scheduler.wait();
+    }
+    cpu.cycle() - start
+}
+
+#[test]
+fn scheduler_real_time() {
+    let mut cpu = spinning_cpu();
+    let mut scheduler = Scheduler::new();
+    let cycles = run_for(&mut cpu, &mut scheduler, Duration::from_millis(600));
+    // Never ahead of the clock (apart from the last instruction), and not far behind
+    assert!(cycles <= (CYCLE_HZ as u64) * 6 / 10 + 2, "{} cycles", cycles);
+    assert!(cycles >= (CYCLE_HZ as u64) * 4 / 10, "{} cycles", cycles);
+    let hz = scheduler.frequency().unwrap();
+    assert!(hz > CYCLE_HZ as f64 * 0.7 && hz < CYCLE_HZ as f64 * 1.1, "{} Hz", hz);
+}
+
+#[test]
+fn scheduler_speed() {
+    let mut cpu = spinning_cpu();
+    let mut scheduler = Scheduler::new();
+    scheduler.set_speed(Speed::Factor(0.5));
+    let cycles = run_for(&mut cpu, &mut scheduler, Duration::from_millis(200));
+    assert!(cycles <= (CYCLE_HZ as u64) / 10 + 2, "{} cycles", cycles);
+
+    scheduler.set_speed(Speed::Factor(2.0));
+    let cycles = run_for(&mut cpu, &mut scheduler, Duration::from_millis(200));
+    assert!(cycles <= (CYCLE_HZ as u64) * 4 / 10 + 2, "{} cycles", cycles);
+    assert!(cycles > (CYCLE_HZ as u64) / 10, "{} cycles", cycles);
+
+    scheduler.set_speed(Speed::Unlimited);
+    assert!(scheduler.due_cycles() > 0);
+    let start = cpu.cycle();
+    scheduler.step(&mut cpu);
+    assert!(cpu.cycle() - start >= scheduler.due_cycles());
+}
+
+#[test]
+fn scheduler_pause() {
+    let mut cpu = spinning_cpu();
+    let mut scheduler = Scheduler::new();
+    scheduler.pause();
+    assert!(scheduler.is_paused());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(scheduler.due_cycles(), 0);
+    assert_eq!(scheduler.step(&mut cpu), RunStatus::CycleBudget);
+    assert_eq!(cpu.cycle(), 0);
+    assert_eq!(scheduler.frequency(), None);
+
+    // Resuming does not make up for the paused time
+    scheduler.resume();
+    assert!(!scheduler.is_paused());
+    assert!(scheduler.due_cycles() < (CYCLE_HZ as u64) / 100);
+}
+
+#[test]
+fn scheduler_drops_backlog() {
+    let mut scheduler = Scheduler::new();
+    thread::sleep(Duration::from_millis(500));
+    // At most 250 ms worth of cycles
+    let due = scheduler.due_cycles();
+    assert!(due <= (CYCLE_HZ as u64) / 4, "{} cycles", due);
+    assert!(due > 0);
+}
+
+#[test]
+fn scheduler_stops() {
+    let mut cpu = DCPU::new();
+    cpu.mem[0] = 0x86a0; // HLT 0
+    let mut scheduler = Scheduler::new();
+    scheduler.set_speed(Speed::Unlimited);
+    assert!(scheduler.step(&mut cpu).is_stopped());
+}
+
+#[test]
+fn scheduler_cycle_goes_back() {
+    let mut cpu = spinning_cpu();
+    let mut scheduler = Scheduler::new();
+    scheduler.set_speed(Speed::Unlimited);
+    scheduler.step(&mut cpu);
+    thread::sleep(Duration::from_millis(510));
+    scheduler.step(&mut cpu);
+    assert!(scheduler.frequency().is_some());
+
+    // Measuring starts over instead of underflowing
+    cpu.hard_reset();
+    cpu.mem[0] = 0x8b83; // SUB PC, 1
+    thread::sleep(Duration::from_millis(510));
+    assert_eq!(scheduler.step(&mut cpu), RunStatus::CycleBudget);
+    thread::sleep(Duration::from_millis(510));
+    scheduler.step(&mut cpu);
+    assert!(scheduler.frequency().unwrap() > 0.0);
+}
@@ -0,0 +1,102 @@
+use dcpu16::dcpu::DCPU;
+use dcpu16::devices::clock_generic::DeviceClockGeneric;
+use dcpu16::devices::floppy_m35fd::{DeviceFloppyM35FD, FloppyDisk};
+
+fn machine() -> DCPU {
+    let mut cpu = DCPU::new();
+    cpu.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    cpu.add_device(Box::new(DeviceFloppyM35FD::new())).unwrap();
+    cpu
+}
+
+#[test]
+fn snapshot_roundtrip() {
+    let mut cpu = machine();
+    cpu.mem[0] = 0x8801; // SET A, 1
+    cpu.mem[1] = 0x8c21; // SET B, 2
+    cpu.mem[2] = 0x8640; // HWI 0 (A=1 queries the clock)
+    cpu.mem[0x8000] = 0xbeef;
+    {
+        let mut disk = FloppyDisk::new();
+        disk.sectors.push([7; 512]);
+        let mut floppy = cpu.devices[1].lock().unwrap();
+        floppy.as_any_mut().downcast_mut::<DeviceFloppyM35FD>().unwrap().insert(disk);
+    }
+    cpu.tick();
+    cpu.tick();
+
+    let mut buf: Vec<u8> = Vec::new();
+    cpu.write_snapshot(&mut buf).unwrap();
+
+    let mut restored = machine();
+    restored.read_snapshot(&mut &buf[..]).unwrap();
+    assert_eq!(restored.pc, 2);
+    assert_eq!(restored.reg[0], 1);
+    assert_eq!(restored.reg[1], 2);
+    assert_eq!(restored.mem[0x8000], 0xbeef);
+    assert_eq!(restored.cycle(), cpu.cycle());
+    {
+        let floppy = restored.devices[1].lock().unwrap();
+        let floppy = floppy.as_any().downcast_ref::<DeviceFloppyM35FD>().unwrap();
+        assert_eq!(floppy.state(), 1);
+        assert_eq!(floppy.disk.as_ref().unwrap().sectors[0][511], 7);
+    }
+
+    // Both machines continue identically
+    cpu.tick();
+    restored.tick();
+    assert_eq!(restored.reg, cpu.reg);
+
+    // Saving again gives the same snapshot
+    let mut buf2: Vec<u8> = Vec::new();
+    let mut buf3: Vec<u8> = Vec::new();
+    cpu.write_snapshot(&mut buf2).unwrap();
+    restored.write_snapshot(&mut buf3).unwrap();
+    assert!(buf2 == buf3);
+}
+
+#[test]
+fn snapshot_device_mismatch() {
+    let cpu = machine();
+    let mut buf: Vec<u8> = Vec::new();
+    cpu.write_snapshot(&mut buf).unwrap();
+
+    let mut other = DCPU::new();
+    other.add_device(Box::new(DeviceFloppyM35FD::new())).unwrap();
+    other.add_device(Box::new(DeviceClockGeneric::new())).unwrap();
+    other.mem[0] = 0x1234;
+    assert!(other.read_snapshot(&mut &buf[..]).is_err());
+    assert_eq!(other.mem[0], 0x1234);
+
+    let mut other = DCPU::new();
+    assert!(other.read_snapshot(&mut &b"garbage"[..]).is_err());
+}
+
+#[test]
+fn snapshot_failed_device_restore() {
+    // The clock loads its state before the floppy drive rejects a disk with too many sectors
+    let mut cpu = machine();
+    cpu.mem[0] = 0x8401; // SET A, 0
+    cpu.mem[1] = 0xfc21; // SET B, 30
+    cpu.mem[2] = 0x8640; // HWI 0 (A=0 sets the clock rate)
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    {
+        let mut disk = FloppyDisk::new();
+        disk.sectors = vec![[0; 512]; 1441];
+        let mut floppy = cpu.devices[1].lock().unwrap();
+        floppy.as_any_mut().downcast_mut::<DeviceFloppyM35FD>().unwrap().insert(disk);
+    }
+    let mut buf: Vec<u8> = Vec::new();
+    cpu.write_snapshot(&mut buf).unwrap();
+
+    let mut other = machine();
+    other.mem[0] = 0x1234;
+    let mut before: Vec<u8> = Vec::new();
+    other.write_snapshot(&mut before).unwrap();
+    assert!(other.read_snapshot(&mut &buf[..]).is_err());
+    let mut after: Vec<u8> = Vec::new();
+    other.write_snapshot(&mut after).unwrap();
+    assert!(before == after);
+}
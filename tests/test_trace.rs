@@ -0,0 +1,110 @@
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+use dcpu16::dcpu::DCPU;
+use dcpu16::instructions::Isa;
+use dcpu16::trace::{self, TraceReader};
+
+// Lets the test look at the trace after handing the writer to the DCPU
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn record_trace(program: &[u16], ticks: usize) -> Vec<u8> {
+    record_trace_isa(program, ticks, Isa::V1_7)
+}
+
+fn record_trace_isa(program: &[u16], ticks: usize, isa: Isa) -> Vec<u8> {
+    let mut cpu = DCPU::new();
+    cpu.set_isa(isa);
+    cpu.mem[..program.len()].copy_from_slice(program);
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    cpu.start_trace(Box::new(buffer.clone())).unwrap();
+    for _ in 0..ticks {
+        cpu.tick();
+    }
+    cpu.stop_trace().unwrap();
+    let v = buffer.0.lock().unwrap().clone();
+    v
+}
+
+#[test]
+fn trace_records() {
+    let data = record_trace(&[
+        0x7c01, 0x1234,         // SET A, 0x1234
+        0x0301,                 // SET PUSH, A
+        0x7d40, 0x0010,         // IAS 0x0010
+        0x9100,                 // INT 3
+    ], 4);
+    let mut reader = TraceReader::new(Cursor::new(data)).unwrap();
+
+    let r = reader.next_record().unwrap().unwrap();
+    assert_eq!(r.pc, 0);
+    assert_eq!(r.instruction, vec![0x7c01, 0x1234]);
+    assert_eq!(r.registers, vec![(0, 0x1234), (8, 2)]);
+    assert_eq!(r.writes, vec![]);
+    assert_eq!(r.cycle, 2);
+
+    let r = reader.next_record().unwrap().unwrap();
+    assert_eq!(r.registers, vec![(8, 3), (9, 0xffff)]);
+    assert_eq!(r.writes, vec![(0xffff, 0x1234)]);
+    assert_eq!(r.cycle, 3);
+
+    let r = reader.next_record().unwrap().unwrap();
+    assert_eq!(r.registers, vec![(8, 5), (11, 0x0010)]);
+
+    // The interrupt is taken right after INT
+    let r = reader.next_record().unwrap().unwrap();
+    assert_eq!(r.pc, 5);
+    assert_eq!(r.interrupts, vec![3]);
+    assert_eq!(r.registers, vec![(0, 3), (8, 0x0010), (9, 0xfffd)]);
+    assert_eq!(r.writes, vec![(0xfffe, 6), (0xfffd, 0x1234)]);
+
+    assert!(reader.next_record().unwrap().is_none());
+}
+
+#[test]
+fn trace_divergence() {
+    let good = record_trace(&[0x8801, 0x8c21, 0x9041], 3); // SET A, 1; SET B, 2; SET C, 3
+    let bad = record_trace(&[0x8801, 0x8c21, 0x9441], 3);  // SET A, 1; SET B, 2; SET C, 4
+
+    let mut a = TraceReader::new(Cursor::new(good.clone())).unwrap();
+    let mut b = TraceReader::new(Cursor::new(good.clone())).unwrap();
+    assert!(trace::first_divergence(&mut a, &mut b).unwrap().is_none());
+
+    let mut a = TraceReader::new(Cursor::new(good.clone())).unwrap();
+    let mut b = TraceReader::new(Cursor::new(bad)).unwrap();
+    let d = trace::first_divergence(&mut a, &mut b).unwrap().unwrap();
+    assert_eq!(d.index, 2);
+    assert_eq!(d.previous.unwrap().pc, 1);
+    assert_eq!(d.a.unwrap().registers[0], (2, 3));
+    assert_eq!(d.b.unwrap().registers[0], (2, 4));
+
+    // A trace that ends early diverges too
+    let short = record_trace(&[0x8801, 0x8c21, 0x9041], 2);
+    let mut a = TraceReader::new(Cursor::new(good)).unwrap();
+    let mut b = TraceReader::new(Cursor::new(short)).unwrap();
+    let d = trace::first_divergence(&mut a, &mut b).unwrap().unwrap();
+    assert_eq!(d.index, 2);
+    assert!(d.a.is_some());
+    assert!(d.b.is_none());
+}
+
+#[test]
+fn trace_isa() {
+    let data = record_trace(&[0x8801], 1); // SET A, 1
+    assert_eq!(TraceReader::new(Cursor::new(data)).unwrap().isa(), Isa::V1_7);
+
+    let data = record_trace_isa(&[0x8801], 1, Isa::V1_1); // SET A, 2
+    let mut reader = TraceReader::new(Cursor::new(data)).unwrap();
+    assert_eq!(reader.isa(), Isa::V1_1);
+    assert_eq!(reader.next_record().unwrap().unwrap().registers, vec![(0, 2), (8, 1)]);
+}
@@ -1,4 +1,29 @@
 extern crate dcpu16;
 
+mod common;
 mod test_emulator;
 mod test_assembler;
+mod test_breakpoints;
+mod test_gdb;
+mod test_snapshot;
+mod test_history;
+mod test_trace;
+mod test_profiler;
+mod test_coverage;
+mod test_memory_bus;
+mod test_rom;
+mod test_faults;
+mod test_run_status;
+mod test_cycles;
+mod test_scheduler;
+mod test_machine;
+mod test_decode_cache;
+mod test_device_batch;
+mod test_isa;
+mod test_extensions;
+mod test_console;
+mod test_hotplug;
+mod test_device_info;
+mod test_reset;
+mod test_floppy_image;
+mod test_floppy_timing;